@@ -15,13 +15,37 @@ CREATE TABLE IF NOT EXISTS changed_tile_batches (
     tile_count   INTEGER,
     started_at   TIMESTAMPTZ,
     finished_at  TIMESTAMPTZ,
-    source_file  TEXT
+    source_file  TEXT,
+    replication_sequence   BIGINT,
+    replication_timestamp  TIMESTAMPTZ,
+    -- When jvt picked the tiles up; finished_at - received_at is pipeline lag
+    received_at            TIMESTAMPTZ
 );
 
+-- Replication metadata from structured notification payloads
+-- (kept idempotent so the script upgrades existing databases)
+ALTER TABLE changed_tile_batches ADD COLUMN IF NOT EXISTS replication_sequence BIGINT;
+ALTER TABLE changed_tile_batches ADD COLUMN IF NOT EXISTS replication_timestamp TIMESTAMPTZ;
+ALTER TABLE changed_tile_batches ADD COLUMN IF NOT EXISTS received_at TIMESTAMPTZ;
+
 -- Index for efficient querying of batch history
 CREATE INDEX IF NOT EXISTS idx_changed_tile_batches_started_at 
 ON changed_tile_batches(started_at);
 
+-- Tiles written by each batch, for exports that follow the archive incrementally
+CREATE TABLE IF NOT EXISTS changed_tiles (
+    batch_id  BIGINT NOT NULL REFERENCES changed_tile_batches(id) ON DELETE CASCADE,
+    z         SMALLINT NOT NULL,
+    x         INTEGER NOT NULL,
+    y         INTEGER NOT NULL,
+    PRIMARY KEY (batch_id, z, x, y)
+);
+
+-- Set once a batch's changed_tiles rows are pruned (CHANGED_TILES_RETENTION_DAYS)
+ALTER TABLE changed_tile_batches ADD COLUMN IF NOT EXISTS tiles_pruned BOOLEAN NOT NULL DEFAULT false;
+CREATE INDEX IF NOT EXISTS idx_changed_tile_batches_unpruned
+ON changed_tile_batches(finished_at) WHERE NOT tiles_pruned;
+
 -- Create notification channel for tile updates
 -- (The Rust worker will listen on this channel)
 -- Note: LISTEN/NOTIFY channels are created automatically when first used
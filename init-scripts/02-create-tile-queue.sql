@@ -0,0 +1,29 @@
+-- Database-backed dirty tile queue
+-- An alternative to dirty tiles files on a shared filesystem: producers
+-- (`jvt enqueue` or osm2pgsql wrappers) insert expired tiles here and workers
+-- claim them with SELECT ... FOR UPDATE SKIP LOCKED.
+
+CREATE TABLE IF NOT EXISTS dirty_tiles (
+    id           BIGSERIAL PRIMARY KEY,
+    z            SMALLINT NOT NULL,
+    x            INTEGER NOT NULL,
+    y            INTEGER NOT NULL,
+    enqueued_at  TIMESTAMPTZ NOT NULL DEFAULT now(),
+    source       TEXT,
+    -- Lease held by the worker currently rendering the tile
+    claimed_by   TEXT,
+    claimed_at   TIMESTAMPTZ,
+    -- OSM data the expiry came from, carried through to the audit table
+    replication_sequence   BIGINT,
+    replication_timestamp  TIMESTAMPTZ
+);
+
+ALTER TABLE dirty_tiles ADD COLUMN IF NOT EXISTS replication_sequence BIGINT;
+ALTER TABLE dirty_tiles ADD COLUMN IF NOT EXISTS replication_timestamp TIMESTAMPTZ;
+
+-- Workers scan for unclaimed rows in insertion order
+CREATE INDEX IF NOT EXISTS idx_dirty_tiles_unclaimed
+ON dirty_tiles(id) WHERE claimed_at IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_dirty_tiles_claimed_at
+ON dirty_tiles(claimed_at) WHERE claimed_at IS NOT NULL;
@@ -0,0 +1,23 @@
+-- Staging area for multi-worker deployments
+-- Workers that do not hold the writer lock render claimed tiles into this
+-- table; the single elected writer commits them to the PMTiles archive and
+-- deletes the rows. Rows outlive a writer crash, so the next writer picks
+-- them up after failover.
+
+CREATE TABLE IF NOT EXISTS rendered_tiles (
+    id           BIGSERIAL PRIMARY KEY,
+    z            SMALLINT NOT NULL,
+    x            INTEGER NOT NULL,
+    y            INTEGER NOT NULL,
+    data         BYTEA NOT NULL,
+    rendered_by  TEXT,
+    rendered_at  TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- Receipt time and replication state of the batch the tile was rendered for
+    queued_at              TIMESTAMPTZ,
+    replication_sequence   BIGINT,
+    replication_timestamp  TIMESTAMPTZ
+);
+
+ALTER TABLE rendered_tiles ADD COLUMN IF NOT EXISTS queued_at TIMESTAMPTZ;
+ALTER TABLE rendered_tiles ADD COLUMN IF NOT EXISTS replication_sequence BIGINT;
+ALTER TABLE rendered_tiles ADD COLUMN IF NOT EXISTS replication_timestamp TIMESTAMPTZ;
@@ -38,12 +38,13 @@ osm2pgsql-replication update \
 
 # Check if dirty tiles file was created and has content
 if [ -f "$DIRTY_TILES_FILE" ] && [ -s "$DIRTY_TILES_FILE" ]; then
-    TILE_COUNT=$(wc -l < "$DIRTY_TILES_FILE")
+    TILE_COUNT=$(( $(wc -l < "$DIRTY_TILES_FILE") ))
     echo "$(date): Found $TILE_COUNT dirty tiles, notifying worker..."
     
     # Notify Rust worker that new tiles are ready
-    # The notification payload contains the filename
-    psql "$DATABASE_URL" -c "NOTIFY tiles_updated, '$DIRTY_TILES_FILE';"
+    # The payload is JSON with the filename and tile count (a bare path is also accepted)
+    PAYLOAD="{\"file\": \"$DIRTY_TILES_FILE\", \"tile_count\": $TILE_COUNT}"
+    psql "$DATABASE_URL" -c "NOTIFY tiles_updated, '$PAYLOAD';"
     
     echo "$(date): Update complete, notification sent"
 else
@@ -0,0 +1,420 @@
+use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use jvt::TileCoord;
+use jvt::config::DirtyTilesFormat;
+use jvt::rerender::ZoomRange;
+
+/// JVT - incremental vector tiles from OpenStreetMap replication diffs
+#[derive(Debug, Parser)]
+#[command(name = "jvt", version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the tile worker (default)
+    Worker,
+    /// Load an expire file into the dirty_tiles queue table
+    Enqueue(EnqueueArgs),
+    /// Run osm2pgsql replication on an interval and render the expired tiles
+    Replicate(ReplicateArgs),
+    /// Serve tiles from the PMTiles archive over HTTP
+    Serve(ServeArgs),
+    /// Check a running worker's readiness (exit code 0 when ready)
+    Healthcheck(HealthcheckArgs),
+    /// Queue a re-render of a tile, bbox, GeoJSON area or OSM relation
+    Rerender(RerenderArgs),
+    /// Render a zoom range over the planet, a bbox or an area into a new archive
+    Seed(SeedArgs),
+    /// Debug individual tiles
+    Tile(TileArgs),
+    /// Work with PMTiles archives
+    Archive(ArchiveArgs),
+    /// Export the archive for consumers that cannot read PMTiles
+    Export(ExportArgs),
+    /// Build the archive from an MBTiles file
+    Import(ImportArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct EnqueueArgs {
+    /// Dirty tiles / expire file (optionally gzip or zstd compressed)
+    pub file: PathBuf,
+
+    /// Source recorded with each queued tile (defaults to the file path)
+    #[arg(long)]
+    pub source: Option<String>,
+
+    /// Addressing scheme of the file: auto, xyz, tms or quadkey
+    #[arg(long)]
+    pub format: Option<DirtyTilesFormat>,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplicateArgs {
+    /// Run a single replication update and exit
+    #[arg(long)]
+    pub once: bool,
+
+    /// Seconds between runs (overrides REPLICATION_INTERVAL_SECS)
+    #[arg(long)]
+    pub interval: Option<u64>,
+}
+
+#[derive(Debug, Args)]
+pub struct ServeArgs {
+    /// Address to listen on (overrides LISTEN_ADDR)
+    #[arg(long)]
+    pub listen: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct HealthcheckArgs {
+    /// Readiness URL (defaults to /readyz on HEALTH_ADDR)
+    #[arg(long)]
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct RerenderArgs {
+    #[command(subcommand)]
+    pub target: RerenderCommand,
+
+    /// Who asked for the re-render, recorded in the dirty tiles file (defaults to $USER)
+    #[arg(long, global = true)]
+    pub requested_by: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RerenderCommand {
+    /// A single tile, z/x/y
+    Tile { tile: String },
+    /// Tiles intersecting min_lon,min_lat,max_lon,max_lat
+    Bbox {
+        bbox: String,
+        /// Zoom range, e.g. 10-14 (defaults to all rendered zooms)
+        #[arg(long)]
+        zoom: Option<ZoomRange>,
+    },
+    /// Tiles intersecting the geometries of a GeoJSON file
+    Geojson {
+        file: PathBuf,
+        #[arg(long)]
+        zoom: Option<ZoomRange>,
+    },
+    /// Tiles intersecting an OSM relation's area in planet_osm_polygon
+    Relation {
+        id: i64,
+        #[arg(long)]
+        zoom: Option<ZoomRange>,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct SeedArgs {
+    /// Zoom range to render, e.g. 0-14 (defaults to all rendered zooms)
+    #[arg(long)]
+    pub zoom: Option<ZoomRange>,
+
+    /// Only tiles intersecting min_lon,min_lat,max_lon,max_lat (default: the planet)
+    #[arg(long, conflicts_with_all = ["geojson", "relation"])]
+    pub bbox: Option<String>,
+
+    /// Only tiles intersecting the geometries of a GeoJSON file
+    #[arg(long, conflicts_with = "relation")]
+    pub geojson: Option<PathBuf>,
+
+    /// Only tiles intersecting an OSM relation's area in planet_osm_polygon
+    #[arg(long)]
+    pub relation: Option<i64>,
+
+    /// Archive to create (defaults to PMTILES_ARCHIVE_PATH)
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+
+    /// Deepest zoom at which empty subtrees are pruned
+    #[arg(long, default_value_t = 12)]
+    pub prune_zoom: u8,
+
+    /// Number of zoom 6 units rendered concurrently
+    #[arg(long, default_value_t = 1)]
+    pub jobs: usize,
+
+    /// Discard an interrupted seed instead of resuming it
+    #[arg(long)]
+    pub restart: bool,
+
+    /// Replace the output archive if it exists
+    #[arg(long)]
+    pub force: bool,
+
+    /// Keep the spool of rendered tiles after the archive is written
+    #[arg(long)]
+    pub keep_spool: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct TileArgs {
+    #[command(subcommand)]
+    pub command: TileCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TileCommand {
+    /// Decode a tile and list its layers, feature counts and attribute keys
+    Inspect(InspectArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct InspectArgs {
+    /// Tile to inspect, z/x/y
+    pub tile: TileCoord,
+
+    /// Render the tile from PostGIS instead of reading it from the archive
+    #[arg(long, conflicts_with = "diff")]
+    pub fresh: bool,
+
+    /// Compare the archived tile with a fresh render
+    #[arg(long)]
+    pub diff: bool,
+
+    /// Print the features as GeoJSON in lon/lat instead of the summary
+    #[arg(long, conflicts_with = "diff")]
+    pub geojson: bool,
+
+    /// Archive to read (defaults to PMTILES_ARCHIVE_PATH)
+    #[arg(long)]
+    pub archive: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ArchiveArgs {
+    #[command(subcommand)]
+    pub command: ArchiveCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ArchiveCommand {
+    /// Report tiles added, removed and changed between two archives
+    Diff(ArchiveDiffArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ArchiveDiffArgs {
+    /// Archive before the change
+    pub before: PathBuf,
+
+    /// Archive after the change
+    pub after: PathBuf,
+
+    /// Also decode differing tiles and report feature count changes per layer
+    #[arg(long)]
+    pub layers: bool,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// Output format
+    #[arg(long, value_enum)]
+    pub format: ExportFormat,
+
+    /// File or directory to write
+    pub output: PathBuf,
+
+    /// Archive to export (defaults to PMTILES_ARCHIVE_PATH)
+    #[arg(long)]
+    pub archive: Option<PathBuf>,
+
+    /// Replace the output if it exists
+    #[arg(long)]
+    pub force: bool,
+
+    /// Also write gzipped .pbf.gz tiles (dir only)
+    #[arg(long)]
+    pub gzip: bool,
+
+    /// Only export this zoom range, e.g. 0-10 (dir only)
+    #[arg(long)]
+    pub zoom: Option<ZoomRange>,
+
+    /// Only export tiles intersecting min_lon,min_lat,max_lon,max_lat (dir only)
+    #[arg(long)]
+    pub bbox: Option<String>,
+
+    /// Update an existing export with the tiles changed by batches after
+    /// this changed_tile_batches id, with the export's own options (dir only)
+    #[arg(long)]
+    pub since: Option<i64>,
+
+    /// Tile URL template written to tiles.json (dir only, default {z}/{x}/{y}.pbf)
+    #[arg(long)]
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// MBTiles SQLite file with gzipped tiles
+    Mbtiles,
+    /// {z}/{x}/{y}.pbf files with a tiles.json
+    Dir,
+}
+
+#[derive(Debug, Args)]
+pub struct ImportArgs {
+    /// MBTiles file to read
+    pub input: PathBuf,
+
+    /// Archive to create (defaults to PMTILES_ARCHIVE_PATH)
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+
+    /// Replace the output archive if it exists
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_command() {
+        let cli = Cli::try_parse_from(["jvt"]).unwrap();
+        assert!(cli.command.is_none());
+    }
+
+    #[test]
+    fn test_enqueue_args() {
+        let cli = Cli::try_parse_from([
+            "jvt", "enqueue", "/tmp/expire.txt.gz", "--source", "manual", "--format", "tms",
+        ]).unwrap();
+
+        let Some(Command::Enqueue(args)) = cli.command else {
+            panic!("expected enqueue command");
+        };
+        assert_eq!(args.file, PathBuf::from("/tmp/expire.txt.gz"));
+        assert_eq!(args.source.as_deref(), Some("manual"));
+        assert_eq!(args.format, Some(DirtyTilesFormat::Tms));
+
+        assert!(Cli::try_parse_from(["jvt", "enqueue", "x", "--format", "bogus"]).is_err());
+    }
+
+    #[test]
+    fn test_replicate_args() {
+        let cli = Cli::try_parse_from(["jvt", "replicate", "--once", "--interval", "60"]).unwrap();
+
+        let Some(Command::Replicate(args)) = cli.command else {
+            panic!("expected replicate command");
+        };
+        assert!(args.once);
+        assert_eq!(args.interval, Some(60));
+    }
+
+    #[test]
+    fn test_healthcheck_args() {
+        let cli = Cli::try_parse_from(["jvt", "healthcheck", "--url", "http://w:9090/readyz"]).unwrap();
+
+        let Some(Command::Healthcheck(args)) = cli.command else {
+            panic!("expected healthcheck command");
+        };
+        assert_eq!(args.url.as_deref(), Some("http://w:9090/readyz"));
+    }
+
+    #[test]
+    fn test_rerender_args() {
+        let cli = Cli::try_parse_from([
+            "jvt", "rerender", "bbox", "10.5,59.8,10.9,60.0", "--zoom", "10-14", "--requested-by", "ops",
+        ]).unwrap();
+
+        let Some(Command::Rerender(args)) = cli.command else {
+            panic!("expected rerender command");
+        };
+        assert_eq!(args.requested_by.as_deref(), Some("ops"));
+        let RerenderCommand::Bbox { bbox, zoom } = args.target else {
+            panic!("expected bbox target");
+        };
+        assert_eq!(bbox, "10.5,59.8,10.9,60.0");
+        assert_eq!(zoom, Some(ZoomRange::new(10, 14)));
+
+        assert!(Cli::try_parse_from(["jvt", "rerender", "relation", "62422", "--zoom", "14-2"]).is_err());
+    }
+
+    #[test]
+    fn test_seed_args() {
+        let cli = Cli::try_parse_from([
+            "jvt", "seed", "--zoom", "0-12", "--bbox", "10.5,59.8,10.9,60.0", "--jobs", "4",
+        ]).unwrap();
+
+        let Some(Command::Seed(args)) = cli.command else {
+            panic!("expected seed command");
+        };
+        assert_eq!(args.zoom, Some(ZoomRange::new(0, 12)));
+        assert_eq!(args.bbox.as_deref(), Some("10.5,59.8,10.9,60.0"));
+        assert_eq!(args.jobs, 4);
+        assert_eq!(args.prune_zoom, 12);
+        assert!(!args.restart);
+
+        assert!(Cli::try_parse_from(["jvt", "seed", "--bbox", "1,2,3,4", "--relation", "62422"]).is_err());
+    }
+
+    #[test]
+    fn test_tile_inspect_args() {
+        let cli = Cli::try_parse_from(["jvt", "tile", "inspect", "14/8234/5425", "--diff"]).unwrap();
+
+        let Some(Command::Tile(TileArgs { command: TileCommand::Inspect(args) })) = cli.command else {
+            panic!("expected tile inspect command");
+        };
+        assert_eq!(args.tile, TileCoord::new(14, 8234, 5425));
+        assert!(args.diff && !args.fresh && !args.geojson);
+
+        assert!(Cli::try_parse_from(["jvt", "tile", "inspect", "14/8234"]).is_err());
+        assert!(Cli::try_parse_from(["jvt", "tile", "inspect", "0/0/0", "--diff", "--fresh"]).is_err());
+    }
+
+    #[test]
+    fn test_archive_diff_args() {
+        let cli = Cli::try_parse_from(["jvt", "archive", "diff", "a.pmtiles", "b.pmtiles", "--json"]).unwrap();
+
+        let Some(Command::Archive(ArchiveArgs { command: ArchiveCommand::Diff(args) })) = cli.command else {
+            panic!("expected archive diff command");
+        };
+        assert_eq!((args.before, args.after), (PathBuf::from("a.pmtiles"), PathBuf::from("b.pmtiles")));
+        assert!(args.json && !args.layers);
+    }
+
+    #[test]
+    fn test_export_and_import_args() {
+        let cli = Cli::try_parse_from(["jvt", "export", "--format", "mbtiles", "/tmp/planet.mbtiles"]).unwrap();
+
+        let Some(Command::Export(args)) = cli.command else {
+            panic!("expected export command");
+        };
+        assert_eq!(args.format, ExportFormat::Mbtiles);
+        assert_eq!(args.output, PathBuf::from("/tmp/planet.mbtiles"));
+        assert!(Cli::try_parse_from(["jvt", "export", "/tmp/planet.mbtiles"]).is_err());
+
+        let cli = Cli::try_parse_from([
+            "jvt", "export", "--format", "dir", "/srv/tiles", "--gzip", "--zoom", "0-10", "--since", "42",
+        ]).unwrap();
+        let Some(Command::Export(args)) = cli.command else {
+            panic!("expected export command");
+        };
+        assert_eq!(args.format, ExportFormat::Dir);
+        assert_eq!((args.zoom, args.since), (Some(ZoomRange::new(0, 10)), Some(42)));
+        assert!(args.gzip && args.bbox.is_none());
+
+        let cli = Cli::try_parse_from(["jvt", "import", "old.mbtiles", "--output", "new.pmtiles", "--force"]).unwrap();
+        let Some(Command::Import(args)) = cli.command else {
+            panic!("expected import command");
+        };
+        assert_eq!(args.input, PathBuf::from("old.mbtiles"));
+        assert_eq!(args.output, Some(PathBuf::from("new.pmtiles")));
+        assert!(args.force);
+    }
+}
@@ -1,3 +1,6 @@
 pub mod settings;
 
-pub use settings::Config; 
\ No newline at end of file
+pub use settings::{
+    AdminConfig, ChangeFeedConfig, Config, DirtyTilesFormat, IngestMode, LockConflict, ReplicationConfig, SchedulingConfig, ServerConfig,
+    ZoomRefreshRule,
+};
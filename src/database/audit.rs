@@ -0,0 +1,119 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use tracing::debug;
+use crate::TileCoord;
+use crate::worker::TileBatch;
+use super::DatabasePool;
+
+/// Writes archive commits to the `changed_tile_batches` audit table, and
+/// their tiles to `changed_tiles`
+#[derive(Clone)]
+pub struct AuditLog {
+    database: DatabasePool,
+}
+
+impl AuditLog {
+    /// Create a new audit log writer
+    pub fn new(database: DatabasePool) -> Self {
+        Self { database }
+    }
+
+    /// Record the tiles of a batch that one archive commit wrote, and return
+    /// its audit row id
+    pub async fn record_batch(
+        &self,
+        batch: &TileBatch,
+        tiles: &[TileCoord],
+        started_at: DateTime<Utc>,
+        finished_at: DateTime<Utc>,
+    ) -> Result<i64> {
+        let source_file = batch.source_file.display().to_string();
+        let min_zoom = tiles.iter().map(|t| i16::from(t.z)).min().unwrap_or(0);
+        let max_zoom = tiles.iter().map(|t| i16::from(t.z)).max().unwrap_or(0);
+        let zs: Vec<i16> = tiles.iter().map(|t| i16::from(t.z)).collect();
+        let xs: Vec<i32> = tiles.iter().map(|t| t.x as i32).collect();
+        let ys: Vec<i32> = tiles.iter().map(|t| t.y as i32).collect();
+
+        let row = self.database.query_one(
+            "WITH batch AS (
+                 INSERT INTO changed_tile_batches
+                     (first_z, last_z, tile_count, started_at, finished_at, source_file,
+                      replication_sequence, replication_timestamp, received_at)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                 RETURNING id
+             ), tiles AS (
+                 INSERT INTO changed_tiles (batch_id, z, x, y)
+                 SELECT batch.id, t.z, t.x, t.y
+                 FROM batch, unnest($10::smallint[], $11::integer[], $12::integer[]) AS t(z, x, y)
+                 ON CONFLICT DO NOTHING
+             )
+             SELECT id FROM batch",
+            &[
+                &min_zoom,
+                &max_zoom,
+                &(tiles.len() as i32),
+                &started_at,
+                &finished_at,
+                &source_file,
+                &batch.replication.sequence,
+                &batch.replication.timestamp,
+                &batch.created_at,
+                &zs,
+                &xs,
+                &ys,
+            ],
+        )
+        .await
+        .context("Failed to record tile batch in audit table")?;
+
+        let id: i64 = row.get(0);
+        debug!("Recorded audit row {} for {} tiles from {}", id, tiles.len(), source_file);
+        Ok(id)
+    }
+
+    /// Tiles written by batches after `batch_id`, with the newest batch id
+    /// (None when no batches have been recorded). Fails if some of those
+    /// batches' tiles were already pruned.
+    pub async fn changed_since(&self, batch_id: i64) -> Result<(Option<i64>, Vec<TileCoord>)> {
+        let row = self.database
+            .query_one(
+                "SELECT max(id), bool_or(tiles_pruned AND id > $1) FROM changed_tile_batches",
+                &[&batch_id],
+            )
+            .await
+            .context("Failed to read the latest tile batch")?;
+        let latest: Option<i64> = row.get(0);
+        if row.get::<_, Option<bool>>(1).unwrap_or(false) {
+            anyhow::bail!("Changed tiles of batches after {} have been pruned; run a full export instead", batch_id);
+        }
+
+        let rows = self.database.query(
+            "SELECT DISTINCT z, x, y FROM changed_tiles WHERE batch_id > $1 AND batch_id <= $2",
+            &[&batch_id, &latest.unwrap_or(batch_id)],
+        )
+        .await
+        .context("Failed to read changed tiles")?;
+
+        let tiles = rows.iter()
+            .map(|row| TileCoord::new(row.get::<_, i16>(0) as u8, row.get::<_, i32>(1) as u32, row.get::<_, i32>(2) as u32))
+            .collect();
+        Ok((latest, tiles))
+    }
+
+    /// Delete the `changed_tiles` rows of batches finished before `cutoff`,
+    /// marking the batches so `changed_since` knows their tiles are gone.
+    /// Returns the number of rows deleted.
+    pub async fn prune_changed_tiles(&self, cutoff: DateTime<Utc>) -> Result<u64> {
+        self.database.execute(
+            "WITH pruned AS (
+                 UPDATE changed_tile_batches SET tiles_pruned = true
+                 WHERE finished_at < $1 AND NOT tiles_pruned
+                 RETURNING id
+             )
+             DELETE FROM changed_tiles WHERE batch_id IN (SELECT id FROM pruned)",
+            &[&cutoff],
+        )
+        .await
+        .context("Failed to prune changed tiles")
+    }
+}
@@ -2,6 +2,7 @@ use std::sync::Arc;
 use tokio_postgres::{Client, NoTls, Row};
 use anyhow::{Context, Result};
 use tracing::{info, error};
+use crate::metrics::InFlight;
 
 /// Database connection pool for PostgreSQL
 #[derive(Clone)]
@@ -46,6 +47,7 @@ impl DatabasePool {
 
     /// Execute a query and return all rows
     pub async fn query(&self, query: &str, params: &[&(dyn tokio_postgres::types::ToSql + Sync)]) -> Result<Vec<Row>> {
+        let _in_flight = InFlight::start();
         self.client
             .query(query, params)
             .await
@@ -54,6 +56,7 @@ impl DatabasePool {
 
     /// Execute a query and return a single row
     pub async fn query_one(&self, query: &str, params: &[&(dyn tokio_postgres::types::ToSql + Sync)]) -> Result<Row> {
+        let _in_flight = InFlight::start();
         self.client
             .query_one(query, params)
             .await
@@ -62,12 +65,18 @@ impl DatabasePool {
 
     /// Execute a query that doesn't return rows
     pub async fn execute(&self, query: &str, params: &[&(dyn tokio_postgres::types::ToSql + Sync)]) -> Result<u64> {
+        let _in_flight = InFlight::start();
         self.client
             .execute(query, params)
             .await
             .context("Database execute failed")
     }
 
+    /// Whether the connection is still open
+    pub fn is_connected(&self) -> bool {
+        !self.client.is_closed()
+    }
+
     /// Test database connectivity
     pub async fn health_check(&self) -> Result<()> {
         self.client
@@ -78,16 +87,52 @@ impl DatabasePool {
     }
 }
 
+#[cfg(test)]
+impl DatabasePool {
+    /// Connection to `TEST_DATABASE_URL` with the init scripts' tables in a
+    /// fresh `schema`, or None (and the test is skipped) when it is not set
+    pub async fn for_test(schema: &str) -> Option<Self> {
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL not set, skipping database test");
+            return None;
+        };
+        let database = Self::new(&url).await.expect("TEST_DATABASE_URL is reachable");
+        database.client
+            .batch_execute(&format!("DROP SCHEMA IF EXISTS {0} CASCADE; CREATE SCHEMA {0}; SET search_path TO {0}", schema))
+            .await
+            .expect("test schema is created");
+
+        // Only the table definitions; extensions and server settings are left alone
+        let scripts = [
+            include_str!("../../init-scripts/01-create-audit-tables.sql"),
+            include_str!("../../init-scripts/02-create-tile-queue.sql"),
+            include_str!("../../init-scripts/03-create-rendered-tiles.sql"),
+        ];
+        let sql: String = scripts.iter()
+            .flat_map(|script| script.lines())
+            .map(|line| line.split_once("--").map_or(line, |(code, _)| code))
+            .collect::<Vec<_>>()
+            .join("\n");
+        for statement in sql.split(';').map(str::trim) {
+            if ["CREATE TABLE", "ALTER TABLE", "CREATE INDEX"].iter().any(|kind| statement.starts_with(kind)) {
+                database.client.batch_execute(statement).await
+                    .unwrap_or_else(|e| panic!("Failed to apply {}: {}", statement, e));
+            }
+        }
+        Some(database)
+    }
+}
+
 /// Mask password in database URL for logging
 fn mask_password(url: &str) -> String {
-    if let Some(start) = url.find("://") {
-        if let Some(at_pos) = url[start + 3..].find('@') {
-            let prefix = &url[..start + 3];
-            let suffix = &url[start + 3 + at_pos..];
-            if let Some(colon_pos) = url[start + 3..start + 3 + at_pos].find(':') {
-                let username = &url[start + 3..start + 3 + colon_pos];
-                return format!("{}{}:***{}", prefix, username, suffix);
-            }
+    if let Some(start) = url.find("://")
+        && let Some(at_pos) = url[start + 3..].find('@')
+    {
+        let prefix = &url[..start + 3];
+        let suffix = &url[start + 3 + at_pos..];
+        if let Some(colon_pos) = url[start + 3..start + 3 + at_pos].find(':') {
+            let username = &url[start + 3..start + 3 + colon_pos];
+            return format!("{}{}:***{}", prefix, username, suffix);
         }
     }
     url.to_string()
@@ -1,5 +1,9 @@
 use std::path::PathBuf;
-use tokio_postgres::{Client, NoTls};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use futures::StreamExt;
+use tokio::sync::mpsc;
+use tokio_postgres::{AsyncMessage, Client, NoTls};
 use tokio::time::{timeout, Duration};
 use anyhow::{Context, Result};
 use tracing::{info, warn, error, debug};
@@ -8,6 +12,7 @@ use tracing::{info, warn, error, debug};
 pub struct NotificationListener {
     client: Client,
     channel: String,
+    notifications: mpsc::UnboundedReceiver<TileNotification>,
 }
 
 /// Notification received from PostgreSQL
@@ -18,25 +23,87 @@ pub struct TileNotification {
     pub process_id: u32,
 }
 
+/// Parsed notification payload.
+///
+/// Payloads are either a bare dirty tiles file path or a JSON object carrying
+/// replication metadata alongside the path:
+/// `{"file": "...", "sequence": 6123456, "timestamp": "2025-07-24T19:32:45Z", "tile_count": 1234}`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NotificationPayload {
+    pub file: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tile_count: Option<u64>,
+}
+
+impl NotificationPayload {
+    /// Parse a payload, detecting JSON and falling back to the plain path format
+    pub fn parse(payload: &str) -> Result<Self> {
+        let payload = payload.trim();
+
+        if payload.is_empty() {
+            return Err(anyhow::anyhow!("Empty notification payload"));
+        }
+
+        if payload.starts_with('{') {
+            return serde_json::from_str(payload)
+                .context("Failed to parse JSON notification payload");
+        }
+
+        // Expected payload format: "/var/cache/renderd/dirty_tiles.20250724_193245.txt"
+        Ok(Self {
+            file: PathBuf::from(payload),
+            sequence: None,
+            timestamp: None,
+            tile_count: None,
+        })
+    }
+}
+
 impl NotificationListener {
     /// Create a new notification listener
     pub async fn new(database_url: &str, channel: &str) -> Result<Self> {
         info!("Creating notification listener for channel: {}", channel);
         
-        let (client, connection) = tokio_postgres::connect(database_url, NoTls)
+        let (client, mut connection) = tokio_postgres::connect(database_url, NoTls)
             .await
             .context("Failed to connect to PostgreSQL for notifications")?;
 
-        // Spawn the connection handler
+        // Drive the connection and forward notifications; the sender is
+        // dropped when the connection ends, which wait_for_notification reports
+        let (sender, notifications) = mpsc::unbounded_channel();
         tokio::spawn(async move {
-            if let Err(e) = connection.await {
-                error!("PostgreSQL notification connection error: {}", e);
+            let mut messages = futures::stream::poll_fn(move |cx| connection.poll_message(cx));
+
+            while let Some(message) = messages.next().await {
+                match message {
+                    Ok(AsyncMessage::Notification(notification)) => {
+                        let notification = TileNotification {
+                            channel: notification.channel().to_string(),
+                            payload: notification.payload().to_string(),
+                            process_id: notification.process_id() as u32,
+                        };
+                        if sender.send(notification).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(AsyncMessage::Notice(notice)) => debug!("PostgreSQL notice: {}", notice),
+                    Ok(_) => {}
+                    Err(e) => {
+                        error!("PostgreSQL notification connection error: {}", e);
+                        break;
+                    }
+                }
             }
         });
 
         let mut listener = Self {
             client,
             channel: channel.to_string(),
+            notifications,
         };
 
         // Subscribe to the channel
@@ -59,49 +126,38 @@ impl NotificationListener {
 
     /// Wait for the next notification with a timeout
     pub async fn wait_for_notification(&mut self, timeout_duration: Duration) -> Result<Option<TileNotification>> {
-        // For tokio-postgres, we need to implement notification listening differently
-        // This is a simplified version - in practice you'd use a connection stream
-        match timeout(timeout_duration, async {
-            loop {
-                // Check for notifications (this would be handled by the connection in practice)
-                tokio::time::sleep(Duration::from_millis(100)).await;
-                // This is a placeholder - the actual implementation would use
-                // the connection's notification stream
-                return Ok::<(), tokio_postgres::Error>(());
-            }
-        }).await {
-            Ok(_) => {
-                // For now, return None (no notification received)
-                // TODO: Implement proper notification handling
-                debug!("Notification timeout after {:?}", timeout_duration);
-                Ok(None)
-            }
+        match timeout(timeout_duration, self.notifications.recv()).await {
+            Ok(Some(notification)) => Ok(Some(notification)),
+            Ok(None) => Err(anyhow::anyhow!("Notification connection for {} closed", self.channel)),
             Err(_) => {
-                // Timeout occurred
                 debug!("Notification timeout after {:?}", timeout_duration);
                 Ok(None)
             }
         }
     }
 
-    /// Parse notification payload to extract dirty tiles file path
-    pub fn parse_notification(&self, notification: &TileNotification) -> Result<PathBuf> {
-        // Expected payload format: "/var/cache/renderd/dirty_tiles.20250724_193245.txt"
-        let path_str = notification.payload.trim();
-        
-        if path_str.is_empty() {
-            return Err(anyhow::anyhow!("Empty notification payload"));
-        }
+    /// Whether the LISTEN connection is still open
+    pub fn is_connected(&self) -> bool {
+        !self.client.is_closed()
+    }
 
-        let path = PathBuf::from(path_str);
+    /// Channel this listener is subscribed to
+    pub fn channel(&self) -> &str {
+        &self.channel
+    }
+
+    /// Parse notification payload to extract the dirty tiles file and replication metadata
+    pub fn parse_notification(notification: &TileNotification) -> Result<NotificationPayload> {
+        let payload = NotificationPayload::parse(&notification.payload)?;
         
-        if !path.exists() {
-            warn!("Dirty tiles file does not exist: {}", path_str);
-            return Err(anyhow::anyhow!("Dirty tiles file not found: {}", path_str));
+        if !payload.file.exists() {
+            warn!("Dirty tiles file does not exist: {}", payload.file.display());
+            return Err(anyhow::anyhow!("Dirty tiles file not found: {}", payload.file.display()));
         }
 
-        info!("Parsed dirty tiles file: {}", path_str);
-        Ok(path)
+        info!("Parsed dirty tiles file: {} (sequence: {:?}, timestamp: {:?})",
+              payload.file.display(), payload.sequence, payload.timestamp);
+        Ok(payload)
     }
 
     /// Get statistics about the listener
@@ -140,22 +196,50 @@ mod tests {
     fn test_notification_parsing() {
         let notification = TileNotification {
             channel: "tiles_updated".to_string(),
-            payload: "/tmp/test_dirty_tiles.txt".to_string(),
+            payload: "/tmp/test_notification_dirty_tiles.txt".to_string(),
             process_id: 12345,
         };
 
         // Create a temporary file for testing
-        std::fs::write("/tmp/test_dirty_tiles.txt", "14/8234/5425\n").unwrap();
+        std::fs::write("/tmp/test_notification_dirty_tiles.txt", "14/8234/5425\n").unwrap();
         
-        let listener = NotificationListener {
-            client: unsafe { std::mem::zeroed() }, // Not used in this test
-            channel: "tiles_updated".to_string(),
-        };
-
-        let path = listener.parse_notification(&notification).unwrap();
-        assert_eq!(path.to_string_lossy(), "/tmp/test_dirty_tiles.txt");
+        let payload = NotificationListener::parse_notification(&notification).unwrap();
+        assert_eq!(payload.file.to_string_lossy(), "/tmp/test_notification_dirty_tiles.txt");
+        assert_eq!(payload.sequence, None);
 
         // Clean up
-        std::fs::remove_file("/tmp/test_dirty_tiles.txt").ok();
+        std::fs::remove_file("/tmp/test_notification_dirty_tiles.txt").ok();
+    }
+
+    #[test]
+    fn test_json_payload_parsing() {
+        let payload = NotificationPayload::parse(r#"{
+            "file": "/var/cache/renderd/dirty_tiles.20250724_193245.txt",
+            "sequence": 6123456,
+            "timestamp": "2025-07-24T19:32:45Z",
+            "tile_count": 1234
+        }"#).unwrap();
+
+        assert_eq!(payload.file, PathBuf::from("/var/cache/renderd/dirty_tiles.20250724_193245.txt"));
+        assert_eq!(payload.sequence, Some(6123456));
+        assert_eq!(payload.timestamp.unwrap().to_rfc3339(), "2025-07-24T19:32:45+00:00");
+        assert_eq!(payload.tile_count, Some(1234));
+        let json = serde_json::to_string(&payload).unwrap();
+        assert_eq!(NotificationPayload::parse(&json).unwrap(), payload);
+
+        // Metadata fields are optional
+        let payload = NotificationPayload::parse(r#"{"file": "/tmp/x.txt"}"#).unwrap();
+        assert_eq!(payload.file, PathBuf::from("/tmp/x.txt"));
+        assert_eq!(payload.timestamp, None);
+
+        assert!(NotificationPayload::parse(r#"{"sequence": 1}"#).is_err());
+        assert!(NotificationPayload::parse("   ").is_err());
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_plain_payload_fallback() {
+        let payload = NotificationPayload::parse(" /tmp/dirty_tiles.txt\n").unwrap();
+        assert_eq!(payload.file, PathBuf::from("/tmp/dirty_tiles.txt"));
+        assert_eq!(payload.tile_count, None);
+    }
+}
@@ -1,5 +1,17 @@
+pub mod audit;
 pub mod connection;
 pub mod listener;
+pub mod rendered_tiles;
+pub mod replication_state;
+pub mod tile_queue;
+pub mod tile_updates;
+pub mod writer_lock;
 
+pub use audit::AuditLog;
 pub use connection::DatabasePool;
-pub use listener::NotificationListener; 
\ No newline at end of file
+pub use listener::{NotificationListener, NotificationPayload};
+pub use rendered_tiles::RenderedTileStore;
+pub use replication_state::ReplicationProperties;
+pub use tile_queue::TileQueue;
+pub use tile_updates::{TileUpdate, TileUpdatePublisher};
+pub use writer_lock::WriterLock;
@@ -0,0 +1,117 @@
+use std::path::PathBuf;
+use anyhow::{Context, Result};
+use tracing::debug;
+use crate::TileCoord;
+use crate::worker::TileBatch;
+use crate::worker::tile_batch::ReplicationInfo;
+use super::DatabasePool;
+
+/// Staging table (`rendered_tiles`) between rendering workers and the writer.
+///
+/// Workers without the writer lock stage rendered tiles here; the writer
+/// takes them in id order, commits them to the archive and only then removes
+/// the rows, so a writer crash mid-commit loses nothing.
+#[derive(Clone)]
+pub struct RenderedTileStore {
+    database: DatabasePool,
+    worker_id: String,
+}
+
+/// Rendered tiles taken from the staging table, to be removed once committed
+#[derive(Debug)]
+pub struct StagedTiles {
+    pub ids: Vec<i64>,
+    pub tiles: Vec<(TileCoord, Vec<u8>)>,
+    pub batch: TileBatch,
+}
+
+impl RenderedTileStore {
+    /// Create a staging handle for this worker
+    pub fn new(database: DatabasePool, worker_id: &str) -> Self {
+        Self {
+            database,
+            worker_id: worker_id.to_string(),
+        }
+    }
+
+    /// Stage rendered tiles of `batch` for the writer, returning the number of rows added
+    pub async fn stage(&self, tiles: &[(TileCoord, Vec<u8>)], batch: &TileBatch) -> Result<u64> {
+        if tiles.is_empty() {
+            return Ok(0);
+        }
+
+        let zs: Vec<i16> = tiles.iter().map(|(t, _)| i16::from(t.z)).collect();
+        let xs: Vec<i32> = tiles.iter().map(|(t, _)| t.x as i32).collect();
+        let ys: Vec<i32> = tiles.iter().map(|(t, _)| t.y as i32).collect();
+        let data: Vec<&[u8]> = tiles.iter().map(|(_, d)| d.as_slice()).collect();
+
+        let staged = self.database.execute(
+            "INSERT INTO rendered_tiles
+                 (z, x, y, data, rendered_by, queued_at, replication_sequence, replication_timestamp)
+             SELECT z, x, y, data, $5, $6, $7, $8
+             FROM UNNEST($1::smallint[], $2::integer[], $3::integer[], $4::bytea[]) AS t(z, x, y, data)",
+            &[
+                &zs, &xs, &ys, &data, &self.worker_id,
+                &batch.created_at, &batch.replication.sequence, &batch.replication.timestamp,
+            ],
+        )
+        .await
+        .context("Failed to stage rendered tiles")?;
+
+        debug!("Staged {} rendered tiles for the writer", staged);
+        Ok(staged)
+    }
+
+    /// Take up to `limit` staged tiles, oldest first. Later renders of the
+    /// same tile sort after earlier ones and win when committed.
+    pub async fn take(&self, limit: usize) -> Result<Option<StagedTiles>> {
+        let rows = self.database.query(
+            "SELECT id, z, x, y, data, queued_at, replication_sequence, replication_timestamp
+             FROM rendered_tiles ORDER BY id LIMIT $1",
+            &[&(limit as i64)],
+        )
+        .await
+        .context("Failed to read staged tiles")?;
+
+        if rows.is_empty() {
+            return Ok(None);
+        }
+
+        let mut batch = TileBatch::new(PathBuf::from("rendered_tiles"));
+        let mut ids = Vec::with_capacity(rows.len());
+        let mut tiles = Vec::with_capacity(rows.len());
+
+        for row in &rows {
+            ids.push(row.get::<_, i64>(0));
+            let (z, x, y) = (row.get::<_, i16>(1), row.get::<_, i32>(2), row.get::<_, i32>(3));
+
+            match TileCoord::try_new(z as u8, x as u32, y as u32) {
+                Ok(coord) => {
+                    batch.add_tile(coord.clone());
+                    tiles.push((coord, row.get::<_, Vec<u8>>(4)));
+                }
+                Err(e) => debug!("Skipping invalid staged tile {}/{}/{}: {}", z, x, y, e),
+            }
+
+            if let Some(queued_at) = row.get(5) {
+                batch.created_at = batch.created_at.min(queued_at);
+            }
+            batch.replication.merge(&ReplicationInfo { sequence: row.get(6), timestamp: row.get(7) });
+        }
+
+        Ok(Some(StagedTiles { ids, tiles, batch }))
+    }
+
+    /// Delete staged rows once they are committed to the archive
+    pub async fn remove(&self, staged: &StagedTiles) -> Result<u64> {
+        self.database.execute("DELETE FROM rendered_tiles WHERE id = ANY($1)", &[&staged.ids])
+            .await
+            .context("Failed to remove committed staged tiles")
+    }
+
+    /// Number of rendered tiles waiting for the writer
+    pub async fn depth(&self) -> Result<i64> {
+        let row = self.database.query_one("SELECT count(*) FROM rendered_tiles", &[]).await?;
+        Ok(row.get(0))
+    }
+}
@@ -0,0 +1,78 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use tracing::debug;
+use crate::worker::tile_batch::ReplicationInfo;
+use super::DatabasePool;
+
+/// Reads the replication state osm2pgsql keeps in `osm2pgsql_properties`
+/// (osm2pgsql 1.9+), i.e. the OSM data timestamp the database reflects.
+#[derive(Clone)]
+pub struct ReplicationProperties {
+    database: DatabasePool,
+}
+
+impl ReplicationProperties {
+    /// Create a reader for the given database
+    pub fn new(database: DatabasePool) -> Self {
+        Self { database }
+    }
+
+    /// Current replication sequence and timestamp; empty if osm2pgsql has
+    /// not recorded any (older versions or a non-updatable import)
+    pub async fn read(&self) -> Result<ReplicationInfo> {
+        let exists = self.database
+            .query_one("SELECT to_regclass('osm2pgsql_properties') IS NOT NULL", &[])
+            .await?;
+        if !exists.get::<_, bool>(0) {
+            debug!("No osm2pgsql_properties table, replication state unknown");
+            return Ok(ReplicationInfo::default());
+        }
+
+        let rows = self.database.query(
+            "SELECT property, value FROM osm2pgsql_properties
+             WHERE property IN ('replication_sequence_number', 'replication_timestamp')",
+            &[],
+        )
+        .await
+        .context("Failed to read osm2pgsql_properties")?;
+
+        Ok(parse_properties(rows.iter().map(|row| (row.get(0), row.get(1)))))
+    }
+}
+
+/// Build replication info from `(property, value)` pairs, ignoring bad values
+fn parse_properties<'a>(properties: impl Iterator<Item = (&'a str, &'a str)>) -> ReplicationInfo {
+    let mut info = ReplicationInfo::default();
+
+    for (property, value) in properties {
+        match property {
+            "replication_sequence_number" => info.sequence = value.trim().parse().ok(),
+            "replication_timestamp" => {
+                info.timestamp = value.trim().parse::<DateTime<Utc>>().ok();
+            }
+            _ => {}
+        }
+    }
+
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_properties() {
+        let info = parse_properties([
+            ("replication_sequence_number", "6123456"),
+            ("replication_timestamp", "2026-10-18T09:59:58Z"),
+            ("import_slim", "true"),
+        ].into_iter());
+
+        assert_eq!(info.sequence, Some(6123456));
+        assert_eq!(info.timestamp, Some("2026-10-18T09:59:58Z".parse().unwrap()));
+
+        let info = parse_properties([("replication_timestamp", "yesterday")].into_iter());
+        assert_eq!(info, ReplicationInfo::default());
+    }
+}
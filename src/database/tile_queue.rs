@@ -0,0 +1,132 @@
+use std::path::PathBuf;
+use anyhow::{Context, Result};
+use tracing::{debug, info};
+use crate::TileCoord;
+use crate::worker::TileBatch;
+use crate::worker::tile_batch::ReplicationInfo;
+use super::DatabasePool;
+
+/// Rows inserted per statement when enqueueing
+const ENQUEUE_CHUNK: usize = 10_000;
+
+/// Database-backed queue of dirty tiles (`dirty_tiles` table).
+///
+/// Producers insert expired tiles; workers claim rows with
+/// `FOR UPDATE SKIP LOCKED` so several workers never claim the same row.
+/// Claims are leases: rows are deleted once the batch is committed, and a
+/// claim whose worker died becomes claimable again after the lease expires.
+#[derive(Clone)]
+pub struct TileQueue {
+    database: DatabasePool,
+    worker_id: String,
+    lease_secs: u64,
+}
+
+/// Tiles claimed from the queue, to be completed or released
+#[derive(Debug)]
+pub struct ClaimedTiles {
+    pub ids: Vec<i64>,
+    pub batch: TileBatch,
+}
+
+impl TileQueue {
+    /// Create a queue handle for this worker
+    pub fn new(database: DatabasePool, worker_id: &str, lease_secs: u64) -> Self {
+        Self {
+            database,
+            worker_id: worker_id.to_string(),
+            lease_secs,
+        }
+    }
+
+    /// Insert tiles into the queue, returning the number of rows added
+    pub async fn enqueue(&self, tiles: &[TileCoord], source: &str, replication: &ReplicationInfo) -> Result<u64> {
+        let mut inserted = 0;
+
+        for chunk in tiles.chunks(ENQUEUE_CHUNK) {
+            let zs: Vec<i16> = chunk.iter().map(|t| i16::from(t.z)).collect();
+            let xs: Vec<i32> = chunk.iter().map(|t| t.x as i32).collect();
+            let ys: Vec<i32> = chunk.iter().map(|t| t.y as i32).collect();
+
+            inserted += self.database.execute(
+                "INSERT INTO dirty_tiles (z, x, y, source, replication_sequence, replication_timestamp)
+                 SELECT z, x, y, $4, $5, $6 FROM UNNEST($1::smallint[], $2::integer[], $3::integer[]) AS t(z, x, y)",
+                &[&zs, &xs, &ys, &source, &replication.sequence, &replication.timestamp],
+            )
+            .await
+            .context("Failed to enqueue dirty tiles")?;
+        }
+
+        debug!("Enqueued {} tiles from {}", inserted, source);
+        Ok(inserted)
+    }
+
+    /// Claim up to `limit` unclaimed (or lease-expired) tiles, oldest first
+    pub async fn claim(&self, limit: usize) -> Result<Option<ClaimedTiles>> {
+        let rows = self.database.query(
+            "UPDATE dirty_tiles SET claimed_by = $1, claimed_at = now()
+             WHERE id IN (
+                 SELECT id FROM dirty_tiles
+                 WHERE claimed_at IS NULL OR claimed_at < now() - make_interval(secs => $2)
+                 ORDER BY id
+                 LIMIT $3
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING id, z, x, y, enqueued_at, replication_sequence, replication_timestamp",
+            &[&self.worker_id, &(self.lease_secs as f64), &(limit as i64)],
+        )
+        .await
+        .context("Failed to claim dirty tiles")?;
+
+        if rows.is_empty() {
+            return Ok(None);
+        }
+
+        let mut batch = TileBatch::new(PathBuf::from("dirty_tiles"));
+        let mut ids = Vec::with_capacity(rows.len());
+
+        for row in &rows {
+            ids.push(row.get::<_, i64>(0));
+            let (z, x, y) = (row.get::<_, i16>(1), row.get::<_, i32>(2), row.get::<_, i32>(3));
+
+            match TileCoord::try_new(z as u8, x as u32, y as u32) {
+                Ok(coord) => batch.add_tile(coord),
+                Err(e) => debug!("Skipping invalid queued tile {}/{}/{}: {}", z, x, y, e),
+            }
+
+            // The batch reflects the newest data and waited since the oldest row
+            batch.created_at = batch.created_at.min(row.get(4));
+            batch.replication.merge(&ReplicationInfo { sequence: row.get(5), timestamp: row.get(6) });
+        }
+
+        info!("Claimed {} queued tiles ({} unique)", ids.len(), batch.len());
+        Ok(Some(ClaimedTiles { ids, batch }))
+    }
+
+    /// Remove claimed rows once their tiles are committed
+    pub async fn complete(&self, claim: &ClaimedTiles) -> Result<u64> {
+        self.database.execute(
+            "DELETE FROM dirty_tiles WHERE id = ANY($1) AND claimed_by = $2",
+            &[&claim.ids, &self.worker_id],
+        )
+        .await
+        .context("Failed to complete claimed tiles")
+    }
+
+    /// Return claimed rows to the queue after a failure
+    pub async fn release(&self, claim: &ClaimedTiles) -> Result<u64> {
+        self.database.execute(
+            "UPDATE dirty_tiles SET claimed_by = NULL, claimed_at = NULL
+             WHERE id = ANY($1) AND claimed_by = $2",
+            &[&claim.ids, &self.worker_id],
+        )
+        .await
+        .context("Failed to release claimed tiles")
+    }
+
+    /// Number of rows waiting in the queue (claimed or not)
+    pub async fn depth(&self) -> Result<i64> {
+        let row = self.database.query_one("SELECT count(*) FROM dirty_tiles", &[]).await?;
+        Ok(row.get(0))
+    }
+}
@@ -0,0 +1,112 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+use crate::TileCoord;
+use super::DatabasePool;
+
+/// Postgres rejects NOTIFY payloads of 8000 bytes or more
+const MAX_PAYLOAD_BYTES: usize = 7_500;
+
+/// Tiles committed to the archive, published on the updates channel
+/// (large commits are split over several notifications)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TileUpdate {
+    pub committed_at: DateTime<Utc>,
+    /// Tiles as "z/x/y"
+    pub tiles: Vec<String>,
+}
+
+impl TileUpdate {
+    /// Split a commit into JSON payloads that each fit in one notification
+    pub fn payloads(tiles: &[TileCoord], committed_at: DateTime<Utc>) -> Result<Vec<String>> {
+        // Fixed part of the payload with an empty tile list
+        let overhead = serde_json::to_string(&TileUpdate { committed_at, tiles: Vec::new() })?.len();
+        let mut payloads = Vec::new();
+        let mut current = Vec::new();
+        let mut size = overhead;
+
+        for tile in tiles {
+            let tile = tile.to_string();
+            // Quotes plus separating comma
+            let tile_size = tile.len() + 3;
+
+            if size + tile_size > MAX_PAYLOAD_BYTES && !current.is_empty() {
+                payloads.push(serde_json::to_string(&TileUpdate { committed_at, tiles: std::mem::take(&mut current) })?);
+                size = overhead;
+            }
+
+            size += tile_size;
+            current.push(tile);
+        }
+
+        if !current.is_empty() {
+            payloads.push(serde_json::to_string(&TileUpdate { committed_at, tiles: current })?);
+        }
+
+        Ok(payloads)
+    }
+
+    /// Parse a notification payload
+    pub fn parse(payload: &str) -> Result<Self> {
+        serde_json::from_str(payload).context("Invalid tile update payload")
+    }
+
+    /// Updated tiles, skipping any that fail to parse
+    pub fn coords(&self) -> Vec<TileCoord> {
+        self.tiles.iter().filter_map(|tile| tile.parse().ok()).collect()
+    }
+}
+
+/// Publishes committed tiles with NOTIFY so tile servers can push them to clients
+#[derive(Clone)]
+pub struct TileUpdatePublisher {
+    database: DatabasePool,
+    channel: String,
+}
+
+impl TileUpdatePublisher {
+    /// Create a publisher for a notification channel
+    pub fn new(database: DatabasePool, channel: &str) -> Self {
+        Self {
+            database,
+            channel: channel.to_string(),
+        }
+    }
+
+    /// Announce tiles that were just committed to the archive
+    pub async fn publish(&self, tiles: &[TileCoord]) -> Result<()> {
+        let payloads = TileUpdate::payloads(tiles, Utc::now())?;
+
+        for payload in &payloads {
+            self.database.execute("SELECT pg_notify($1, $2)", &[&self.channel, payload])
+                .await
+                .context("Failed to publish tile update")?;
+        }
+
+        debug!("Published {} updated tiles in {} notifications", tiles.len(), payloads.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payloads_fit_in_notifications() {
+        let tiles: Vec<TileCoord> = (0..2_000).map(|x| TileCoord::new(14, 8000 + x, 5425)).collect();
+        let committed_at = Utc::now();
+
+        let payloads = TileUpdate::payloads(&tiles, committed_at).unwrap();
+        assert!(payloads.len() > 1);
+        assert!(payloads.iter().all(|p| p.len() <= MAX_PAYLOAD_BYTES));
+
+        let round_trip: Vec<TileCoord> = payloads.iter()
+            .flat_map(|p| TileUpdate::parse(p).unwrap().coords())
+            .collect();
+        assert_eq!(round_trip, tiles);
+
+        assert!(TileUpdate::payloads(&[], committed_at).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,143 @@
+use std::path::Path;
+use anyhow::{Context, Result};
+use tracing::{info, warn};
+use super::DatabasePool;
+
+/// Session-level Postgres advisory lock electing the single archive writer.
+///
+/// The lock belongs to the worker's database session, so it is released by
+/// Postgres as soon as the holder disconnects or dies, and another worker
+/// takes over on its next `try_acquire`. The holder therefore re-checks the
+/// lock against its live session on every `try_acquire` rather than trusting
+/// that it still has it.
+pub struct WriterLock {
+    database: DatabasePool,
+    key: i64,
+    held: bool,
+}
+
+impl WriterLock {
+    /// Create a lock handle keyed on the archive path
+    pub fn new(database: DatabasePool, archive_path: &Path) -> Self {
+        Self {
+            database,
+            key: lock_key("jvt-writer", archive_path),
+            held: false,
+        }
+    }
+
+    /// Try to become (or confirm this worker still is) the writer; returns
+    /// whether this session holds the lock. Any database error drops the role.
+    pub async fn try_acquire(&mut self) -> Result<bool> {
+        let was_held = std::mem::take(&mut self.held);
+
+        // Advisory locks are re-entrant, so only take it again if the session lost it
+        if was_held {
+            let row = self.database.query_one(
+                "SELECT EXISTS (
+                     SELECT 1 FROM pg_locks
+                     WHERE locktype = 'advisory' AND granted AND objsubid = 1
+                       AND pid = pg_backend_pid() AND ((classid::bigint << 32) | objid::bigint) = $1
+                 )",
+                &[&self.key],
+            )
+            .await
+            .context("Failed to check writer advisory lock")?;
+
+            if row.get(0) {
+                self.held = true;
+                return Ok(true);
+            }
+            warn!("Writer lock {} is no longer held by this session (reconnected?)", self.key);
+        }
+
+        let row = self.database.query_one("SELECT pg_try_advisory_lock($1)", &[&self.key])
+            .await
+            .context("Failed to try writer advisory lock")?;
+        self.held = row.get(0);
+
+        if self.held {
+            info!("Acquired writer lock {} - this worker now commits to the archive", self.key);
+        }
+
+        Ok(self.held)
+    }
+
+    /// Give up the writer role
+    pub async fn release(&mut self) -> Result<()> {
+        if !std::mem::take(&mut self.held) {
+            return Ok(());
+        }
+
+        let row = self.database.query_one("SELECT pg_advisory_unlock($1)", &[&self.key])
+            .await
+            .context("Failed to release writer advisory lock")?;
+
+        if !row.get::<_, bool>(0) {
+            warn!("Writer lock {} was not held by this session", self.key);
+        }
+
+        Ok(())
+    }
+
+    /// Describe the session holding the lock, if any (for conflict logs)
+    pub async fn holder(&self) -> Result<Option<String>> {
+        // Advisory bigint keys are split into classid (high) and objid (low) halves
+        let rows = self.database.query(
+            "SELECT a.pid, a.application_name, a.client_addr::text, a.backend_start
+             FROM pg_locks l JOIN pg_stat_activity a ON a.pid = l.pid
+             WHERE l.locktype = 'advisory' AND l.granted AND l.objsubid = 1
+               AND ((l.classid::bigint << 32) | l.objid::bigint) = $1",
+            &[&self.key],
+        )
+        .await
+        .context("Failed to look up writer lock holder")?;
+
+        Ok(rows.first().map(|row| {
+            let started: chrono::DateTime<chrono::Utc> = row.get(3);
+            format!(
+                "backend pid {} ({}) from {}, connected since {}",
+                row.get::<_, i32>(0),
+                row.get::<_, Option<String>>(1).filter(|s| !s.is_empty()).unwrap_or_else(|| "unnamed".to_string()),
+                row.get::<_, Option<String>>(2).unwrap_or_else(|| "local socket".to_string()),
+                started.format("%Y-%m-%d %H:%M:%S UTC"),
+            )
+        }))
+    }
+
+    /// Whether this worker currently holds the writer role
+    pub fn is_held(&self) -> bool {
+        self.held
+    }
+
+    /// Advisory lock key used by this handle
+    pub fn key(&self) -> i64 {
+        self.key
+    }
+}
+
+/// Stable 64-bit advisory lock key for a namespace and path (FNV-1a)
+pub fn lock_key(namespace: &str, path: &Path) -> i64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+    for byte in namespace.bytes().chain([0]).chain(path.to_string_lossy().bytes()) {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    hash as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_key_is_stable() {
+        let path = Path::new("/var/lib/pmtiles/planet.pmtiles");
+
+        assert_eq!(lock_key("jvt-writer", path), lock_key("jvt-writer", path));
+        assert_ne!(lock_key("jvt-writer", path), lock_key("jvt-writer", Path::new("/tmp/other.pmtiles")));
+        assert_ne!(lock_key("jvt-writer", path), lock_key("jvt-archive", path));
+    }
+}
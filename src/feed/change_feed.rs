@@ -0,0 +1,107 @@
+use std::path::PathBuf;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+use crate::{Config, TileCoord};
+use crate::worker::tile_batch::ReplicationInfo;
+use super::{ChangeManifest, ManifestLog, Webhook};
+
+/// Manifests waiting for webhook delivery before new ones are dropped
+const WEBHOOK_QUEUE: usize = 1_000;
+
+/// Emits a change manifest for every archive commit so CDNs can purge
+/// exactly the affected tile URLs.
+///
+/// Manifests are appended to a rotating JSON lines file before `emit`
+/// returns; webhook deliveries run in order on a background task so a slow
+/// or unreachable endpoint never holds up the worker. Deliveries are not
+/// persisted: manifests still queued when the worker stops, or dropped after
+/// the last retry, are lost to the webhook (the file, if set, still has them
+/// until it rotates).
+pub struct ChangeFeed {
+    archive_path: PathBuf,
+    log: Option<ManifestLog>,
+    webhook: Option<mpsc::Sender<(i64, Vec<u8>)>>,
+}
+
+impl ChangeFeed {
+    /// The configured feed, or None when neither a file nor a webhook is set
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        let feed = &config.change_feed;
+        if feed.path.is_none() && feed.webhook_url.is_none() {
+            return Ok(None);
+        }
+
+        let log = feed.path.clone().map(|path| {
+            info!("Writing change manifests to {}", path.display());
+            ManifestLog::new(path, feed.max_file_bytes, feed.keep_files)
+        });
+        let webhook = match &feed.webhook_url {
+            Some(url) => {
+                info!("Posting change manifests to {}", url);
+                if log.is_none() {
+                    warn!("CHANGE_FEED_PATH is not set; manifests the webhook fails to deliver are lost");
+                }
+                Some(Self::spawn_delivery(Webhook::new(url, feed.webhook_secret.clone(), feed.webhook_retries)?))
+            }
+            None => None,
+        };
+
+        Ok(Some(Self { archive_path: config.files.pmtiles_archive_path.clone(), log, webhook }))
+    }
+
+    /// Deliver queued manifests one at a time, in batch order
+    fn spawn_delivery(webhook: Webhook) -> mpsc::Sender<(i64, Vec<u8>)> {
+        let (sender, mut receiver) = mpsc::channel::<(i64, Vec<u8>)>(WEBHOOK_QUEUE);
+        tokio::spawn(async move {
+            while let Some((batch_id, body)) = receiver.recv().await {
+                match webhook.deliver(batch_id, &body).await {
+                    Ok(()) => debug!("Delivered change manifest for batch {}", batch_id),
+                    Err(e) => warn!("Dropping change manifest for batch {}: {:#}", batch_id, e),
+                }
+            }
+        });
+        sender
+    }
+
+    /// Publish the manifest of tiles just committed and recorded; failures
+    /// are logged since the tiles are already in the archive
+    pub async fn emit<'a>(
+        &self,
+        batch_id: i64,
+        committed_at: DateTime<Utc>,
+        replication: &ReplicationInfo,
+        tiles: impl IntoIterator<Item = &'a TileCoord>,
+    ) {
+        let manifest = match ChangeManifest::build(batch_id, committed_at, replication, tiles, &self.archive_path).await {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                warn!("Failed to build change manifest for batch {}: {:#}", batch_id, e);
+                return;
+            }
+        };
+        let body = match serde_json::to_vec(&manifest) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Failed to serialize change manifest for batch {}: {}", batch_id, e);
+                return;
+            }
+        };
+
+        if let Some(log) = &self.log {
+            let (log, line) = (log.clone(), body.clone());
+            match tokio::task::spawn_blocking(move || log.append(&line)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!("Failed to write change manifest for batch {}: {:#}", batch_id, e),
+                Err(e) => warn!("Failed to write change manifest for batch {}: {}", batch_id, e),
+            }
+        }
+        if let Some(webhook) = &self.webhook
+            && webhook.try_send((batch_id, body)).is_err()
+        {
+            warn!("Webhook queue is full; dropping change manifest for batch {} from delivery", batch_id);
+        }
+        debug!("Emitted change manifest for batch {} ({} tiles)", batch_id, manifest.tiles.len());
+    }
+}
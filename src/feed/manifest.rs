@@ -0,0 +1,176 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use pmtiles::TileId;
+use serde::{Deserialize, Serialize};
+use crate::TileCoord;
+use crate::server::tiles::etag_digest;
+use crate::tiles::pmtiles_writer::open_archive;
+use crate::worker::tile_batch::ReplicationInfo;
+
+/// Tiles committed by one archive commit, with the ETags `jvt serve` now returns for them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChangeManifest {
+    /// `changed_tile_batches` id
+    pub batch_id: i64,
+    pub committed_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replication_sequence: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replication_timestamp: Option<DateTime<Utc>>,
+    pub tiles: Vec<ChangedTile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangedTile {
+    pub z: u8,
+    pub x: u32,
+    pub y: u32,
+    /// Unquoted ETag; None when the archive no longer has the tile
+    pub etag: Option<String>,
+}
+
+impl ChangeManifest {
+    /// Manifest for committed tiles, reading their ETags from the archive
+    pub async fn build<'a>(
+        batch_id: i64,
+        committed_at: DateTime<Utc>,
+        replication: &ReplicationInfo,
+        tiles: impl IntoIterator<Item = &'a TileCoord>,
+        archive: &Path,
+    ) -> Result<Self> {
+        let reader = open_archive(archive).await?;
+        let mut coords: Vec<&TileCoord> = tiles.into_iter().collect();
+        coords.sort();
+
+        let mut changed = Vec::with_capacity(coords.len());
+        for coord in coords {
+            let tile_id = coord.to_tile_id().ok().and_then(TileId::new).ok_or_else(|| anyhow::anyhow!("Invalid tile {}", coord))?;
+            let etag = reader.get_tile(tile_id).await?
+                .filter(|data| !data.is_empty())
+                .map(|data| etag_digest(&data));
+            changed.push(ChangedTile { z: coord.z, x: coord.x, y: coord.y, etag });
+        }
+
+        Ok(Self {
+            batch_id,
+            committed_at,
+            replication_sequence: replication.sequence,
+            replication_timestamp: replication.timestamp,
+            tiles: changed,
+        })
+    }
+}
+
+/// Append-only JSON lines file that rotates by size, keeping `<path>.1`
+/// (newest) to `<path>.<keep>`. Appends block on `sync_data`, so call them
+/// from a blocking task.
+#[derive(Debug, Clone)]
+pub struct ManifestLog {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+}
+
+impl ManifestLog {
+    pub fn new(path: PathBuf, max_bytes: u64, keep: usize) -> Self {
+        Self { path, max_bytes, keep }
+    }
+
+    /// Append one line, rotating first if it would push the file past the limit
+    pub fn append(&self, line: &[u8]) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        let size = std::fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+        if size > 0 && size + line.len() as u64 + 1 > self.max_bytes {
+            self.rotate()?;
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)
+            .with_context(|| format!("Failed to open {}", self.path.display()))?;
+        file.write_all(line)?;
+        file.write_all(b"\n")?;
+        file.sync_data().with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+
+    fn rotate(&self) -> Result<()> {
+        if self.keep == 0 {
+            return File::create(&self.path).map(|_| ())
+                .with_context(|| format!("Failed to truncate {}", self.path.display()));
+        }
+
+        for n in (1..self.keep).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                std::fs::rename(&from, self.rotated(n + 1))
+                    .with_context(|| format!("Failed to rotate {}", from.display()))?;
+            }
+        }
+        std::fs::rename(&self.path, self.rotated(1))
+            .with_context(|| format!("Failed to rotate {}", self.path.display()))
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+    use crate::tiles::TilesetMetadata;
+    use crate::tiles::pmtiles_writer::ArchiveBuilder;
+
+    #[tokio::test]
+    async fn test_manifest_etags() {
+        let archive = std::env::temp_dir().join("test_change_manifest.pmtiles");
+        let mut builder = ArchiveBuilder::create(&archive, &TilesetMetadata::from_config(&Config::default())).unwrap();
+        builder.add(TileCoord::new(1, 1, 0).to_tile_id().unwrap(), b"tile").unwrap();
+        builder.finish().unwrap();
+
+        let (present, gone) = (TileCoord::new(1, 1, 0), TileCoord::new(1, 0, 0));
+        let replication = ReplicationInfo { sequence: Some(42), timestamp: None };
+        let manifest = ChangeManifest::build(7, Utc::now(), &replication, [&present, &gone], &archive).await.unwrap();
+
+        // Sorted, and the ETag is the one served for the stored bytes
+        let stored = open_archive(&archive).await.unwrap()
+            .get_tile(TileId::new(present.to_tile_id().unwrap()).unwrap()).await.unwrap().unwrap();
+        assert_eq!(manifest.tiles, vec![
+            ChangedTile { z: 1, x: 0, y: 0, etag: None },
+            ChangedTile { z: 1, x: 1, y: 0, etag: Some(etag_digest(&stored)) },
+        ]);
+
+        let json = serde_json::to_value(&manifest).unwrap();
+        assert_eq!((json["batch_id"].as_i64(), json["replication_sequence"].as_i64()), (Some(7), Some(42)));
+        assert!(json.get("replication_timestamp").is_none());
+
+        std::fs::remove_file(archive).ok();
+    }
+
+    #[test]
+    fn test_log_rotation() {
+        let dir = std::env::temp_dir().join("test_manifest_log");
+        std::fs::remove_dir_all(&dir).ok();
+        let log = ManifestLog::new(dir.join("changes.jsonl"), 6, 2);
+
+        for line in ["one", "two", "three", "four"] {
+            log.append(line.as_bytes()).unwrap();
+        }
+
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("changes.jsonl"), "four\n");
+        assert_eq!(read("changes.jsonl.1"), "three\n");
+        assert_eq!(read("changes.jsonl.2"), "two\n");
+        assert!(!dir.join("changes.jsonl.3").exists());
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
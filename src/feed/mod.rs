@@ -0,0 +1,7 @@
+pub mod change_feed;
+pub mod manifest;
+pub mod webhook;
+
+pub use change_feed::ChangeFeed;
+pub use manifest::{ChangeManifest, ChangedTile, ManifestLog};
+pub use webhook::Webhook;
@@ -0,0 +1,145 @@
+use std::time::Duration;
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use reqwest::StatusCode;
+use sha2::Sha256;
+use tracing::warn;
+
+/// Header carrying `sha256=<hex HMAC-SHA256 of the body>`
+pub const SIGNATURE_HEADER: &str = "X-Jvt-Signature";
+
+/// Header carrying the manifest's batch id
+pub const BATCH_HEADER: &str = "X-Jvt-Batch";
+
+/// Longest wait between delivery attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// POSTs change manifests to an HTTP endpoint. Connection errors, timeouts,
+/// 429 and 5xx responses are retried with exponential backoff; other
+/// responses fail the delivery immediately.
+#[derive(Clone)]
+pub struct Webhook {
+    client: reqwest::Client,
+    url: String,
+    secret: Option<String>,
+    retries: u32,
+    backoff: Duration,
+}
+
+impl Webhook {
+    pub fn new(url: &str, secret: Option<String>, retries: u32) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()
+            .context("Failed to create webhook client")?;
+
+        Ok(Self { client, url: url.to_string(), secret, retries, backoff: Duration::from_secs(1) })
+    }
+
+    /// Wait before the first retry, doubled for each one after
+    pub fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Deliver one manifest body, retrying until accepted or out of attempts
+    pub async fn deliver(&self, batch_id: i64, body: &[u8]) -> Result<()> {
+        let mut wait = self.backoff;
+        let mut attempts = 0;
+
+        loop {
+            let mut request = self.client.post(&self.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(BATCH_HEADER, batch_id.to_string())
+                .body(body.to_vec());
+            if let Some(secret) = &self.secret {
+                request = request.header(SIGNATURE_HEADER, sign(secret, body));
+            }
+
+            let error = match request.send().await {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) if retryable(response.status()) => anyhow::anyhow!("webhook returned {}", response.status()),
+                Ok(response) => anyhow::bail!("Webhook rejected batch {} with {}", batch_id, response.status()),
+                Err(e) => anyhow::Error::from(e),
+            };
+
+            attempts += 1;
+            if attempts > self.retries {
+                return Err(error.context(format!("Failed to deliver batch {} after {} attempts", batch_id, attempts)));
+            }
+            warn!("Delivering batch {} to webhook failed (attempt {}): {}; retrying in {:?}", batch_id, attempts, error, wait);
+            tokio::time::sleep(wait).await;
+            wait = (wait * 2).min(MAX_BACKOFF);
+        }
+    }
+}
+
+/// `sha256=<hex>` signature of a body
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+fn retryable(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use axum::Router;
+    use axum::extract::State;
+    use axum::http::HeaderMap;
+    use axum::routing::post;
+
+    type Received = Arc<Mutex<Vec<(HeaderMap, Vec<u8>)>>>;
+
+    /// Local endpoint answering with `statuses` in turn, recording each request
+    async fn stand_in(statuses: Vec<u16>) -> (String, Received) {
+        let received: Received = Arc::default();
+        let handler = |State((received, statuses)): State<(Received, Arc<Vec<u16>>)>, headers: HeaderMap, body: axum::body::Bytes| async move {
+            let mut received = received.lock().unwrap();
+            received.push((headers, body.to_vec()));
+            let status = statuses.get(received.len() - 1).copied().unwrap_or(200);
+            axum::http::StatusCode::from_u16(status).unwrap()
+        };
+        let app = Router::new().route("/purge", post(handler)).with_state((received.clone(), Arc::new(statuses)));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/purge", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, received)
+    }
+
+    #[tokio::test]
+    async fn test_signed_delivery_with_retries() {
+        let (url, received) = stand_in(vec![503, 429, 200]).await;
+        let webhook = Webhook::new(&url, Some("secret".to_string()), 3).unwrap().with_backoff(Duration::from_millis(1));
+
+        webhook.deliver(12, br#"{"batch_id":12}"#).await.unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        let (headers, body) = &received[2];
+        assert_eq!(body, br#"{"batch_id":12}"#);
+        assert_eq!(headers[BATCH_HEADER], "12");
+        assert_eq!(headers[SIGNATURE_HEADER].to_str().unwrap(), sign("secret", body));
+        assert!(sign("other", body) != sign("secret", body));
+    }
+
+    #[tokio::test]
+    async fn test_delivery_gives_up() {
+        let (url, received) = stand_in(vec![400]).await;
+        let webhook = Webhook::new(&url, None, 3).unwrap().with_backoff(Duration::from_millis(1));
+        assert!(webhook.deliver(1, b"{}").await.is_err());
+        assert_eq!(received.lock().unwrap().len(), 1);
+        assert!(received.lock().unwrap()[0].0.get(SIGNATURE_HEADER).is_none());
+
+        let (url, received) = stand_in(vec![500; 5]).await;
+        let webhook = Webhook::new(&url, None, 2).unwrap().with_backoff(Duration::from_millis(1));
+        assert!(webhook.deliver(1, b"{}").await.is_err());
+        assert_eq!(received.lock().unwrap().len(), 3);
+    }
+}
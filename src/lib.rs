@@ -1,11 +1,22 @@
 pub mod config;
 pub mod database;
+pub mod feed;
+pub mod metrics;
+pub mod replication;
+pub mod rerender;
+pub mod seed;
+pub mod server;
 pub mod tiles;
 pub mod worker;
 
 // Re-export common types
 pub use config::Config;
 
+use tiles::mercator::{self, BBox};
+
+/// Highest zoom level a `TileCoord` can address
+pub const MAX_ZOOM: u8 = 30;
+
 /// Core tile coordinate representation  
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct TileCoord {
@@ -15,12 +26,240 @@ pub struct TileCoord {
 }
 
 impl TileCoord {
+    /// Create a coordinate without bounds checking.
+    /// Use `try_new` for values from untrusted input.
     pub fn new(z: u8, x: u32, y: u32) -> Self {
         Self { z, x, y }
     }
-    
+
+    /// Create a coordinate, validating z <= 30 and x, y < 2^z
+    pub fn try_new(z: u8, x: u32, y: u32) -> Result<Self, String> {
+        if z > MAX_ZOOM {
+            return Err(format!("Zoom level {} exceeds maximum of {}", z, MAX_ZOOM));
+        }
+
+        let size = 1u64 << z;
+        if u64::from(x) >= size || u64::from(y) >= size {
+            return Err(format!("Tile {}/{}/{} is outside the zoom {} grid", z, x, y, z));
+        }
+
+        Ok(Self::new(z, x, y))
+    }
+
+    /// The tile containing a lon/lat point at the given zoom
+    pub fn from_lonlat(lon: f64, lat: f64, z: u8) -> Result<Self, String> {
+        if z > MAX_ZOOM {
+            return Err(format!("Zoom level {} exceeds maximum of {}", z, MAX_ZOOM));
+        }
+
+        let max = (1u64 << z) - 1;
+        let x = (mercator::lon_to_tile_x(lon, z).floor().max(0.0) as u64).min(max);
+        let y = (mercator::lat_to_tile_y(lat, z).floor().max(0.0) as u64).min(max);
+
+        Ok(Self::new(z, x as u32, y as u32))
+    }
+
+    /// Bounding box in lon/lat degrees
+    pub fn bounds_lonlat(&self) -> BBox {
+        BBox::new(
+            mercator::tile_x_to_lon(f64::from(self.x), self.z),
+            mercator::tile_y_to_lat(f64::from(self.y) + 1.0, self.z),
+            mercator::tile_x_to_lon(f64::from(self.x) + 1.0, self.z),
+            mercator::tile_y_to_lat(f64::from(self.y), self.z),
+        )
+    }
+
+    /// Bounding box in EPSG:3857 meters
+    pub fn bounds_mercator(&self) -> BBox {
+        let size = 2.0 * mercator::ORIGIN_SHIFT / mercator::tiles_per_side(self.z);
+        let min_x = -mercator::ORIGIN_SHIFT + f64::from(self.x) * size;
+        let max_y = mercator::ORIGIN_SHIFT - f64::from(self.y) * size;
+        BBox::new(min_x, max_y - size, min_x + size, max_y)
+    }
+
+    /// The tile one zoom level up that contains this tile
+    pub fn parent(&self) -> Option<TileCoord> {
+        (self.z > 0).then(|| TileCoord::new(self.z - 1, self.x / 2, self.y / 2))
+    }
+
+    /// The four tiles one zoom level down (empty at the maximum zoom)
+    pub fn children(&self) -> Vec<TileCoord> {
+        if self.z >= MAX_ZOOM {
+            return Vec::new();
+        }
+
+        let (z, x, y) = (self.z + 1, self.x * 2, self.y * 2);
+        vec![
+            TileCoord::new(z, x, y),
+            TileCoord::new(z, x + 1, y),
+            TileCoord::new(z, x, y + 1),
+            TileCoord::new(z, x + 1, y + 1),
+        ]
+    }
+
+    /// The up to eight surrounding tiles at the same zoom.
+    /// Columns wrap around the antimeridian; rows stop at the poles.
+    pub fn neighbours(&self) -> Vec<TileCoord> {
+        let size = 1i64 << self.z;
+        let mut result = Vec::with_capacity(8);
+
+        for dy in -1i64..=1 {
+            for dx in -1i64..=1 {
+                if dx == 0 && dy == 0 {
+                    continue;
+                }
+
+                let y = i64::from(self.y) + dy;
+                if y < 0 || y >= size {
+                    continue;
+                }
+
+                let x = (i64::from(self.x) + dx).rem_euclid(size);
+                let neighbour = TileCoord::new(self.z, x as u32, y as u32);
+                if neighbour != *self && !result.contains(&neighbour) {
+                    result.push(neighbour);
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Whether `other` is this tile or one of its descendants
+    pub fn contains(&self, other: &TileCoord) -> bool {
+        if other.z < self.z {
+            return false;
+        }
+
+        // Zoom gaps of 32 or more only arise from unchecked coordinates
+        let shift = u32::from(other.z - self.z);
+        other.x.checked_shr(shift) == Some(self.x) && other.y.checked_shr(shift) == Some(self.y)
+    }
+
+    /// Whether a lon/lat point falls inside this tile
+    pub fn contains_lonlat(&self, lon: f64, lat: f64) -> bool {
+        self.bounds_lonlat().contains_point(lon, lat)
+    }
+
+    /// Iterate all tiles at `z` that intersect a lon/lat bounding box
+    pub fn tiles_in_bbox(bbox: &BBox, z: u8) -> Result<TileRange, String> {
+        let top_left = TileCoord::from_lonlat(bbox.min_x, bbox.max_y, z)?;
+        let bottom_right = TileCoord::from_lonlat(bbox.max_x, bbox.min_y, z)?;
+
+        Ok(TileRange::new(z, top_left.x, top_left.y, bottom_right.x, bottom_right.y))
+    }
+}
+
+/// Conversions between addressing schemes used by other tools
+impl TileCoord {
+    /// Bing Maps quadkey, one base-4 digit per zoom level ("" for zoom 0)
+    pub fn to_quadkey(&self) -> String {
+        (1..=self.z)
+            .rev()
+            .map(|i| {
+                let mask = 1u32 << (i - 1);
+                let digit = u8::from(self.x & mask != 0) + 2 * u8::from(self.y & mask != 0);
+                char::from(b'0' + digit)
+            })
+            .collect()
+    }
+
+    /// Parse a Bing Maps quadkey
+    pub fn from_quadkey(quadkey: &str) -> Result<Self, String> {
+        let quadkey = quadkey.trim();
+        if quadkey.len() > usize::from(MAX_ZOOM) {
+            return Err(format!("Quadkey too long: {}", quadkey));
+        }
+
+        let (mut x, mut y) = (0u32, 0u32);
+        for c in quadkey.chars() {
+            let digit = c.to_digit(4)
+                .ok_or_else(|| format!("Invalid quadkey digit '{}' in {}", c, quadkey))?;
+            x = (x << 1) | (digit & 1);
+            y = (y << 1) | (digit >> 1);
+        }
+
+        TileCoord::try_new(quadkey.len() as u8, x, y)
+    }
+
+    /// Row index in the TMS scheme, where y grows northwards
+    pub fn tms_y(&self) -> u32 {
+        ((1u64 << self.z) - 1 - u64::from(self.y)) as u32
+    }
+
+    /// Create from TMS coordinates (y flipped relative to XYZ)
+    pub fn from_tms(z: u8, x: u32, tms_y: u32) -> Result<Self, String> {
+        let coord = TileCoord::try_new(z, x, tms_y)?;
+        Ok(TileCoord::new(z, x, coord.tms_y()))
+    }
+
+    /// PMTiles v3 tile ID (position along the per-zoom Hilbert curve);
+    /// fails for coordinates outside the grid
+    pub fn to_tile_id(&self) -> Result<u64, String> {
+        let coord = pmtiles::TileCoord::new(self.z, self.x, self.y)
+            .ok_or_else(|| format!("Tile {} is outside the PMTiles grid", self))?;
+        Ok(pmtiles::TileId::from(coord).value())
+    }
+
+    /// Create from a PMTiles v3 tile ID
+    pub fn from_tile_id(tile_id: u64) -> Result<Self, String> {
+        let tile_id = pmtiles::TileId::new(tile_id)
+            .ok_or_else(|| format!("Invalid PMTiles tile id: {}", tile_id))?;
+        let coord = pmtiles::TileCoord::from(tile_id);
+        TileCoord::try_new(coord.z(), coord.x(), coord.y())
+    }
+}
+
+/// Iterator over a rectangular range of tiles at a single zoom level, row by row
+#[derive(Debug, Clone)]
+pub struct TileRange {
+    pub z: u8,
+    pub min_x: u32,
+    pub min_y: u32,
+    pub max_x: u32,
+    pub max_y: u32,
+    next_x: u32,
+    next_y: u32,
+}
+
+impl TileRange {
+    /// Create an inclusive range of tile columns and rows
+    pub fn new(z: u8, min_x: u32, min_y: u32, max_x: u32, max_y: u32) -> Self {
+        Self { z, min_x, min_y, max_x, max_y, next_x: min_x, next_y: min_y }
+    }
+
+    /// Total number of tiles covered by the range (0 if it is inverted)
+    pub fn tile_count(&self) -> u64 {
+        let span = |min: u32, max: u32| max.checked_sub(min).map_or(0, |d| u64::from(d) + 1);
+        span(self.min_x, self.max_x) * span(self.min_y, self.max_y)
+    }
+}
+
+impl Iterator for TileRange {
+    type Item = TileCoord;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_y > self.max_y || self.min_x > self.max_x {
+            return None;
+        }
+
+        let coord = TileCoord::new(self.z, self.next_x, self.next_y);
+        if self.next_x == self.max_x {
+            self.next_x = self.min_x;
+            self.next_y += 1;
+        } else {
+            self.next_x += 1;
+        }
+
+        Some(coord)
+    }
+}
+
+impl std::str::FromStr for TileCoord {
+    type Err = String;
+
     /// Parse from "z/x/y" format used in dirty_tiles.txt
-    pub fn from_str(s: &str) -> Result<Self, String> {
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
         let parts: Vec<&str> = s.trim().split('/').collect();
         if parts.len() != 3 {
             return Err(format!("Invalid tile coordinate format: {}", s));
@@ -33,18 +272,21 @@ impl TileCoord {
         let y = parts[2].parse::<u32>()
             .map_err(|_| format!("Invalid y coordinate: {}", parts[2]))?;
             
-        Ok(TileCoord::new(z, x, y))
+        TileCoord::try_new(z, x, y)
     }
-    
-    /// Format as "z/x/y" string
-    pub fn to_string(&self) -> String {
-        format!("{}/{}/{}", self.z, self.x, self.y)
+}
+
+/// Format as "z/x/y" string
+impl std::fmt::Display for TileCoord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}/{}", self.z, self.x, self.y)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::str::FromStr;
 
     #[test]
     fn test_tile_coord_parsing() {
@@ -61,4 +303,154 @@ mod tests {
         assert!(TileCoord::from_str("14/8234").is_err());
         assert!(TileCoord::from_str("14/8234/5425/extra").is_err());
     }
+
+    #[test]
+    fn test_tile_coord_bounds_validation() {
+        assert!(TileCoord::from_str("3/999/999").is_err());
+        assert!(TileCoord::from_str("255/0/0").is_err());
+        assert!(TileCoord::from_str("31/0/0").is_err());
+        assert!(TileCoord::from_str("3/7/7").is_ok());
+        assert!(TileCoord::from_str("3/8/0").is_err());
+        assert!(TileCoord::try_new(30, (1 << 30) - 1, 0).is_ok());
+    }
+
+    #[test]
+    fn test_tile_bounds() {
+        let world = TileCoord::new(0, 0, 0).bounds_lonlat();
+        assert!((world.min_x + 180.0).abs() < 1e-9);
+        assert!((world.max_x - 180.0).abs() < 1e-9);
+        assert!((world.max_y - mercator::MAX_LATITUDE).abs() < 1e-9);
+
+        let merc = TileCoord::new(1, 1, 0).bounds_mercator();
+        assert!(merc.min_x.abs() < 1e-6);
+        assert!(merc.min_y.abs() < 1e-6);
+        assert!((merc.max_x - mercator::ORIGIN_SHIFT).abs() < 1e-6);
+
+        // Oslo
+        let coord = TileCoord::from_lonlat(10.75, 59.91, 14).unwrap();
+        assert_eq!(coord, TileCoord::new(14, 8681, 4766));
+        assert!(coord.contains_lonlat(10.75, 59.91));
+        assert!(!coord.contains_lonlat(11.75, 59.91));
+    }
+
+    #[test]
+    fn test_tile_hierarchy() {
+        let coord = TileCoord::new(14, 8234, 5425);
+        let parent = coord.parent().unwrap();
+        assert_eq!(parent, TileCoord::new(13, 4117, 2712));
+        assert!(parent.contains(&coord));
+        assert!(coord.contains(&coord));
+        assert!(!coord.contains(&parent));
+        assert!(!TileCoord::new(0, 0, 0).contains(&TileCoord::new(40, 0, 0)));
+        assert!(TileCoord::new(0, 0, 0).parent().is_none());
+
+        let children = coord.children();
+        assert_eq!(children.len(), 4);
+        assert!(children.iter().all(|child| coord.contains(child)));
+        assert!(children.iter().all(|child| child.parent().as_ref() == Some(&coord)));
+        assert!(TileCoord::new(MAX_ZOOM, 0, 0).children().is_empty());
+    }
+
+    #[test]
+    fn test_tile_neighbours() {
+        assert_eq!(TileCoord::new(14, 8234, 5425).neighbours().len(), 8);
+
+        // Top row: no neighbours above, columns wrap across the antimeridian
+        let edge = TileCoord::new(2, 0, 0).neighbours();
+        assert_eq!(edge.len(), 5);
+        assert!(edge.contains(&TileCoord::new(2, 3, 0)));
+        assert!(edge.contains(&TileCoord::new(2, 3, 1)));
+
+        assert!(TileCoord::new(0, 0, 0).neighbours().is_empty());
+        assert_eq!(TileCoord::new(1, 0, 0).neighbours().len(), 3);
+    }
+
+    #[test]
+    fn test_quadkey_conversion() {
+        // Examples from the Bing Maps tile system documentation
+        let coord = TileCoord::new(3, 3, 5);
+        assert_eq!(coord.to_quadkey(), "213");
+        assert_eq!(TileCoord::from_quadkey("213").unwrap(), coord);
+        assert_eq!(TileCoord::new(0, 0, 0).to_quadkey(), "");
+
+        assert!(TileCoord::from_quadkey("0124").is_err());
+        assert!(TileCoord::from_quadkey(&"0".repeat(31)).is_err());
+    }
+
+    #[test]
+    fn test_tms_conversion() {
+        let coord = TileCoord::new(14, 8234, 5425);
+        assert_eq!(coord.tms_y(), 10958);
+        assert_eq!(TileCoord::from_tms(14, 8234, 10958).unwrap(), coord);
+        assert_eq!(TileCoord::new(0, 0, 0).tms_y(), 0);
+        assert!(TileCoord::from_tms(3, 0, 8).is_err());
+    }
+
+    #[test]
+    fn test_tile_id_conversion() {
+        assert_eq!(TileCoord::new(0, 0, 0).to_tile_id(), Ok(0));
+        assert_eq!(TileCoord::new(1, 0, 0).to_tile_id(), Ok(1));
+        assert_eq!(TileCoord::new(1, 1, 0).to_tile_id(), Ok(4));
+        assert!(TileCoord::new(1, 2, 0).to_tile_id().is_err());
+        assert!(TileCoord::new(40, 0, 0).to_tile_id().is_err());
+        assert_eq!(TileCoord::from_tile_id(4).unwrap(), TileCoord::new(1, 1, 0));
+        assert!(TileCoord::from_tile_id(u64::MAX).is_err());
+    }
+
+    mod proptests {
+        use super::*;
+        use proptest::prelude::*;
+
+        /// Any valid tile at any supported zoom level
+        fn any_tile() -> impl Strategy<Value = TileCoord> {
+            (0..=MAX_ZOOM).prop_flat_map(|z| {
+                let size = 1u32 << z;
+                (Just(z), 0..size, 0..size)
+                    .prop_map(|(z, x, y)| TileCoord::new(z, x, y))
+            })
+        }
+
+        proptest! {
+            #[test]
+            fn quadkey_round_trip(coord in any_tile()) {
+                let quadkey = coord.to_quadkey();
+                prop_assert_eq!(quadkey.len(), usize::from(coord.z));
+                prop_assert_eq!(TileCoord::from_quadkey(&quadkey).unwrap(), coord);
+            }
+
+            #[test]
+            fn tms_round_trip(coord in any_tile()) {
+                let tms = TileCoord::from_tms(coord.z, coord.x, coord.tms_y()).unwrap();
+                prop_assert_eq!(tms, coord);
+            }
+
+            #[test]
+            fn tile_id_round_trip(coord in any_tile()) {
+                prop_assert_eq!(TileCoord::from_tile_id(coord.to_tile_id().unwrap()).unwrap(), coord);
+            }
+
+            #[test]
+            fn string_round_trip(coord in any_tile()) {
+                prop_assert_eq!(TileCoord::from_str(&coord.to_string()).unwrap(), coord);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tiles_in_bbox() {
+        let world: Vec<TileCoord> = TileCoord::tiles_in_bbox(&BBox::world(), 2).unwrap().collect();
+        assert_eq!(world.len(), 16);
+
+        let bbox = BBox::new(10.5, 59.8, 10.9, 60.0);
+        let range = TileCoord::tiles_in_bbox(&bbox, 12).unwrap();
+        let expected = range.tile_count();
+        let tiles: Vec<TileCoord> = range.collect();
+        assert_eq!(tiles.len() as u64, expected);
+        assert!(tiles.iter().all(|t| t.bounds_lonlat().intersects(&bbox)));
+        assert!(tiles.contains(&TileCoord::from_lonlat(10.75, 59.91, 12).unwrap()));
+
+        let inverted = TileRange::new(3, 5, 0, 4, 7);
+        assert_eq!(inverted.tile_count(), 0);
+        assert_eq!(inverted.count(), 0);
+    }
 } 
\ No newline at end of file
@@ -1,23 +1,72 @@
-use anyhow::Result;
-use tracing::{info, error, warn};
+use anyhow::{Context, Result};
+use clap::Parser;
+use tracing::{error, info};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use tokio::time::{sleep, Duration};
 
 use jvt::{Config, TileCoord};
-use jvt::database::{DatabasePool, NotificationListener};
-use jvt::worker::{DirtyTilesProcessor, TileBatch};
+use jvt::database::{AuditLog, DatabasePool, NotificationListener, ReplicationProperties, TileQueue};
+use jvt::replication::ReplicationSupervisor;
+use jvt::rerender::{Area, RerenderTarget, Rerenderer, ZoomRange};
+use jvt::seed::{SeedArea, SeedOptions, Seeder};
+use jvt::server::health::{self, HealthContext};
+use jvt::tiles::{ArchiveLock, DecodedTile, MvtGenerator};
+use jvt::tiles::archive_diff::diff_archives;
+use jvt::tiles::dir_export::{DirExportOptions, export_dir, update_dir};
+use jvt::tiles::inspect::diff_layers;
+use jvt::tiles::mbtiles::{export_mbtiles, import_mbtiles};
+use jvt::tiles::mercator::BBox;
+use jvt::tiles::pmtiles_writer::read_tile;
+use jvt::worker::{DirtyTilesProcessor, Worker};
+
+mod cli;
+
+use cli::{Cli, Command, EnqueueArgs, HealthcheckArgs, ReplicateArgs, RerenderArgs, RerenderCommand, SeedArgs, ServeArgs,
+          TileArgs, TileCommand, InspectArgs, ArchiveArgs, ArchiveCommand, ArchiveDiffArgs,
+          ExportArgs, ExportFormat, ImportArgs};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // Initialize tracing/logging
     init_logging()?;
     
-    info!("Starting JVT (Incremental Vector Tiles) worker");
+    let cli = Cli::parse();
     
     // Load configuration from environment
     let config = Config::from_env()?;
     info!("Configuration loaded successfully");
     
+    match cli.command.unwrap_or(Command::Worker) {
+        Command::Worker => run_worker(config).await,
+        Command::Enqueue(args) => run_enqueue(config, args).await,
+        Command::Replicate(args) => run_replicate(config, args).await,
+        Command::Serve(args) => run_serve(config, args).await,
+        Command::Healthcheck(args) => run_healthcheck(config, args).await,
+        Command::Rerender(args) => run_rerender(config, args).await,
+        Command::Seed(args) => run_seed(config, args).await,
+        Command::Tile(TileArgs { command: TileCommand::Inspect(args) }) => run_inspect(config, args).await,
+        Command::Archive(ArchiveArgs { command: ArchiveCommand::Diff(args) }) => run_archive_diff(args).await,
+        Command::Export(args) => run_export(config, args).await,
+        Command::Import(args) => run_import(config, args).await,
+    }
+}
+
+/// Initialize structured logging
+fn init_logging() -> Result<()> {
+    let env_filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+    
+    tracing_subscriber::registry()
+        .with(env_filter)
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+    
+    Ok(())
+}
+
+/// Run the tile worker until the process is stopped
+async fn run_worker(config: Config) -> Result<()> {
+    info!("Starting JVT (Incremental Vector Tiles) worker");
+    
     // Initialize database connection
     let database = DatabasePool::new(&config.database.url).await?;
     info!("Database connection established");
@@ -34,108 +83,296 @@ async fn main() -> Result<()> {
     info!("Notification listener initialized for channel: {}", 
           config.database.notification_channel);
     
-    // Create dirty tiles processor
-    let processor = DirtyTilesProcessor::new(config.clone());
-    
     // Main worker loop
-    run_worker_loop(&mut listener, &processor, &config).await?;
+    let mut worker = Worker::new(config.clone(), database.clone())?;
+    spawn_health_server(&config, &worker, database);
+    worker.run(&mut listener).await
+}
+
+/// Run replication updates and render their expired tiles in-process
+async fn run_replicate(mut config: Config, args: ReplicateArgs) -> Result<()> {
+    if let Some(interval) = args.interval {
+        config.replication.interval_secs = interval;
+    }
+    info!("Starting JVT replication (every {}s)", config.replication.interval_secs);
     
+    let database = DatabasePool::new(&config.database.url).await?;
+    database.health_check().await?;
+    
+    let supervisor = ReplicationSupervisor::new(config.clone());
+    let mut worker = Worker::new(config.clone(), database.clone())?;
+    spawn_health_server(&config, &worker, database);
+    worker.run_replication(&supervisor, args.once).await
+}
+
+/// Expose /healthz and /readyz for the worker
+fn spawn_health_server(config: &Config, worker: &Worker, database: DatabasePool) {
+    let context = HealthContext {
+        health: worker.health(),
+        database: Some(database),
+        archive_path: config.files.pmtiles_archive_path.clone(),
+        stuck_after_secs: config.worker.stuck_after_secs,
+    };
+    let addr = config.server.health_addr.clone();
+    
+    tokio::spawn(async move {
+        if let Err(e) = health::serve(addr, context).await {
+            error!("Health endpoints unavailable: {:#}", e);
+        }
+    });
+}
+
+/// Probe a running worker's readiness endpoint
+async fn run_healthcheck(config: Config, args: HealthcheckArgs) -> Result<()> {
+    let url = args.url.unwrap_or_else(|| {
+        let addr = config.server.health_addr.replace("0.0.0.0", "127.0.0.1");
+        format!("http://{}/readyz", addr)
+    });
+    
+    let (code, body) = health::probe(&url).await?;
+    println!("{}", body);
+    
+    if code != 200 {
+        anyhow::bail!("Worker not ready (HTTP {})", code);
+    }
     Ok(())
 }
 
-/// Initialize structured logging
-fn init_logging() -> Result<()> {
-    let env_filter = tracing_subscriber::EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+/// Serve tiles from the archive
+async fn run_serve(mut config: Config, args: ServeArgs) -> Result<()> {
+    if let Some(listen) = args.listen {
+        config.server.listen_addr = listen;
+    }
     
-    tracing_subscriber::registry()
-        .with(env_filter)
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    jvt::server::serve(config).await
+}
+
+/// Load an expire file into the dirty_tiles queue table
+async fn run_enqueue(mut config: Config, args: EnqueueArgs) -> Result<()> {
+    if let Some(format) = args.format {
+        config.files.dirty_tiles_format = format;
+    }
     
+    let database = DatabasePool::new(&config.database.url).await?;
+    let replication = ReplicationProperties::new(database.clone()).read().await?;
+    let queue = TileQueue::new(database, &config.worker.worker_id, config.worker.claim_lease_secs);
+    let processor = DirtyTilesProcessor::new(config);
+    let source = args.source.unwrap_or_else(|| args.file.display().to_string());
+    
+    let mut stream = processor.stream_file(&args.file)?;
+    let mut enqueued = 0;
+    
+    for batch in &mut stream {
+        let tiles: Vec<TileCoord> = batch?.tiles.into_iter().collect();
+        enqueued += queue.enqueue(&tiles, &source, &replication).await?;
+    }
+    
+    info!("Enqueued {} tiles from {} ({})", enqueued, args.file.display(), stream.stats());
     Ok(())
 }
 
-/// Main worker loop - listen for notifications and process tiles
-async fn run_worker_loop(
-    listener: &mut NotificationListener,
-    processor: &DirtyTilesProcessor,
-    config: &Config,
-) -> Result<()> {
-    info!("Starting worker loop (timeout: {}s)", config.worker.batch_timeout_secs);
-    
-    loop {
-        match listener.wait_for_notification(
-            Duration::from_secs(config.worker.batch_timeout_secs)
-        ).await {
-            Ok(Some(notification)) => {
-                info!("Received notification: {} bytes payload", 
-                      notification.payload.len());
-                
-                match process_notification(listener, processor, &notification).await {
-                    Ok(()) => {
-                        info!("Successfully processed notification");
-                    }
-                    Err(e) => {
-                        error!("Failed to process notification: {}", e);
-                        // Continue loop - don't exit on processing errors
-                    }
-                }
-            }
-            Ok(None) => {
-                // Timeout occurred - this is normal
-                debug_worker_status().await;
-            }
-            Err(e) => {
-                error!("Error waiting for notification: {}", e);
-                warn!("Sleeping 10s before retrying...");
-                sleep(Duration::from_secs(10)).await;
-            }
+/// Queue a re-render through the worker pipeline
+async fn run_rerender(config: Config, args: RerenderArgs) -> Result<()> {
+    let target = match args.target {
+        RerenderCommand::Tile { tile } => RerenderTarget::Tile { tile },
+        RerenderCommand::Bbox { bbox, zoom } => RerenderTarget::Bbox { bbox, zoom },
+        RerenderCommand::Geojson { file, zoom } => {
+            let text = std::fs::read_to_string(&file)
+                .with_context(|| format!("Failed to read {}", file.display()))?;
+            let geojson = serde_json::from_str(&text)
+                .with_context(|| format!("Invalid JSON in {}", file.display()))?;
+            RerenderTarget::Polygon { geojson, zoom }
         }
+        RerenderCommand::Relation { id, zoom } => RerenderTarget::Relation { relation: id, zoom },
+    };
+    let requested_by = args.requested_by
+        .or_else(|| std::env::var("USER").ok())
+        .unwrap_or_else(|| "jvt rerender".to_string());
+    
+    let database = DatabasePool::new(&config.database.url).await?;
+    let job = Rerenderer::new(config, database).submit(&target, &requested_by).await?;
+    
+    match &job.file {
+        Some(file) => info!("Queued {} tiles for {} in {}", job.tiles, job.description, file.display()),
+        None => info!("Queued {} tiles for {} in the dirty_tiles queue", job.tiles, job.description),
     }
+    Ok(())
 }
 
-/// Process a single notification
-async fn process_notification(
-    listener: &NotificationListener,
-    processor: &DirtyTilesProcessor,
-    notification: &jvt::database::listener::TileNotification,
-) -> Result<()> {
-    // Parse the notification to get file path
-    let dirty_tiles_file = listener.parse_notification(notification)?;
+/// Render a zoom range into a new archive
+async fn run_seed(config: Config, args: SeedArgs) -> Result<()> {
+    let database = DatabasePool::new(&config.database.url).await?;
     
-    // Validate the file
-    let file_info = processor.validate_file(&dirty_tiles_file)?;
-    info!("Processing dirty tiles file: {}", file_info);
+    let area = if let Some(bbox) = &args.bbox {
+        SeedArea::Bbox(BBox::parse(bbox).map_err(anyhow::Error::msg)?)
+    } else if let Some(file) = &args.geojson {
+        let text = std::fs::read_to_string(file)
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+        let geojson = serde_json::from_str(&text)
+            .with_context(|| format!("Invalid JSON in {}", file.display()))?;
+        let area = Area::from_geojson(&database, &geojson).await?;
+        SeedArea::Polygon { description: "GeoJSON area".to_string(), area }
+    } else if let Some(relation) = args.relation {
+        let area = Area::from_relation(&database, relation).await?;
+        SeedArea::Polygon { description: format!("relation {}", relation), area }
+    } else {
+        SeedArea::Planet
+    };
     
-    // Process the file into a tile batch
-    let batch = processor.process_file(&dirty_tiles_file)?;
+    let options = SeedOptions {
+        output: args.output.unwrap_or_else(|| config.files.pmtiles_archive_path.clone()),
+        zooms: args.zoom.unwrap_or(ZoomRange::new(config.tiles.min_zoom, config.tiles.max_zoom)),
+        prune_zoom: args.prune_zoom,
+        jobs: args.jobs,
+        restart: args.restart,
+        force: args.force,
+        keep_spool: args.keep_spool,
+    };
     
-    if batch.is_empty() {
-        warn!("No valid tiles found in {}", dirty_tiles_file.display());
-        return Ok(());
+    Seeder::new(config, database, area, options).run().await?;
+    Ok(())
+}
+
+/// Decode a tile from the archive and/or a fresh render
+async fn run_inspect(config: Config, args: InspectArgs) -> Result<()> {
+    let coord = args.tile;
+    let archive = args.archive.unwrap_or_else(|| config.files.pmtiles_archive_path.clone());
+    
+    let archived = if args.fresh {
+        None
+    } else {
+        let data = read_tile(&archive, &coord).await?;
+        if data.is_none() && !args.diff {
+            anyhow::bail!("Tile {} is not in {}", coord, archive.display());
+        }
+        Some(DecodedTile::decode(coord.clone(), &data.unwrap_or_default())?)
+    };
+    
+    let fresh = if args.fresh || args.diff {
+        let database = DatabasePool::new(&config.database.url).await?;
+        let data = MvtGenerator::new(database, config).generate_tile(&coord).await?;
+        Some(DecodedTile::decode(coord.clone(), &data)?)
+    } else {
+        None
+    };
+    
+    match (archived, fresh) {
+        (Some(archived), Some(fresh)) => {
+            let (before, after) = (archived.summary(), fresh.summary());
+            println!("Tile {} in {}: {}", coord, archive.display(), before);
+            println!("Tile {} rendered fresh: {}", coord, after);
+            
+            let changes = diff_layers(&before, &after);
+            if archived.tile == fresh.tile {
+                println!("Identical content");
+            } else if changes.is_empty() {
+                println!("Same layers and feature counts, {} -> {} bytes", before.bytes, after.bytes);
+            } else {
+                println!("Changes ({} -> {} bytes):", before.bytes, after.bytes);
+                for change in changes {
+                    println!("  {}", change);
+                }
+            }
+        }
+        (Some(tile), None) | (None, Some(tile)) => {
+            if args.geojson {
+                println!("{}", serde_json::to_string_pretty(&tile.to_geojson()?)?);
+            } else {
+                let source = if args.fresh { "rendered fresh".to_string() } else { format!("in {}", archive.display()) };
+                println!("Tile {} {}: {}", coord, source, tile.summary());
+            }
+        }
+        (None, None) => unreachable!("either the archive or a fresh render is read"),
     }
+    Ok(())
+}
+
+/// Compare two archives
+async fn run_archive_diff(args: ArchiveDiffArgs) -> Result<()> {
+    let diff = diff_archives(&args.before, &args.after, args.layers).await?;
     
-    let summary = batch.summary();
-    info!("Tile batch ready: {}", summary);
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+    } else {
+        print!("{}", diff);
+        if diff.is_identical() {
+            println!("Archives hold identical tiles");
+        }
+    }
+    Ok(())
+}
+
+/// Export the archive to another format
+async fn run_export(config: Config, args: ExportArgs) -> Result<()> {
+    let archive = args.archive.clone().unwrap_or_else(|| config.files.pmtiles_archive_path.clone());
     
-    // TODO: Generate MVT tiles for the batch
-    // TODO: Write to PMTiles archive
-    // TODO: Update audit tables
+    match args.format {
+        ExportFormat::Dir => run_dir_export(config, args, archive).await,
+        ExportFormat::Mbtiles => {
+            if args.gzip || args.zoom.is_some() || args.bbox.is_some() || args.since.is_some() || args.url.is_some() {
+                anyhow::bail!("--gzip, --zoom, --bbox, --since and --url only apply to --format dir");
+            }
+            check_output(&args.output, args.force)?;
+            let tiles = export_mbtiles(&archive, &args.output, &config).await?;
+            info!("Exported {} tiles from {} to {}", tiles, archive.display(), args.output.display());
+            Ok(())
+        }
+    }
+}
+
+/// Export to a tile directory, in full or only the tiles changed since a batch
+async fn run_dir_export(config: Config, args: ExportArgs, archive: std::path::PathBuf) -> Result<()> {
+    let options = DirExportOptions {
+        gzip: args.gzip,
+        zooms: args.zoom,
+        bbox: args.bbox.as_deref().map(BBox::parse).transpose().map_err(anyhow::Error::msg)?,
+        tiles_url: args.url,
+    };
     
-    info!("Processed {} tiles from {}", batch.len(), dirty_tiles_file.display());
+    let Some(since) = args.since else {
+        check_output(&args.output, args.force)?;
+        let stats = export_dir(&archive, &args.output, &options, &config).await?;
+        info!("Exported {} tiles from {} to {}", stats.written, archive.display(), args.output.display());
+        return Ok(());
+    };
     
+    if options != DirExportOptions::default() {
+        anyhow::bail!("--since reuses the options of the full export; drop --gzip, --zoom, --bbox and --url");
+    }
+    let database = DatabasePool::new(&config.database.url).await?;
+    let (latest, tiles) = AuditLog::new(database).changed_since(since).await?;
+    let stats = update_dir(&archive, &args.output, &tiles, &config).await?;
+    info!(
+        "Updated {} with {} changed tiles: {} written, {} removed; pass --since {} next time",
+        args.output.display(), tiles.len(), stats.written, stats.removed, latest.unwrap_or(since).max(since),
+    );
     Ok(())
 }
 
-/// Log worker status during idle periods
-async fn debug_worker_status() {
-    use tracing::debug;
+/// Build an archive from an MBTiles file
+async fn run_import(config: Config, args: ImportArgs) -> Result<()> {
+    let output = args.output.unwrap_or_else(|| config.files.pmtiles_archive_path.clone());
+    check_output(&output, args.force)?;
+    let _lock = ArchiveLock::try_acquire(&output)?.with_context(|| format!(
+        "{} is locked by another jvt instance ({})",
+        output.display(),
+        ArchiveLock::holder(&output).unwrap_or_else(|| "unknown holder".to_string()),
+    ))?;
     
-    debug!("Worker heartbeat - waiting for notifications...");
+    let tiles = tokio::task::spawn_blocking({
+        let (input, output) = (args.input.clone(), output.clone());
+        move || import_mbtiles(&input, &output, &config)
+    })
+    .await??;
     
-    // TODO: Add more detailed status:
-    // - Current PMTiles archive size
-    // - Recent processing stats
-    // - Memory usage
+    info!("Imported {} tiles from {} into {}", tiles, args.input.display(), output.display());
+    Ok(())
+}
+
+/// Refuse to overwrite an existing output unless forced
+fn check_output(path: &std::path::Path, force: bool) -> Result<()> {
+    if path.exists() && !force {
+        anyhow::bail!("{} already exists; pass --force to replace it", path.display());
+    }
+    Ok(())
 }
@@ -0,0 +1,230 @@
+use std::path::Path;
+use std::sync::LazyLock;
+use chrono::{DateTime, Utc};
+use prometheus::{
+    exponential_buckets, Encoder, Gauge, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec,
+    IntGauge, Opts, Registry, TextEncoder,
+};
+use tracing::warn;
+use crate::database::DatabasePool;
+use crate::tiles::pmtiles_writer::archive_stats;
+
+/// Content type of the Prometheus text exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Process-wide metrics, scraped from `/metrics`
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+/// Prometheus collectors for the tile pipeline
+pub struct Metrics {
+    registry: Registry,
+    pub notifications_received: IntCounter,
+    pub tiles_rendered: IntCounterVec,
+    pub tiles_failed: IntCounterVec,
+    pub render_seconds: HistogramVec,
+    pub tile_bytes: HistogramVec,
+    pub batch_seconds: Histogram,
+    pub parse_errors: IntCounter,
+    pub dead_letter_lines: IntCounter,
+    pub archive_bytes: IntGauge,
+    pub archive_tiles: IntGauge,
+    pub db_connected: IntGauge,
+    pub db_queries_in_flight: IntGauge,
+    pub replication_lag_seconds: Gauge,
+    pub pipeline_lag_seconds: Histogram,
+    pub data_timestamp_seconds: Gauge,
+    pub data_age_seconds: Gauge,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("jvt".to_string()), None)
+            .expect("valid metrics namespace");
+
+        let metrics = Self {
+            notifications_received: IntCounter::new(
+                "notifications_received_total", "Dirty tile notifications received",
+            ).unwrap(),
+            tiles_rendered: IntCounterVec::new(
+                Opts::new("tiles_rendered_total", "Tiles rendered, by zoom"), &["zoom"],
+            ).unwrap(),
+            tiles_failed: IntCounterVec::new(
+                Opts::new("tiles_failed_total", "Tiles that failed to render, by zoom"), &["zoom"],
+            ).unwrap(),
+            render_seconds: HistogramVec::new(
+                HistogramOpts::new("tile_render_seconds", "Time to render one tile, by zoom")
+                    .buckets(exponential_buckets(0.001, 4.0, 9).unwrap()),
+                &["zoom"],
+            ).unwrap(),
+            tile_bytes: HistogramVec::new(
+                HistogramOpts::new("tile_bytes", "Rendered tile size in bytes, by zoom")
+                    .buckets(exponential_buckets(256.0, 4.0, 8).unwrap()),
+                &["zoom"],
+            ).unwrap(),
+            batch_seconds: Histogram::with_opts(
+                HistogramOpts::new("batch_duration_seconds", "Time to render and commit a batch")
+                    .buckets(exponential_buckets(0.5, 4.0, 9).unwrap()),
+            ).unwrap(),
+            parse_errors: IntCounter::new(
+                "parse_errors_total", "Dirty tiles lines that could not be parsed",
+            ).unwrap(),
+            dead_letter_lines: IntCounter::new(
+                "dead_letter_lines_total", "Lines written to the dead letter file",
+            ).unwrap(),
+            archive_bytes: IntGauge::new("archive_size_bytes", "PMTiles archive size").unwrap(),
+            archive_tiles: IntGauge::new("archive_tiles", "Tiles addressed by the PMTiles archive").unwrap(),
+            db_connected: IntGauge::new(
+                "db_connected", "Whether the PostgreSQL connection is open",
+            ).unwrap(),
+            db_queries_in_flight: IntGauge::new(
+                "db_queries_in_flight", "PostgreSQL queries currently running",
+            ).unwrap(),
+            replication_lag_seconds: Gauge::new(
+                "replication_lag_seconds", "Age of the replication timestamp of the last committed batch",
+            ).unwrap(),
+            pipeline_lag_seconds: Histogram::with_opts(
+                HistogramOpts::new("pipeline_lag_seconds", "Time from picking up dirty tiles to committing them")
+                    .buckets(exponential_buckets(1.0, 4.0, 8).unwrap()),
+            ).unwrap(),
+            data_timestamp_seconds: Gauge::new(
+                "data_timestamp_seconds", "OSM data timestamp of the newest committed batch (unix time)",
+            ).unwrap(),
+            data_age_seconds: Gauge::new(
+                "data_age_seconds", "Age of the newest OSM data committed to the archive",
+            ).unwrap(),
+            registry,
+        };
+
+        metrics.register();
+        metrics
+    }
+
+    fn register(&self) {
+        let collectors: [Box<dyn prometheus::core::Collector>; 16] = [
+            Box::new(self.notifications_received.clone()),
+            Box::new(self.tiles_rendered.clone()),
+            Box::new(self.tiles_failed.clone()),
+            Box::new(self.render_seconds.clone()),
+            Box::new(self.tile_bytes.clone()),
+            Box::new(self.batch_seconds.clone()),
+            Box::new(self.parse_errors.clone()),
+            Box::new(self.dead_letter_lines.clone()),
+            Box::new(self.archive_bytes.clone()),
+            Box::new(self.archive_tiles.clone()),
+            Box::new(self.db_connected.clone()),
+            Box::new(self.db_queries_in_flight.clone()),
+            Box::new(self.replication_lag_seconds.clone()),
+            Box::new(self.pipeline_lag_seconds.clone()),
+            Box::new(self.data_timestamp_seconds.clone()),
+            Box::new(self.data_age_seconds.clone()),
+        ];
+
+        for collector in collectors {
+            self.registry.register(collector).expect("metric registered once");
+        }
+    }
+
+    /// Record one rendered tile
+    pub fn tile_rendered(&self, zoom: u8, seconds: f64, bytes: usize) {
+        let zoom = zoom.to_string();
+        self.tiles_rendered.with_label_values(&[&zoom]).inc();
+        self.render_seconds.with_label_values(&[&zoom]).observe(seconds);
+        self.tile_bytes.with_label_values(&[&zoom]).observe(bytes as f64);
+    }
+
+    /// Record a tile that failed to render
+    pub fn tile_failed(&self, zoom: u8) {
+        self.tiles_failed.with_label_values(&[&zoom.to_string()]).inc();
+    }
+
+    /// Record a committed batch's lag behind OSM and through the pipeline
+    pub fn batch_committed(&self, data_timestamp: Option<DateTime<Utc>>, committed_at: DateTime<Utc>, pipeline_lag_secs: f64) {
+        self.pipeline_lag_seconds.observe(pipeline_lag_secs);
+
+        if let Some(timestamp) = data_timestamp {
+            self.replication_lag_seconds.set((committed_at - timestamp).as_seconds_f64());
+            let seconds = timestamp.timestamp_millis() as f64 / 1000.0;
+            if seconds > self.data_timestamp_seconds.get() {
+                self.data_timestamp_seconds.set(seconds);
+            }
+        }
+    }
+
+    /// Refresh gauges read at scrape time and encode everything
+    pub fn gather(&self, archive: Option<&Path>, database: Option<&DatabasePool>) -> String {
+        if let Some(path) = archive {
+            match archive_stats(path) {
+                Ok(stats) => {
+                    self.archive_bytes.set(stats.file_size as i64);
+                    self.archive_tiles.set(stats.tile_count as i64);
+                }
+                Err(e) => warn!("Failed to read archive stats: {:#}", e),
+            }
+        }
+        if let Some(database) = database {
+            self.db_connected.set(database.is_connected() as i64);
+        }
+        let data_timestamp = self.data_timestamp_seconds.get();
+        if data_timestamp > 0.0 {
+            let now = Utc::now().timestamp_millis() as f64 / 1000.0;
+            self.data_age_seconds.set(now - data_timestamp);
+        }
+
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            warn!("Failed to encode metrics: {}", e);
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+/// Counts a query as in flight until dropped
+pub struct InFlight(());
+
+impl InFlight {
+    pub fn start() -> Self {
+        metrics().db_queries_in_flight.inc();
+        Self(())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        metrics().db_queries_in_flight.dec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gather_exposes_pipeline_metrics() {
+        let metrics = metrics();
+        metrics.tile_rendered(12, 0.02, 1500);
+        metrics.tile_failed(12);
+        let committed_at = Utc::now();
+        metrics.batch_committed(Some(committed_at - chrono::Duration::seconds(120)), committed_at, 3.0);
+        {
+            let _query = InFlight::start();
+            assert!(metrics.db_queries_in_flight.get() >= 1);
+        }
+
+        let path = std::env::temp_dir().join("test_metrics_missing.pmtiles");
+        let text = metrics.gather(Some(&path), None);
+
+        assert!(text.contains(r#"jvt_tiles_rendered_total{zoom="12"}"#));
+        assert!(text.contains(r#"jvt_tile_render_seconds_count{zoom="12"}"#));
+        assert!(text.contains(r#"jvt_tile_bytes_sum{zoom="12"}"#));
+        assert!(text.contains(r#"jvt_tiles_failed_total{zoom="12"}"#));
+        assert!(text.contains("jvt_archive_size_bytes"));
+        assert!(text.contains("jvt_notifications_received_total"));
+        assert!(text.contains("jvt_replication_lag_seconds"));
+        assert!(text.contains("jvt_pipeline_lag_seconds_count"));
+        assert!(metrics.data_age_seconds.get() >= 120.0);
+    }
+}
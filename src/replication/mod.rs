@@ -0,0 +1,3 @@
+pub mod supervisor;
+
+pub use supervisor::{ReplicationRun, ReplicationSupervisor};
@@ -0,0 +1,304 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use crate::Config;
+use crate::database::NotificationPayload;
+use crate::tiles::ArchiveLock;
+
+/// Expire files from earlier runs kept for debugging
+const KEEP_EXPIRE_FILES: usize = 10;
+
+/// Runs `osm2pgsql-replication update` as a supervised subprocess.
+///
+/// Output is forwarded line by line into tracing (target `osm2pgsql`), runs
+/// are killed after the configured timeout, and only one run can be active at
+/// a time - within this process via a mutex, and across processes on the same
+/// host (e.g. a leftover cron job using `jvt replicate --once`) via a file lock
+/// in the dirty tiles directory.
+pub struct ReplicationSupervisor {
+    config: Config,
+    running: Mutex<()>,
+}
+
+/// Outcome of one replication run
+#[derive(Debug, Clone)]
+pub struct ReplicationRun {
+    /// Expire file written by the run, if any tiles were expired
+    pub expire_file: Option<PathBuf>,
+    pub sequence: Option<i64>,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub duration: Duration,
+}
+
+impl ReplicationRun {
+    /// Payload for feeding the expire file straight into the worker pipeline
+    pub fn payload(&self) -> Option<NotificationPayload> {
+        self.expire_file.as_ref().map(|file| NotificationPayload {
+            file: file.clone(),
+            sequence: self.sequence,
+            timestamp: self.timestamp,
+            tile_count: None,
+        })
+    }
+}
+
+/// Subset of `osm2pgsql-replication status --json`
+#[derive(Debug, Deserialize)]
+struct ReplicationStatus {
+    local: Option<LocalStatus>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LocalStatus {
+    sequence: Option<i64>,
+    timestamp: Option<DateTime<Utc>>,
+}
+
+impl ReplicationSupervisor {
+    /// Create a supervisor from configuration
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            running: Mutex::new(()),
+        }
+    }
+
+    /// Run one replication update and report what it produced
+    pub async fn run_once(&self) -> Result<ReplicationRun> {
+        let Ok(_running) = self.running.try_lock() else {
+            return Err(anyhow::anyhow!("A replication run is already in progress"));
+        };
+
+        let dirty_dir = &self.config.files.dirty_tiles_path;
+        std::fs::create_dir_all(dirty_dir)
+            .with_context(|| format!("Failed to create {}", dirty_dir.display()))?;
+
+        let lock_name = dirty_dir.join("osm2pgsql-replication");
+        let Some(_lock) = ArchiveLock::try_acquire(&lock_name)? else {
+            return Err(anyhow::anyhow!(
+                "Another replication run holds {} ({})",
+                ArchiveLock::lock_path(&lock_name).display(),
+                ArchiveLock::holder(&lock_name).unwrap_or_else(|| "unknown holder".to_string()),
+            ));
+        };
+
+        let started = Instant::now();
+        let expire_file = dirty_dir.join(format!("dirty_tiles.{}.txt", Utc::now().format("%Y%m%d_%H%M%S")));
+        let replication = &self.config.replication;
+
+        let mut command = Command::new(&replication.command);
+        command
+            .arg("update")
+            .arg(format!("--database={}", self.config.database.url))
+            .arg(format!("--max-diff-size={}", replication.max_diff_size_mb))
+            .arg(format!("--expire-tiles={}", replication.expire_zoom))
+            .arg(format!("--expire-output={}", expire_file.display()))
+            .arg("--")
+            .args(&replication.osm2pgsql_args);
+
+        info!("Starting replication update: {}", replication.command.display());
+        self.supervise(command).await?;
+
+        let (sequence, timestamp) = match self.status().await {
+            Ok(status) => status,
+            Err(e) => {
+                warn!("Could not read replication status: {}", e);
+                (None, None)
+            }
+        };
+
+        let has_tiles = std::fs::metadata(&expire_file).map(|m| m.len() > 0).unwrap_or(false);
+        let run = ReplicationRun {
+            expire_file: has_tiles.then_some(expire_file),
+            sequence,
+            timestamp,
+            duration: started.elapsed(),
+        };
+
+        info!("Replication update finished in {:.1}s (sequence {:?}, {})",
+              run.duration.as_secs_f64(), run.sequence,
+              if has_tiles { "tiles expired" } else { "no tiles expired" });
+
+        cleanup_expire_files(dirty_dir);
+        Ok(run)
+    }
+
+    /// Read the local replication sequence and timestamp
+    async fn status(&self) -> Result<(Option<i64>, Option<DateTime<Utc>>)> {
+        let output = Command::new(&self.config.replication.command)
+            .arg("status")
+            .arg("--json")
+            .arg(format!("--database={}", self.config.database.url))
+            .kill_on_drop(true)
+            .output()
+            .await
+            .context("Failed to run osm2pgsql-replication status")?;
+
+        let status: ReplicationStatus = serde_json::from_slice(&output.stdout)
+            .context("Invalid osm2pgsql-replication status output")?;
+        let local = status.local.unwrap_or(LocalStatus { sequence: None, timestamp: None });
+
+        Ok((local.sequence, local.timestamp))
+    }
+
+    /// Spawn a command, forward its output and enforce the timeout
+    async fn supervise(&self, mut command: Command) -> Result<()> {
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to start {}", self.config.replication.command.display()))?;
+
+        let stdout = child.stdout.take().map(|out| tokio::spawn(forward_lines(out, "stdout")));
+        let stderr = child.stderr.take().map(|err| tokio::spawn(forward_lines(err, "stderr")));
+
+        let timeout = Duration::from_secs(self.config.replication.timeout_secs);
+        let status = match tokio::time::timeout(timeout, child.wait()).await {
+            Ok(status) => status.context("Failed to wait for replication process")?,
+            Err(_) => {
+                child.kill().await.ok();
+                return Err(anyhow::anyhow!("Replication update timed out after {}s and was killed",
+                                           timeout.as_secs()));
+            }
+        };
+
+        for task in [stdout, stderr].into_iter().flatten() {
+            task.await.ok();
+        }
+
+        if !status.success() {
+            return Err(anyhow::anyhow!("Replication update failed: {}", status));
+        }
+
+        Ok(())
+    }
+}
+
+/// Log each line of a child process stream
+async fn forward_lines(stream: impl AsyncRead + Unpin, name: &'static str) {
+    let mut lines = BufReader::new(stream).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        info!(target: "osm2pgsql", stream = name, "{}", line);
+    }
+}
+
+/// Remove all but the newest expire files
+fn cleanup_expire_files(dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("dirty_tiles.") && name.ends_with(".txt"))
+        })
+        .collect();
+
+    // Names embed the timestamp, so they sort chronologically
+    files.sort();
+    let excess = files.len().saturating_sub(KEEP_EXPIRE_FILES);
+
+    for path in &files[..excess] {
+        std::fs::remove_file(path).ok();
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// Write an executable stand-in for osm2pgsql-replication
+    fn stub_config(name: &str, script: &str) -> Config {
+        let dir = std::env::temp_dir().join(name);
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let bin = dir.join("osm2pgsql-replication");
+        std::fs::write(&bin, format!("#!/bin/sh\n{}", script)).unwrap();
+        std::fs::set_permissions(&bin, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut config = Config::default();
+        config.files.dirty_tiles_path = dir.join("expire");
+        config.replication.command = bin;
+        config.replication.timeout_secs = 5;
+        config
+    }
+
+    #[tokio::test]
+    async fn test_run_reads_expire_file_and_sequence() {
+        let config = stub_config("test_replication_ok", r#"
+case "$1" in
+  update)
+    for arg in "$@"; do
+      case "$arg" in --expire-output=*) out="${arg#--expire-output=}";; esac
+    done
+    echo "Applying diff"
+    echo "osm2pgsql progress" >&2
+    printf '14/8234/5425\n14/8234/5426\n' > "$out"
+    ;;
+  status)
+    echo '{"status": 0, "local": {"sequence": 4242, "timestamp": "2026-10-18T10:00:00Z"}}'
+    ;;
+esac
+"#);
+        let supervisor = ReplicationSupervisor::new(config);
+
+        let run = supervisor.run_once().await.unwrap();
+        assert_eq!(run.sequence, Some(4242));
+        assert_eq!(run.timestamp.unwrap().to_rfc3339(), "2026-10-18T10:00:00+00:00");
+
+        let payload = run.payload().unwrap();
+        assert_eq!(std::fs::read_to_string(&payload.file).unwrap().lines().count(), 2);
+        assert_eq!(payload.sequence, Some(4242));
+    }
+
+    #[tokio::test]
+    async fn test_failed_run_is_an_error() {
+        let config = stub_config("test_replication_fail", "echo 'download failed' >&2\nexit 3\n");
+        let supervisor = ReplicationSupervisor::new(config);
+
+        let err = supervisor.run_once().await.unwrap_err();
+        assert!(err.to_string().contains("Replication update failed"));
+    }
+
+    #[tokio::test]
+    async fn test_hung_run_is_killed() {
+        let mut config = stub_config("test_replication_hang", "sleep 30\n");
+        config.replication.timeout_secs = 1;
+        let supervisor = ReplicationSupervisor::new(config);
+
+        let err = supervisor.run_once().await.unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+    }
+
+    #[test]
+    fn test_cleanup_keeps_newest_expire_files() {
+        let dir = std::env::temp_dir().join("test_replication_cleanup");
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for i in 0..12 {
+            std::fs::write(dir.join(format!("dirty_tiles.20260101_0000{:02}.txt", i)), "0/0/0\n").unwrap();
+        }
+        cleanup_expire_files(&dir);
+
+        let remaining = std::fs::read_dir(&dir).unwrap().count();
+        assert_eq!(remaining, KEEP_EXPIRE_FILES);
+        assert!(!dir.join("dirty_tiles.20260101_000000.txt").exists());
+        assert!(dir.join("dirty_tiles.20260101_000011.txt").exists());
+    }
+}
@@ -0,0 +1,5 @@
+pub mod queue;
+pub mod target;
+
+pub use queue::{RerenderJob, Rerenderer};
+pub use target::{Area, RerenderTarget, ZoomRange};
@@ -0,0 +1,151 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::Serialize;
+use tracing::info;
+use crate::{Config, TileCoord};
+use crate::config::IngestMode;
+use crate::database::{DatabasePool, NotificationPayload, TileQueue};
+use crate::worker::tile_batch::ReplicationInfo;
+use super::{RerenderTarget, ZoomRange};
+
+/// Queues re-renders through the normal pipeline: the tiles are written to a
+/// dirty tiles file in `DIRTY_TILES_PATH` and announced on the worker's
+/// notification channel, or pushed into the `dirty_tiles` queue in queue
+/// ingest mode, so they are throttled, rendered, committed and recorded in
+/// `changed_tile_batches` like tiles expired by osm2pgsql.
+pub struct Rerenderer {
+    config: Config,
+    database: DatabasePool,
+}
+
+/// A queued re-render
+#[derive(Debug, Clone, Serialize)]
+pub struct RerenderJob {
+    pub description: String,
+    pub tiles: usize,
+    /// Dirty tiles file handed to the worker; its path is the audit
+    /// `source_file`. None when the tiles went to the queue.
+    pub file: Option<PathBuf>,
+}
+
+impl Rerenderer {
+    /// Create a re-render queue for the configured worker
+    pub fn new(config: Config, database: DatabasePool) -> Self {
+        Self { config, database }
+    }
+
+    /// Expand a target to tiles and hand them to the worker
+    pub async fn submit(&self, target: &RerenderTarget, requested_by: &str) -> Result<RerenderJob> {
+        check_requested_by(requested_by)?;
+        let zooms = ZoomRange::new(self.config.tiles.min_zoom, self.config.tiles.max_zoom);
+        let tiles = target.tiles(Some(&self.database), zooms, self.config.admin.max_rerender_tiles).await?;
+        if tiles.is_empty() {
+            return Err(anyhow::anyhow!("{} covers no tiles", target.describe()));
+        }
+
+        let description = target.describe();
+        let file = match self.config.worker.ingest_mode {
+            IngestMode::Queue => {
+                let queue = TileQueue::new(self.database.clone(), &self.config.worker.worker_id, self.config.worker.claim_lease_secs);
+                let source = format!("rerender: {} (requested by {})", description, requested_by);
+                queue.enqueue(&tiles, &source, &ReplicationInfo::default()).await?;
+                None
+            }
+            IngestMode::Notify => Some(self.notify_file(&description, requested_by, &tiles).await?),
+        };
+
+        match &file {
+            Some(file) => info!("Queued re-render of {} ({} tiles) requested by {} as {}",
+                                description, tiles.len(), requested_by, file.display()),
+            None => info!("Queued re-render of {} ({} tiles) requested by {} in the dirty_tiles queue",
+                          description, tiles.len(), requested_by),
+        }
+
+        Ok(RerenderJob { description, tiles: tiles.len(), file })
+    }
+
+    /// Write the tiles to a dirty tiles file and announce it to the worker
+    async fn notify_file(&self, description: &str, requested_by: &str, tiles: &[TileCoord]) -> Result<PathBuf> {
+        let file = write_dirty_tiles(&self.config.files.dirty_tiles_path, description, requested_by, tiles)?;
+
+        let payload = NotificationPayload {
+            file: file.clone(),
+            sequence: None,
+            timestamp: None,
+            tile_count: Some(tiles.len() as u64),
+        };
+        self.database.execute(
+            "SELECT pg_notify($1, $2)",
+            &[&self.config.database.notification_channel, &serde_json::to_string(&payload)?],
+        )
+        .await
+        .context("Failed to notify the worker")?;
+
+        Ok(file)
+    }
+}
+
+/// Requesters are recorded in dirty tiles file headers and queue sources,
+/// so a line break could smuggle tile lines into the file
+fn check_requested_by(requested_by: &str) -> Result<()> {
+    if requested_by.chars().any(char::is_control) {
+        return Err(anyhow::anyhow!("requested_by must not contain control characters"));
+    }
+    Ok(())
+}
+
+/// Write tiles to a new `rerender.<timestamp>.<id>.txt` dirty tiles file
+fn write_dirty_tiles(dir: &Path, description: &str, requested_by: &str, tiles: &[TileCoord]) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let now = Utc::now();
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    let path = dir.join(format!("rerender.{}.{}.txt", now.format("%Y%m%d_%H%M%S"), &id[..8]));
+
+    let mut file = std::io::BufWriter::new(
+        std::fs::File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?,
+    );
+    writeln!(file, "# format: xyz")?;
+    writeln!(file, "# rerender: {}", description)?;
+    writeln!(file, "# requested by {} at {}", requested_by, now.to_rfc3339())?;
+    for tile in tiles {
+        writeln!(file, "{}", tile)?;
+    }
+    file.flush().with_context(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worker::DirtyTilesProcessor;
+
+    #[test]
+    fn test_dirty_tiles_file_feeds_the_pipeline() {
+        let dir = std::env::temp_dir().join("test_rerender_queue");
+        let tiles = vec![TileCoord::new(14, 8234, 5425), TileCoord::new(13, 4117, 2712)];
+
+        let path = write_dirty_tiles(&dir, "bbox 1,2,3,4 z13-14", "admin", &tiles).unwrap();
+        assert!(path.file_name().unwrap().to_string_lossy().starts_with("rerender."));
+
+        let processor = DirtyTilesProcessor::new(Config::default());
+        let mut stream = processor.stream_file(&path).unwrap();
+        let batch = stream.next().unwrap().unwrap();
+        assert_eq!(batch.len(), 2);
+        assert!(batch.tiles.contains(&tiles[0]));
+        assert_eq!(stream.stats().parse_errors, 0);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_requested_by_rejects_control_characters() {
+        assert!(check_requested_by("alice").is_ok());
+        assert!(check_requested_by("alice\n14/8234/5425").is_err());
+        assert!(check_requested_by("alice\r").is_err());
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::BTreeSet;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::TileCoord;
+use crate::database::DatabasePool;
+use crate::tiles::mercator::BBox;
+
+/// Inclusive zoom range, written "10-14" or "12"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ZoomRange {
+    pub min: u8,
+    pub max: u8,
+}
+
+impl ZoomRange {
+    pub fn new(min: u8, max: u8) -> Self {
+        Self { min, max }
+    }
+
+    pub fn zooms(&self) -> std::ops::RangeInclusive<u8> {
+        self.min..=self.max
+    }
+}
+
+impl std::str::FromStr for ZoomRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (min, max) = s.trim().split_once('-').unwrap_or((s, s));
+        let parse = |v: &str| v.trim().parse::<u8>().map_err(|_| format!("Invalid zoom range: {}", s));
+        let (min, max) = (parse(min)?, parse(max)?);
+
+        if min > max || max > crate::MAX_ZOOM {
+            return Err(format!("Invalid zoom range: {}", s));
+        }
+        Ok(Self { min, max })
+    }
+}
+
+impl TryFrom<String> for ZoomRange {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ZoomRange> for String {
+    fn from(range: ZoomRange) -> Self {
+        range.to_string()
+    }
+}
+
+impl std::fmt::Display for ZoomRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.min == self.max {
+            write!(f, "{}", self.min)
+        } else {
+            write!(f, "{}-{}", self.min, self.max)
+        }
+    }
+}
+
+/// What to re-render. As JSON: `{"type": "bbox", "bbox": "10.5,59.8,10.9,60.0", "zoom": "10-14"}`.
+/// Without a zoom range, areas cover every zoom the worker renders.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RerenderTarget {
+    /// A single tile, "z/x/y"
+    Tile { tile: String },
+    /// Every tile intersecting a lon/lat bbox, "min_lon,min_lat,max_lon,max_lat"
+    Bbox { bbox: String, zoom: Option<ZoomRange> },
+    /// Every tile intersecting a GeoJSON geometry, feature or feature collection (WGS84)
+    Polygon { geojson: Value, zoom: Option<ZoomRange> },
+    /// Every tile intersecting an OSM relation's area in `planet_osm_polygon`
+    Relation { relation: i64, zoom: Option<ZoomRange> },
+}
+
+impl RerenderTarget {
+    /// Short description for logs and the dirty tiles file header
+    pub fn describe(&self) -> String {
+        let zoom = |zoom: &Option<ZoomRange>| zoom.map(|z| format!(" z{}", z)).unwrap_or_default();
+        match self {
+            Self::Tile { tile } => format!("tile {}", tile),
+            Self::Bbox { bbox, zoom: z } => format!("bbox {}{}", bbox, zoom(z)),
+            Self::Polygon { zoom: z, .. } => format!("GeoJSON polygon{}", zoom(z)),
+            Self::Relation { relation, zoom: z } => format!("relation {}{}", relation, zoom(z)),
+        }
+    }
+
+    /// Expand to the tiles to re-render, refusing requests over `max_tiles`.
+    /// Polygons are intersected with tile envelopes by PostGIS.
+    pub async fn tiles(&self, database: Option<&DatabasePool>, default_zoom: ZoomRange, max_tiles: u64) -> Result<Vec<TileCoord>> {
+        match self {
+            Self::Tile { tile } => {
+                let coord: TileCoord = tile.parse().map_err(anyhow::Error::msg)?;
+                if !default_zoom.zooms().contains(&coord.z) {
+                    return Err(anyhow::anyhow!("Tile {} is outside rendered zooms {}", coord, default_zoom));
+                }
+                Ok(vec![coord])
+            }
+            Self::Bbox { bbox, zoom } => {
+                let bbox = BBox::parse(bbox).map_err(anyhow::Error::msg)?;
+                bbox_tiles(&bbox, zoom.unwrap_or(default_zoom), max_tiles)
+            }
+            Self::Polygon { geojson, zoom } => {
+                let database = database.context("Polygon re-renders need a database connection")?;
+                let area = Area::from_geojson(database, geojson).await?;
+                area.tiles(database, zoom.unwrap_or(default_zoom), max_tiles).await
+            }
+            Self::Relation { relation, zoom } => {
+                let database = database.context("Relation re-renders need a database connection")?;
+                let area = Area::from_relation(database, *relation).await?;
+                area.tiles(database, zoom.unwrap_or(default_zoom), max_tiles).await
+            }
+        }
+    }
+}
+
+/// All tiles intersecting a lon/lat bbox over a zoom range
+pub fn bbox_tiles(bbox: &BBox, zoom: ZoomRange, max_tiles: u64) -> Result<Vec<TileCoord>> {
+    let ranges = zoom.zooms()
+        .map(|z| TileCoord::tiles_in_bbox(bbox, z).map_err(anyhow::Error::msg))
+        .collect::<Result<Vec<_>>>()?;
+    check_size(ranges.iter().map(|range| range.tile_count()).sum(), max_tiles)?;
+
+    Ok(ranges.into_iter().flatten().collect())
+}
+
+fn check_size(tiles: u64, max_tiles: u64) -> Result<()> {
+    if tiles > max_tiles {
+        return Err(anyhow::anyhow!(
+            "Re-render would cover {} tiles, more than the limit of {} (MAX_RERENDER_TILES)", tiles, max_tiles,
+        ));
+    }
+    Ok(())
+}
+
+/// A polygon resolved to EPSG:3857 EWKB plus its lon/lat bounds
+pub struct Area {
+    pub ewkb: Vec<u8>,
+    pub bounds: BBox,
+}
+
+impl Area {
+    /// Union of the geometries of a GeoJSON object (WGS84)
+    pub async fn from_geojson(database: &DatabasePool, geojson: &Value) -> Result<Self> {
+        let geometries = geojson_geometries(geojson)?;
+        let row = database.query_one(
+            "SELECT ST_AsEWKB(geom), ST_XMin(bounds), ST_YMin(bounds), ST_XMax(bounds), ST_YMax(bounds)
+             FROM (SELECT ST_Transform(ST_SetSRID(ST_Union(ST_GeomFromGeoJSON(g)), 4326), 3857) AS geom
+                   FROM unnest($1::text[]) AS g) AS area,
+                  LATERAL (SELECT ST_Transform(geom, 4326)::box2d AS bounds) AS b",
+            &[&geometries],
+        )
+        .await
+        .context("Invalid GeoJSON geometry")?;
+
+        Self::from_row(&row).context("GeoJSON has no geometry")
+    }
+
+    /// A relation's area from `planet_osm_polygon`
+    pub async fn from_relation(database: &DatabasePool, relation: i64) -> Result<Self> {
+        // osm2pgsql stores relations with negated ids
+        let row = database.query_one(
+            "SELECT ST_AsEWKB(geom), ST_XMin(bounds), ST_YMin(bounds), ST_XMax(bounds), ST_YMax(bounds)
+             FROM (SELECT ST_Union(way) AS geom FROM planet_osm_polygon WHERE osm_id = -$1) AS area,
+                  LATERAL (SELECT ST_Transform(geom, 4326)::box2d AS bounds) AS b",
+            &[&relation],
+        )
+        .await
+        .context("Failed to look up relation")?;
+
+        Self::from_row(&row)
+            .with_context(|| format!("Relation {} not found in planet_osm_polygon", relation))
+    }
+
+    fn from_row(row: &tokio_postgres::Row) -> Option<Self> {
+        Some(Self {
+            ewkb: row.get::<_, Option<Vec<u8>>>(0)?,
+            bounds: BBox::new(row.get(1), row.get(2), row.get(3), row.get(4)),
+        })
+    }
+
+    /// Tiles whose envelope intersects the area (the limit applies to its bbox)
+    async fn tiles(&self, database: &DatabasePool, zoom: ZoomRange, max_tiles: u64) -> Result<Vec<TileCoord>> {
+        let candidates = zoom.zooms()
+            .map(|z| TileCoord::tiles_in_bbox(&self.bounds, z).map_err(anyhow::Error::msg))
+            .collect::<Result<Vec<_>>>()?;
+        check_size(candidates.iter().map(|range| range.tile_count()).sum(), max_tiles)?;
+
+        let mut tiles = BTreeSet::new();
+        for range in candidates {
+            let rows = database.query(
+                "SELECT x, y
+                 FROM generate_series($2::integer, $3::integer) AS x,
+                      generate_series($4::integer, $5::integer) AS y
+                 WHERE ST_Intersects(ST_GeomFromEWKB($6), ST_TileEnvelope($1::integer, x, y))",
+                &[
+                    &i32::from(range.z), &(range.min_x as i32), &(range.max_x as i32),
+                    &(range.min_y as i32), &(range.max_y as i32), &self.ewkb,
+                ],
+            )
+            .await
+            .with_context(|| format!("Failed to intersect area with zoom {} tiles", range.z))?;
+
+            for row in rows {
+                tiles.insert(TileCoord::new(range.z, row.get::<_, i32>(0) as u32, row.get::<_, i32>(1) as u32));
+            }
+        }
+
+        Ok(tiles.into_iter().collect())
+    }
+}
+
+/// Geometry objects (as JSON text) of a GeoJSON geometry, feature or feature collection
+fn geojson_geometries(geojson: &Value) -> Result<Vec<String>> {
+    let geometries = match geojson.get("type").and_then(Value::as_str) {
+        Some("FeatureCollection") => geojson.get("features")
+            .and_then(Value::as_array)
+            .context("FeatureCollection without features")?
+            .iter()
+            .filter_map(|feature| feature.get("geometry"))
+            .filter(|geometry| !geometry.is_null())
+            .map(Value::to_string)
+            .collect(),
+        Some("Feature") => geojson.get("geometry")
+            .filter(|geometry| !geometry.is_null())
+            .map(|geometry| vec![geometry.to_string()])
+            .unwrap_or_default(),
+        Some(_) => vec![geojson.to_string()],
+        None => return Err(anyhow::anyhow!("Not a GeoJSON object")),
+    };
+
+    if geometries.is_empty() {
+        return Err(anyhow::anyhow!("GeoJSON has no geometry"));
+    }
+    Ok(geometries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zoom_range_parsing() {
+        assert_eq!("10-14".parse::<ZoomRange>().unwrap(), ZoomRange::new(10, 14));
+        assert_eq!("12".parse::<ZoomRange>().unwrap(), ZoomRange::new(12, 12));
+        assert!("14-10".parse::<ZoomRange>().is_err());
+        assert!("0-31".parse::<ZoomRange>().is_err());
+        assert_eq!(ZoomRange::new(3, 5).to_string(), "3-5");
+    }
+
+    #[test]
+    fn test_target_json() {
+        let target: RerenderTarget = serde_json::from_str(
+            r#"{"type": "bbox", "bbox": "10.5,59.8,10.9,60.0", "zoom": "10-14"}"#,
+        ).unwrap();
+        assert_eq!(target, RerenderTarget::Bbox {
+            bbox: "10.5,59.8,10.9,60.0".to_string(),
+            zoom: Some(ZoomRange::new(10, 14)),
+        });
+        assert_eq!(target.describe(), "bbox 10.5,59.8,10.9,60.0 z10-14");
+
+        let target: RerenderTarget = serde_json::from_str(r#"{"type": "relation", "relation": 62422}"#).unwrap();
+        assert_eq!(target, RerenderTarget::Relation { relation: 62422, zoom: None });
+
+        assert!(serde_json::from_str::<RerenderTarget>(r#"{"type": "bbox", "bbox": "1,2,3,4", "zoom": "9-1"}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tile_and_bbox_expansion() {
+        let zooms = ZoomRange::new(0, 14);
+
+        let tile = RerenderTarget::Tile { tile: "14/8234/5425".to_string() };
+        assert_eq!(tile.tiles(None, zooms, 10).await.unwrap(), vec![TileCoord::new(14, 8234, 5425)]);
+
+        let too_deep = RerenderTarget::Tile { tile: "16/0/0".to_string() };
+        assert!(too_deep.tiles(None, zooms, 10).await.is_err());
+
+        let bbox = RerenderTarget::Bbox { bbox: "10.5,59.8,10.9,60.0".to_string(), zoom: Some(ZoomRange::new(0, 10)) };
+        let tiles = bbox.tiles(None, zooms, 1_000).await.unwrap();
+        assert!(tiles.contains(&TileCoord::new(0, 0, 0)));
+        assert!(tiles.iter().all(|t| t.z <= 10 && t.bounds_lonlat().intersects(&BBox::new(10.5, 59.8, 10.9, 60.0))));
+
+        let err = bbox.tiles(None, zooms, 5).await.unwrap_err();
+        assert!(err.to_string().contains("MAX_RERENDER_TILES"));
+
+        let polygon = RerenderTarget::Polygon { geojson: serde_json::json!({"type": "Point"}), zoom: None };
+        assert!(polygon.tiles(None, zooms, 10).await.is_err());
+    }
+
+    #[test]
+    fn test_geojson_geometries() {
+        let polygon = serde_json::json!({"type": "Polygon", "coordinates": [[[0, 0], [1, 0], [1, 1], [0, 0]]]});
+        let feature = serde_json::json!({"type": "Feature", "properties": {}, "geometry": polygon});
+        let collection = serde_json::json!({"type": "FeatureCollection", "features": [feature, feature]});
+
+        assert_eq!(geojson_geometries(&polygon).unwrap(), vec![polygon.to_string()]);
+        assert_eq!(geojson_geometries(&feature).unwrap(), vec![polygon.to_string()]);
+        assert_eq!(geojson_geometries(&collection).unwrap().len(), 2);
+        assert!(geojson_geometries(&serde_json::json!({"type": "FeatureCollection", "features": []})).is_err());
+        assert!(geojson_geometries(&serde_json::json!([1, 2])).is_err());
+    }
+}
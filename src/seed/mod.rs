@@ -0,0 +1,5 @@
+pub mod seeder;
+pub mod spool;
+
+pub use seeder::{SeedArea, SeedOptions, SeedStats, Seeder};
+pub use spool::SeedSpool;
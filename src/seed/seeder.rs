@@ -0,0 +1,384 @@
+use std::path::PathBuf;
+use std::time::Instant;
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use tracing::info;
+use crate::{Config, TileCoord};
+use crate::database::{DatabasePool, ReplicationProperties};
+use crate::metrics::metrics;
+use crate::rerender::{Area, ZoomRange};
+use crate::tiles::{ArchiveLock, MvtGenerator, TilesetMetadata};
+use crate::tiles::mercator::BBox;
+use crate::tiles::metadata::vector_layers;
+use crate::tiles::pmtiles_writer::ArchiveBuilder;
+use crate::worker::ProgressTracker;
+use crate::worker::progress::format_duration;
+use crate::worker::tile_batch::ReplicationInfo;
+use super::spool::{SeedSpool, TOP_UNIT};
+
+/// Zoom whose tiles split a seed into resumable units of work
+const UNIT_ZOOM: u8 = 6;
+
+/// Rendered tiles a unit buffers before flushing them to the spool
+const FLUSH_TILES: usize = 1000;
+
+/// Whether a tile's envelope (and the seeded area, if any) touches any data
+const HAS_DATA_SQL: &str = "
+    WITH tile AS (SELECT ST_TileEnvelope($1::integer, $2::integer, $3::integer) AS env)
+    SELECT ($4::bytea IS NULL OR ST_Intersects(ST_GeomFromEWKB($4), env))
+       AND (EXISTS (SELECT 1 FROM planet_osm_polygon WHERE way && env)
+         OR EXISTS (SELECT 1 FROM planet_osm_line WHERE way && env)
+         OR EXISTS (SELECT 1 FROM planet_osm_point WHERE way && env))
+    FROM tile";
+
+/// Area to seed
+pub enum SeedArea {
+    Planet,
+    /// Lon/lat bbox
+    Bbox(BBox),
+    /// A GeoJSON area or relation, described for logs and resume checks
+    Polygon { description: String, area: Area },
+}
+
+impl SeedArea {
+    /// Lon/lat bounds of the area
+    pub fn bounds(&self) -> BBox {
+        match self {
+            Self::Planet => BBox::world(),
+            Self::Bbox(bbox) => *bbox,
+            Self::Polygon { area, .. } => area.bounds,
+        }
+    }
+
+    /// Description recorded in the spool; polygons include a digest of their
+    /// geometry so a resume cannot silently continue with a different area
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Planet => "planet".to_string(),
+            Self::Bbox(b) => format!("bbox {},{},{},{}", b.min_x, b.min_y, b.max_x, b.max_y),
+            Self::Polygon { description, area } => {
+                format!("{} ({})", description, &hex::encode(Sha256::digest(&area.ewkb))[..12])
+            }
+        }
+    }
+
+    /// Cheap bounds check; polygons are intersected exactly by PostGIS
+    fn may_cover(&self, coord: &TileCoord) -> bool {
+        matches!(self, Self::Planet) || coord.bounds_lonlat().intersects(&self.bounds())
+    }
+
+    fn ewkb(&self) -> Option<&[u8]> {
+        match self {
+            Self::Polygon { area, .. } => Some(&area.ewkb),
+            _ => None,
+        }
+    }
+}
+
+/// How to seed
+#[derive(Debug, Clone)]
+pub struct SeedOptions {
+    pub output: PathBuf,
+    pub zooms: ZoomRange,
+    /// Deepest zoom at which tiles are checked for data before descending
+    pub prune_zoom: u8,
+    /// Units rendered concurrently
+    pub jobs: usize,
+    /// Discard an interrupted seed instead of resuming it
+    pub restart: bool,
+    /// Replace an existing output archive
+    pub force: bool,
+    /// Keep the spool after the archive is written
+    pub keep_spool: bool,
+}
+
+/// Counters for a seed or one of its units
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SeedStats {
+    /// Tiles with data
+    pub rendered: u64,
+    /// Tiles that rendered empty and are left out of the archive
+    pub empty: u64,
+    /// Subtrees skipped because their tile holds no data
+    pub pruned: u64,
+    pub bytes: u64,
+}
+
+impl SeedStats {
+    fn add(&mut self, other: &SeedStats) {
+        self.rendered += other.rendered;
+        self.empty += other.empty;
+        self.pruned += other.pruned;
+        self.bytes += other.bytes;
+    }
+}
+
+impl std::fmt::Display for SeedStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} tiles ({} bytes), {} empty, {} subtrees pruned",
+               self.rendered, self.bytes, self.empty, self.pruned)
+    }
+}
+
+/// A resumable piece of a seed: the zooms above the unit zoom, or one unit
+/// zoom tile and everything below it
+#[derive(Debug, Clone, PartialEq)]
+enum Unit {
+    Top,
+    Tile(TileCoord),
+}
+
+impl Unit {
+    fn key(&self) -> Result<i64> {
+        match self {
+            Self::Top => Ok(TOP_UNIT),
+            Self::Tile(coord) => Ok(coord.to_tile_id().map_err(anyhow::Error::msg)? as i64),
+        }
+    }
+}
+
+impl std::fmt::Display for Unit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Top => write!(f, "zooms below {}", UNIT_ZOOM),
+            Self::Tile(coord) => write!(f, "{}", coord),
+        }
+    }
+}
+
+/// Renders every tile of a zoom range over an area into a new archive.
+///
+/// The area is walked depth first from the unit zoom tiles. Down to the
+/// prune zoom each tile is first checked for data with an index-only query,
+/// and empty tiles are skipped together with their whole subtree, which is
+/// what keeps oceans and deserts cheap. Tiles are spooled to SQLite and the
+/// archive is assembled in one pass at the end.
+pub struct Seeder {
+    config: Config,
+    database: DatabasePool,
+    generator: MvtGenerator,
+    area: SeedArea,
+    options: SeedOptions,
+}
+
+impl Seeder {
+    pub fn new(config: Config, database: DatabasePool, area: SeedArea, options: SeedOptions) -> Self {
+        Self {
+            generator: MvtGenerator::new(database.clone(), config.clone()),
+            config,
+            database,
+            area,
+            options,
+        }
+    }
+
+    /// Seed the archive, resuming an interrupted run for the same area and zooms
+    pub async fn run(&self) -> Result<SeedStats> {
+        let output = &self.options.output;
+        if output.exists() && !self.options.force {
+            return Err(anyhow::anyhow!("{} already exists; pass --force to replace it", output.display()));
+        }
+        let _lock = ArchiveLock::try_acquire(output)?.ok_or_else(|| anyhow::anyhow!(
+            "{} is locked by another jvt instance ({})",
+            output.display(),
+            ArchiveLock::holder(output).unwrap_or_else(|| "unknown holder".to_string()),
+        ))?;
+
+        if self.options.restart {
+            SeedSpool::open(output)?.remove()?;
+        }
+        let spool = SeedSpool::open(output)?;
+        let replication = self.start(&spool).await?;
+
+        let units = plan_units(&self.area, self.options.zooms)?;
+        let done = spool.completed_units()?;
+        let mut pending = Vec::with_capacity(units.len());
+        for unit in &units {
+            if !done.contains(&unit.key()?) {
+                pending.push(unit.clone());
+            }
+        }
+        info!("Seeding {} at zooms {} into {}: {} units, {} already done",
+              self.area.describe(), self.options.zooms, output.display(), units.len(), units.len() - pending.len());
+
+        let mut progress = ProgressTracker::new(pending.len() as u64);
+        let mut stats = SeedStats::default();
+        let started = Instant::now();
+
+        let mut results = futures::stream::iter(pending)
+            .map(|unit| self.seed_unit(&spool, unit))
+            .buffer_unordered(self.options.jobs.max(1));
+        while let Some(result) = results.next().await {
+            let (unit, unit_stats) = result?;
+            stats.add(&unit_stats);
+            progress.advance(1);
+
+            let renders = (stats.rendered + stats.empty) as f64 / started.elapsed().as_secs_f64().max(1e-3);
+            info!("Seeded {}: {}; units {}; {:.1} tiles/s", unit, unit_stats, progress, renders);
+        }
+        drop(results);
+
+        let tiles = self.assemble(&spool, &replication)?;
+        info!("Seeded {} with {} tiles in {} ({}), {:.1} tiles/s",
+              output.display(), tiles, format_duration(started.elapsed()), stats,
+              (stats.rendered + stats.empty) as f64 / started.elapsed().as_secs_f64().max(1e-3));
+
+        if self.options.keep_spool {
+            info!("Kept seed spool {}", spool.path().display());
+        } else {
+            spool.remove()?;
+        }
+        Ok(stats)
+    }
+
+    /// Check the spool belongs to this seed, recording the parameters and the
+    /// starting replication state on first use. The archive is stamped with
+    /// the state at the start, so updates applied while seeding are replayed.
+    async fn start(&self, spool: &SeedSpool) -> Result<ReplicationInfo> {
+        let zooms = self.options.zooms.to_string();
+        let area = self.area.describe();
+
+        if let (Some(spooled_zooms), Some(spooled_area)) = (spool.param("zooms")?, spool.param("area")?) {
+            if spooled_zooms != zooms || spooled_area != area {
+                return Err(anyhow::anyhow!(
+                    "{} holds an interrupted seed of {} at zooms {}; seed the same area and zooms to resume it, or pass --restart",
+                    spool.path().display(), spooled_area, spooled_zooms,
+                ));
+            }
+            info!("Resuming seed from {}", spool.path().display());
+            return Ok(ReplicationInfo {
+                sequence: spool.param("replication_sequence")?.and_then(|v| v.parse().ok()),
+                timestamp: spool.param("replication_timestamp")?.and_then(|v| v.parse().ok()),
+            });
+        }
+
+        let replication = ReplicationProperties::new(self.database.clone()).read().await?;
+        if let Some(sequence) = replication.sequence {
+            spool.set_param("replication_sequence", &sequence.to_string())?;
+        }
+        if let Some(timestamp) = replication.timestamp {
+            spool.set_param("replication_timestamp", &timestamp.to_rfc3339())?;
+        }
+        spool.set_param("zooms", &zooms)?;
+        spool.set_param("area", &area)?;
+        Ok(replication)
+    }
+
+    /// Walk one unit depth first, pruning empty subtrees
+    async fn seed_unit(&self, spool: &SeedSpool, unit: Unit) -> Result<(Unit, SeedStats)> {
+        let zooms = self.options.zooms;
+        let (root, deepest) = match &unit {
+            Unit::Top => (TileCoord::new(0, 0, 0), unit_zoom(zooms) - 1),
+            Unit::Tile(coord) => (coord.clone(), zooms.max),
+        };
+
+        let mut stats = SeedStats::default();
+        let mut rendered = Vec::new();
+        let mut stack = vec![root];
+
+        while let Some(coord) = stack.pop() {
+            if coord.z <= self.options.prune_zoom && !self.has_data(&coord).await? {
+                stats.pruned += 1;
+                continue;
+            }
+
+            if coord.z >= zooms.min {
+                let started = Instant::now();
+                let data = self.generator.generate_tile(&coord).await
+                    .with_context(|| format!("Failed to render tile {}", coord))?;
+                metrics().tile_rendered(coord.z, started.elapsed().as_secs_f64(), data.len());
+
+                if data.is_empty() {
+                    stats.empty += 1;
+                } else {
+                    stats.rendered += 1;
+                    stats.bytes += data.len() as u64;
+                    rendered.push((coord.to_tile_id().map_err(anyhow::Error::msg)?, data));
+                }
+                if rendered.len() >= FLUSH_TILES {
+                    spool.write(&rendered, None)?;
+                    rendered.clear();
+                }
+            }
+
+            if coord.z < deepest {
+                stack.extend(coord.children().into_iter().rev().filter(|child| self.area.may_cover(child)));
+            }
+        }
+
+        spool.write(&rendered, Some(unit.key()?))?;
+        Ok((unit, stats))
+    }
+
+    async fn has_data(&self, coord: &TileCoord) -> Result<bool> {
+        let row = self.database.query_one(
+            HAS_DATA_SQL,
+            &[&i32::from(coord.z), &(coord.x as i32), &(coord.y as i32), &self.area.ewkb()],
+        )
+        .await
+        .with_context(|| format!("Failed to check tile {} for data", coord))?;
+        Ok(row.get(0))
+    }
+
+    /// Write the spooled tiles to the output archive
+    fn assemble(&self, spool: &SeedSpool, replication: &ReplicationInfo) -> Result<u64> {
+        let zooms = self.options.zooms;
+        let bounds = self.area.bounds();
+
+        let mut metadata = TilesetMetadata::from_config(&self.config);
+        metadata.minzoom = zooms.min;
+        metadata.maxzoom = zooms.max;
+        metadata.bounds = [bounds.min_x, bounds.min_y, bounds.max_x, bounds.max_y];
+        metadata.vector_layers = vector_layers(zooms.min, zooms.max);
+        metadata.record_replication(replication);
+
+        info!("Writing {} spooled tiles to {}", spool.tile_count()?, self.options.output.display());
+        let mut builder = ArchiveBuilder::create(&self.options.output, &metadata)?;
+        spool.for_each_tile(|tile_id, data| builder.add(tile_id, data))?;
+        builder.finish()
+    }
+}
+
+/// Zoom of the unit tiles
+fn unit_zoom(zooms: ZoomRange) -> u8 {
+    zooms.max.min(UNIT_ZOOM)
+}
+
+/// Units covering an area, in tile id order
+fn plan_units(area: &SeedArea, zooms: ZoomRange) -> Result<Vec<Unit>> {
+    let unit_zoom = unit_zoom(zooms);
+    let mut tiles: Vec<TileCoord> = TileCoord::tiles_in_bbox(&area.bounds(), unit_zoom)
+        .map_err(anyhow::Error::msg)?
+        .filter(|coord| area.may_cover(coord))
+        .collect();
+    tiles.sort_by_key(TileCoord::to_tile_id);
+
+    let top = (zooms.min < unit_zoom).then_some(Unit::Top);
+    Ok(top.into_iter().chain(tiles.into_iter().map(Unit::Tile)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_units() {
+        let planet = plan_units(&SeedArea::Planet, ZoomRange::new(0, 14)).unwrap();
+        assert_eq!(planet.len(), 1 + 4096);
+        assert_eq!(planet[0], Unit::Top);
+        assert!(planet.windows(2).all(|pair| pair[0].key().unwrap() < pair[1].key().unwrap()));
+
+        // Shallow seeds split at their max zoom; deep ones starting at the
+        // unit zoom need no top pass
+        let shallow = plan_units(&SeedArea::Planet, ZoomRange::new(2, 4)).unwrap();
+        assert_eq!(shallow.len(), 1 + 256);
+        let deep = plan_units(&SeedArea::Planet, ZoomRange::new(6, 14)).unwrap();
+        assert!(!deep.contains(&Unit::Top));
+
+        let oslo = SeedArea::Bbox(BBox::new(10.5, 59.8, 10.9, 60.0));
+        let units = plan_units(&oslo, ZoomRange::new(8, 14)).unwrap();
+        assert_eq!(units, vec![Unit::Tile(TileCoord::new(6, 33, 18))]);
+        assert_eq!(oslo.describe(), "bbox 10.5,59.8,10.9,60");
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use anyhow::{Context, Result};
+use rusqlite::{Connection, OptionalExtension, params};
+
+/// Unit key of the pass over zooms below the unit zoom
+pub const TOP_UNIT: i64 = -1;
+
+/// On-disk store for a seed in progress. PMTiles archives can only be
+/// written in tile id order, so rendered tiles collect in a SQLite file next
+/// to the output together with the units of work already finished; an
+/// interrupted seed picks up from the first unfinished unit.
+pub struct SeedSpool {
+    path: PathBuf,
+    conn: Mutex<Connection>,
+}
+
+impl SeedSpool {
+    /// Spool path for an output archive, `<output>.seed.sqlite`
+    pub fn path_for(output: &Path) -> PathBuf {
+        let mut name = output.file_name().unwrap_or_default().to_os_string();
+        name.push(".seed.sqlite");
+        output.with_file_name(name)
+    }
+
+    /// Open (or create) the spool for an output archive
+    pub fn open(output: &Path) -> Result<Self> {
+        let path = Self::path_for(output);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        let conn = Connection::open(&path)
+            .with_context(|| format!("Failed to open seed spool {}", path.display()))?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;
+             CREATE TABLE IF NOT EXISTS params (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+             CREATE TABLE IF NOT EXISTS units (unit INTEGER PRIMARY KEY);
+             CREATE TABLE IF NOT EXISTS tiles (tile_id INTEGER PRIMARY KEY, data BLOB NOT NULL);",
+        )
+        .with_context(|| format!("Failed to initialize seed spool {}", path.display()))?;
+
+        Ok(Self { path, conn: Mutex::new(conn) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// A recorded parameter, if any
+    pub fn param(&self, key: &str) -> Result<Option<String>> {
+        self.lock()
+            .query_row("SELECT value FROM params WHERE key = ?1", [key], |row| row.get(0))
+            .optional()
+            .context("Failed to read seed spool parameters")
+    }
+
+    /// Record a parameter
+    pub fn set_param(&self, key: &str, value: &str) -> Result<()> {
+        self.lock()
+            .execute("INSERT OR REPLACE INTO params (key, value) VALUES (?1, ?2)", [key, value])
+            .context("Failed to write seed spool parameters")?;
+        Ok(())
+    }
+
+    /// Units finished by earlier runs
+    pub fn completed_units(&self) -> Result<HashSet<i64>> {
+        let conn = self.lock();
+        let mut stmt = conn.prepare("SELECT unit FROM units")?;
+        let units = stmt.query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()
+            .context("Failed to read completed seed units")?;
+        Ok(units)
+    }
+
+    /// Store rendered tiles, and mark `completed_unit` done in the same
+    /// transaction once its last tiles are written
+    pub fn write(&self, tiles: &[(u64, Vec<u8>)], completed_unit: Option<i64>) -> Result<()> {
+        let mut conn = self.lock();
+        let tx = conn.transaction()?;
+        {
+            let mut insert = tx.prepare_cached("INSERT OR REPLACE INTO tiles (tile_id, data) VALUES (?1, ?2)")?;
+            for (tile_id, data) in tiles {
+                insert.execute(params![*tile_id as i64, data])?;
+            }
+        }
+        if let Some(unit) = completed_unit {
+            tx.execute("INSERT OR IGNORE INTO units (unit) VALUES (?1)", [unit])?;
+        }
+        tx.commit().context("Failed to write tiles to the seed spool")
+    }
+
+    /// Number of spooled tiles
+    pub fn tile_count(&self) -> Result<u64> {
+        let count: i64 = self.lock().query_row("SELECT count(*) FROM tiles", [], |row| row.get(0))?;
+        Ok(count as u64)
+    }
+
+    /// Visit spooled tiles in ascending tile id order
+    pub fn for_each_tile(&self, mut visit: impl FnMut(u64, &[u8]) -> Result<()>) -> Result<()> {
+        let conn = self.lock();
+        let mut stmt = conn.prepare("SELECT tile_id, data FROM tiles ORDER BY tile_id")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let tile_id: i64 = row.get(0)?;
+            visit(tile_id as u64, row.get_ref(1)?.as_blob()?)?;
+        }
+        Ok(())
+    }
+
+    /// Close and delete the spool
+    pub fn remove(self) -> Result<()> {
+        drop(self.conn);
+        for suffix in ["", "-wal", "-shm"] {
+            let mut name = self.path.clone().into_os_string();
+            name.push(suffix);
+            match std::fs::remove_file(&name) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e).with_context(|| format!("Failed to remove {}", self.path.display())),
+            }
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spool_survives_reopen() {
+        let output = std::env::temp_dir().join("test_seed_spool.pmtiles");
+        std::fs::remove_file(SeedSpool::path_for(&output)).ok();
+
+        let spool = SeedSpool::open(&output).unwrap();
+        assert_eq!(spool.param("zooms").unwrap(), None);
+        spool.set_param("zooms", "0-14").unwrap();
+
+        // Tiles flushed mid-unit, then the unit's last tiles
+        spool.write(&[(30, b"c".to_vec()), (5, b"a".to_vec())], None).unwrap();
+        spool.write(&[(7, b"b".to_vec()), (5, b"a2".to_vec())], Some(TOP_UNIT)).unwrap();
+        drop(spool);
+
+        let spool = SeedSpool::open(&output).unwrap();
+        assert_eq!(spool.param("zooms").unwrap().as_deref(), Some("0-14"));
+        assert_eq!(spool.completed_units().unwrap(), HashSet::from([TOP_UNIT]));
+        assert_eq!(spool.tile_count().unwrap(), 3);
+
+        let mut tiles = Vec::new();
+        spool.for_each_tile(|id, data| {
+            tiles.push((id, data.to_vec()));
+            Ok(())
+        }).unwrap();
+        assert_eq!(tiles, vec![(5, b"a2".to_vec()), (7, b"b".to_vec()), (30, b"c".to_vec())]);
+
+        let path = spool.path().to_path_buf();
+        spool.remove().unwrap();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,94 @@
+use std::sync::Arc;
+use axum::{Json, Router};
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+use crate::rerender::{RerenderTarget, Rerenderer};
+
+/// State for the admin routes
+#[derive(Clone)]
+pub struct AdminState {
+    pub token: Arc<String>,
+    pub rerenderer: Arc<Rerenderer>,
+}
+
+/// `POST /admin/rerender` body: a target plus who asked for it
+#[derive(Debug, Deserialize)]
+struct RerenderRequest {
+    #[serde(flatten)]
+    target: RerenderTarget,
+    requested_by: Option<String>,
+}
+
+/// Admin routes, all requiring `Authorization: Bearer <ADMIN_TOKEN>`
+pub fn router(state: AdminState) -> Router {
+    Router::new()
+        .route("/rerender", post(post_rerender))
+        .layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .with_state(state)
+}
+
+async fn require_token(State(state): State<AdminState>, request: Request, next: Next) -> Response {
+    if !authorized(request.headers(), &state.token) {
+        return (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Bearer")], "Unauthorized\n")
+            .into_response();
+    }
+    next.run(request).await
+}
+
+/// Check the bearer token, comparing digests so timing reveals nothing about the token
+fn authorized(headers: &HeaderMap, token: &str) -> bool {
+    let Some(given) = headers.get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+
+    Sha256::digest(given.trim().as_bytes()) == Sha256::digest(token.as_bytes())
+}
+
+async fn post_rerender(State(state): State<AdminState>, Json(request): Json<RerenderRequest>) -> Response {
+    let requested_by = request.requested_by.as_deref().unwrap_or("admin API");
+
+    match state.rerenderer.submit(&request.target, requested_by).await {
+        Ok(job) => (StatusCode::ACCEPTED, Json(job)).into_response(),
+        Err(e) => {
+            warn!("Rejected re-render of {}: {:#}", request.target.describe(), e);
+            (StatusCode::BAD_REQUEST, format!("{:#}\n", e)).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_bearer_token_check() {
+        let mut headers = HeaderMap::new();
+        assert!(!authorized(&headers, "s3cret"));
+
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer s3cret"));
+        assert!(authorized(&headers, "s3cret"));
+        assert!(!authorized(&headers, "other"));
+
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Basic s3cret"));
+        assert!(!authorized(&headers, "s3cret"));
+    }
+
+    #[test]
+    fn test_request_body() {
+        let request: RerenderRequest = serde_json::from_str(
+            r#"{"type": "tile", "tile": "14/8234/5425", "requested_by": "alice"}"#,
+        ).unwrap();
+        assert_eq!(request.target, RerenderTarget::Tile { tile: "14/8234/5425".to_string() });
+        assert_eq!(request.requested_by.as_deref(), Some("alice"));
+    }
+}
@@ -0,0 +1,166 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use anyhow::{Context, Result};
+use pmtiles::{AsyncPmTilesReader, HashMapCache, MmapBackend};
+use tracing::{info, warn};
+use crate::tiles::TilesetMetadata;
+
+/// Reader type used for serving archives
+pub type ArchiveReader = AsyncPmTilesReader<MmapBackend, HashMapCache>;
+
+/// Identifies one committed version of the archive file. Every commit
+/// renames a new file over the path, so the inode changes even when the
+/// modification time and size do not; the inode cannot be reused while the
+/// version being compared against is still held open.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveVersion {
+    pub inode: u64,
+    pub modified: SystemTime,
+    pub len: u64,
+}
+
+impl ArchiveVersion {
+    fn of(path: &Path) -> Result<Self> {
+        let metadata = std::fs::metadata(path)
+            .with_context(|| format!("Failed to stat {}", path.display()))?;
+        Self::from_metadata(&metadata)
+    }
+
+    fn from_metadata(metadata: &std::fs::Metadata) -> Result<Self> {
+        Ok(Self {
+            inode: inode(metadata),
+            modified: metadata.modified()?,
+            len: metadata.len(),
+        })
+    }
+
+    /// Strong ETag identifying this archive generation
+    pub fn etag(&self) -> String {
+        let nanos = self.modified
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        format!("\"{:x}-{:x}-{:x}\"", self.inode, nanos, self.len)
+    }
+}
+
+#[cfg(unix)]
+fn inode(metadata: &std::fs::Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::ino(metadata)
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &std::fs::Metadata) -> u64 {
+    0
+}
+
+/// An opened archive version
+pub struct LoadedArchive {
+    pub reader: ArchiveReader,
+    pub version: ArchiveVersion,
+    /// Handle on this generation's file, still readable after a commit
+    /// renames a newer archive over the path
+    pub file: Arc<File>,
+    /// Metadata JSON written by `PmtilesWriter` (None for foreign archives)
+    pub metadata: Option<TilesetMetadata>,
+}
+
+/// The archive being served, reopened whenever the worker commits a new version.
+///
+/// Readers are memory mapped; a request that started on the previous version
+/// keeps its `Arc` (and the replaced file's mapping) until it finishes.
+pub struct ArchiveSource {
+    path: PathBuf,
+    current: RwLock<Option<Arc<LoadedArchive>>>,
+}
+
+impl ArchiveSource {
+    /// Create a source for an archive path (the archive may not exist yet)
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            current: RwLock::new(None),
+        }
+    }
+
+    /// Currently loaded archive, if any
+    pub fn current(&self) -> Option<Arc<LoadedArchive>> {
+        self.current.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Reopen the archive if a new version was committed; returns whether it changed
+    pub async fn reload_if_changed(&self) -> Result<bool> {
+        if !self.path.exists() {
+            return Ok(false);
+        }
+
+        let version = ArchiveVersion::of(&self.path)?;
+        if self.current().is_some_and(|loaded| loaded.version == version) {
+            return Ok(false);
+        }
+
+        let file = File::open(&self.path)
+            .with_context(|| format!("Failed to open PMTiles archive {}", self.path.display()))?;
+        let version = ArchiveVersion::from_metadata(&file.metadata()?)?;
+        let reader = AsyncPmTilesReader::new_with_cached_path(HashMapCache::default(), &self.path)
+            .await
+            .with_context(|| format!("Failed to open PMTiles archive {}", self.path.display()))?;
+
+        // A commit landed between opening the file and the reader; pick it up next time
+        if ArchiveVersion::of(&self.path)? != version {
+            return Ok(false);
+        }
+
+        let metadata = match reader.get_metadata().await.map(|json| serde_json::from_str(&json)) {
+            Ok(Ok(metadata)) => Some(metadata),
+            Ok(Err(e)) => {
+                warn!("Archive metadata is not in the jvt format: {}", e);
+                None
+            }
+            Err(e) => {
+                warn!("Failed to read archive metadata: {}", e);
+                None
+            }
+        };
+
+        info!("Serving PMTiles archive {} ({} bytes)", self.path.display(), version.len);
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) =
+            Some(Arc::new(LoadedArchive { reader, version, file: Arc::new(file), metadata }));
+
+        Ok(true)
+    }
+
+    /// Path of the served archive
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_replaced_file_changes_version() {
+        let path = std::env::temp_dir().join("test_archive_version.pmtiles");
+        let tmp = path.with_extension("pmtiles.tmp");
+        std::fs::write(&path, b"first").unwrap();
+        let held = File::open(&path).unwrap();
+        let first = ArchiveVersion::from_metadata(&held.metadata().unwrap()).unwrap();
+
+        // Same size and modification time, committed by rename
+        std::fs::write(&tmp, b"again").unwrap();
+        File::options().write(true).open(&tmp).unwrap().set_modified(first.modified).unwrap();
+        std::fs::rename(&tmp, &path).unwrap();
+
+        let second = ArchiveVersion::of(&path).unwrap();
+        assert_eq!((second.modified, second.len), (first.modified, first.len));
+        assert_ne!(second, first);
+        assert_ne!(second.etag(), first.etag());
+
+        std::fs::remove_file(path).ok();
+    }
+}
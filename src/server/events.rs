@@ -0,0 +1,199 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use futures::Stream;
+use serde::Deserialize;
+use tokio::sync::broadcast;
+use tracing::{error, info, warn};
+use crate::Config;
+use crate::database::{NotificationListener, TileUpdate};
+use crate::tiles::mercator::BBox;
+use super::{AppState, ArchiveSource};
+
+/// Updates buffered per subscriber before it is told to resync
+pub const EVENT_BUFFER: usize = 256;
+
+/// What the relay broadcasts to `/events` subscribers
+#[derive(Debug)]
+pub enum TileEvent {
+    Tiles(TileUpdate),
+    /// Updates may have been missed while the listener was disconnected
+    Resync,
+}
+
+/// Subscription parameters for `/events`
+#[derive(Debug, Default, Deserialize)]
+pub struct Subscription {
+    /// "min_lon,min_lat,max_lon,max_lat"
+    pub bbox: Option<String>,
+    pub minzoom: Option<u8>,
+    pub maxzoom: Option<u8>,
+}
+
+/// Parsed subscription filter
+#[derive(Debug, Clone)]
+pub struct TileFilter {
+    bbox: Option<BBox>,
+    min_zoom: u8,
+    max_zoom: u8,
+}
+
+impl TileFilter {
+    /// Build a filter from query parameters
+    pub fn from_subscription(subscription: &Subscription) -> Result<Self, String> {
+        let bbox = subscription.bbox.as_deref().map(BBox::parse).transpose()?;
+        let min_zoom = subscription.minzoom.unwrap_or(0);
+        let max_zoom = subscription.maxzoom.unwrap_or(u8::MAX);
+
+        if min_zoom > max_zoom {
+            return Err(format!("minzoom {} is above maxzoom {}", min_zoom, max_zoom));
+        }
+
+        Ok(Self { bbox, min_zoom, max_zoom })
+    }
+
+    /// The part of an update this subscriber cares about, if any
+    pub fn apply(&self, update: &TileUpdate) -> Option<TileUpdate> {
+        let tiles: Vec<String> = update.coords()
+            .into_iter()
+            .filter(|tile| (self.min_zoom..=self.max_zoom).contains(&tile.z))
+            .filter(|tile| self.bbox.as_ref().is_none_or(|bbox| bbox.intersects(&tile.bounds_lonlat())))
+            .map(|tile| tile.to_string())
+            .collect();
+
+        (!tiles.is_empty()).then_some(TileUpdate { committed_at: update.committed_at, tiles })
+    }
+}
+
+/// `GET /events` - Server-Sent Events stream of committed tiles.
+///
+/// Each `tiles` event carries `{"committed_at": ..., "tiles": ["z/x/y", ...]}`
+/// for tiles matching the subscription. A `resync` event means updates were
+/// dropped, because the client fell behind (data is the number dropped) or the
+/// server lost its listener (data is `reconnect`), and it should reload all tiles.
+pub async fn get_events(State(state): State<AppState>, Query(subscription): Query<Subscription>) -> Response {
+    let filter = match TileFilter::from_subscription(&subscription) {
+        Ok(filter) => filter,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let receiver = state.events.subscribe();
+    Sse::new(event_stream(receiver, filter))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+fn event_stream(
+    receiver: broadcast::Receiver<Arc<TileEvent>>,
+    filter: TileFilter,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    futures::stream::unfold((receiver, filter), |(mut receiver, filter)| async move {
+        loop {
+            let event = match receiver.recv().await.as_deref() {
+                Ok(TileEvent::Tiles(update)) => match filter.apply(update) {
+                    Some(update) => Event::default()
+                        .event("tiles")
+                        .json_data(&update)
+                        .expect("tile updates serialize"),
+                    None => continue,
+                },
+                Ok(TileEvent::Resync) => Event::default().event("resync").data("reconnect"),
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    Event::default().event("resync").data(missed.to_string())
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+
+            return Some((Ok(event), (receiver, filter)));
+        }
+    })
+}
+
+/// Relay tile update notifications from the worker to SSE subscribers.
+///
+/// The archive is reloaded before an update is broadcast, so clients that
+/// refetch a tile straight away get the new version. Notifications sent while
+/// the listener was down are lost, so every reconnect is followed by a resync.
+pub async fn relay_updates(config: Config, archive: Arc<ArchiveSource>, events: broadcast::Sender<Arc<TileEvent>>) {
+    let mut reconnect = false;
+    loop {
+        let mut listener = match NotificationListener::new(&config.database.url, &config.database.updates_channel).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                warn!("Tile update events unavailable ({}), retrying in 30s", e);
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                continue;
+            }
+        };
+        info!("Relaying tile updates from channel {}", config.database.updates_channel);
+
+        if std::mem::replace(&mut reconnect, true) {
+            if let Err(e) = archive.reload_if_changed().await {
+                error!("Failed to reload PMTiles archive: {}", e);
+            }
+            events.send(Arc::new(TileEvent::Resync)).ok();
+        }
+
+        loop {
+            let notification = match listener.wait_for_notification(Duration::from_secs(60)).await {
+                Ok(Some(notification)) => notification,
+                Ok(None) => continue,
+                Err(e) => {
+                    error!("Lost tile update listener: {}", e);
+                    break;
+                }
+            };
+
+            let update = match TileUpdate::parse(&notification.payload) {
+                Ok(update) => update,
+                Err(e) => {
+                    warn!("Ignoring tile update notification: {}", e);
+                    continue;
+                }
+            };
+
+            if let Err(e) = archive.reload_if_changed().await {
+                error!("Failed to reload PMTiles archive: {}", e);
+            }
+
+            // No subscribers is not an error
+            events.send(Arc::new(TileEvent::Tiles(update))).ok();
+        }
+
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn test_filter_by_zoom_and_bbox() {
+        let update = TileUpdate {
+            committed_at: Utc::now(),
+            // Two tiles in Europe at z14, one in the Americas, one at z2
+            tiles: vec!["14/8234/5425".into(), "14/8235/5425".into(), "14/4000/6000".into(), "2/2/1".into()],
+        };
+
+        let all = TileFilter::from_subscription(&Subscription::default()).unwrap();
+        assert_eq!(all.apply(&update).unwrap().tiles.len(), 4);
+
+        let europe = TileFilter::from_subscription(&Subscription {
+            bbox: Some("0,40,10,55".into()),
+            minzoom: Some(10),
+            maxzoom: None,
+        }).unwrap();
+        assert_eq!(europe.apply(&update).unwrap().tiles, vec!["14/8234/5425", "14/8235/5425"]);
+
+        let low = TileFilter::from_subscription(&Subscription { bbox: None, minzoom: Some(0), maxzoom: Some(1) }).unwrap();
+        assert!(low.apply(&update).is_none());
+
+        assert!(TileFilter::from_subscription(&Subscription { bbox: None, minzoom: Some(9), maxzoom: Some(3) }).is_err());
+    }
+}
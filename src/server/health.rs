@@ -0,0 +1,192 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use anyhow::{Context, Result};
+use axum::{Json, Router};
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use chrono::Utc;
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::info;
+use crate::database::DatabasePool;
+use crate::metrics::{self, metrics};
+use crate::tiles::pmtiles_writer::check_archive;
+use crate::worker::WorkerHealth;
+use crate::worker::health::HealthState;
+
+/// Everything the readiness probe checks
+#[derive(Clone)]
+pub struct HealthContext {
+    pub health: WorkerHealth,
+    /// None for processes without a database connection
+    pub database: Option<DatabasePool>,
+    pub archive_path: PathBuf,
+    /// Readiness fails when the worker makes no progress for this long
+    pub stuck_after_secs: u64,
+}
+
+/// `/readyz` response body
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    /// "ok" or the reason a check failed
+    pub checks: BTreeMap<&'static str, String>,
+    pub worker: HealthState,
+}
+
+impl HealthContext {
+    /// Run all readiness checks
+    pub async fn readiness(&self) -> Readiness {
+        let mut checks = BTreeMap::new();
+        let worker = self.health.snapshot();
+
+        if let Some(database) = &self.database {
+            checks.insert("database", status(database.health_check().await));
+        }
+
+        if let Some(connected) = worker.listener_connected {
+            let listener = if connected { Ok(()) } else { Err(anyhow::anyhow!("notification listener disconnected")) };
+            checks.insert("listener", status(listener));
+        }
+
+        // A missing archive is fine; the first commit creates it
+        checks.insert("archive", status(check_archive(&self.archive_path).map(|_| ())));
+
+        let progress = if self.health.is_stuck(Utc::now(), self.stuck_after_secs) {
+            Err(anyhow::anyhow!("no progress for over {}s (last heartbeat {:?})",
+                                self.stuck_after_secs, worker.last_heartbeat_at))
+        } else {
+            Ok(())
+        };
+        checks.insert("worker", status(progress));
+
+        Readiness {
+            ready: checks.values().all(|check| check == "ok"),
+            checks,
+            worker,
+        }
+    }
+}
+
+fn status(result: Result<()>) -> String {
+    match result {
+        Ok(()) => "ok".to_string(),
+        Err(e) => format!("{:#}", e),
+    }
+}
+
+/// `/healthz` (process alive), `/readyz` (dependencies and progress) and
+/// `/metrics` (Prometheus)
+pub fn router(context: HealthContext) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(get_metrics))
+        .with_state(context)
+}
+
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+async fn readyz(State(context): State<HealthContext>) -> Response {
+    let readiness = context.readiness().await;
+    let status = if readiness.ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(readiness)).into_response()
+}
+
+async fn get_metrics(State(context): State<HealthContext>) -> Response {
+    let body = metrics().gather(Some(&context.archive_path), context.database.as_ref());
+    ([(header::CONTENT_TYPE, metrics::CONTENT_TYPE)], body).into_response()
+}
+
+/// Serve the health endpoints until the process exits
+pub async fn serve(addr: String, context: HealthContext) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(&addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", addr))?;
+    info!("Health endpoints listening on http://{}/readyz", addr);
+
+    axum::serve(listener, router(context)).await.context("Health server failed")
+}
+
+/// Minimal HTTP GET for `jvt healthcheck`, returning the status code and body
+pub async fn probe(url: &str) -> Result<(u16, String)> {
+    let rest = url.strip_prefix("http://")
+        .ok_or_else(|| anyhow::anyhow!("Only http:// URLs are supported: {}", url))?;
+    let (host, path) = rest.split_once('/').map(|(h, p)| (h, format!("/{}", p))).unwrap_or((rest, "/".to_string()));
+
+    let mut stream = tokio::net::TcpStream::connect(host)
+        .await
+        .with_context(|| format!("Failed to connect to {}", host))?;
+    let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n", path, host);
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let code = head.split_whitespace().nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| anyhow::anyhow!("Malformed HTTP response from {}", url))?;
+
+    Ok((code, body.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(name: &str) -> HealthContext {
+        HealthContext {
+            health: WorkerHealth::new(),
+            database: None,
+            archive_path: std::env::temp_dir().join(name),
+            stuck_after_secs: 600,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_readiness_checks() {
+        let ctx = context("test_health_missing.pmtiles");
+        let readiness = ctx.readiness().await;
+        assert!(readiness.ready, "{:?}", readiness.checks);
+
+        ctx.health.set_listener_connected(false);
+        let readiness = ctx.readiness().await;
+        assert!(!readiness.ready);
+        assert_eq!(readiness.checks["listener"], "notification listener disconnected");
+
+        let ctx = context("test_health_corrupt.pmtiles");
+        std::fs::write(&ctx.archive_path, b"garbage").unwrap();
+        assert!(!ctx.readiness().await.ready);
+        std::fs::remove_file(&ctx.archive_path).ok();
+
+        let mut ctx = context("test_health_stuck.pmtiles");
+        ctx.stuck_after_secs = 0;
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        assert!(ctx.readiness().await.checks["worker"].starts_with("no progress"));
+    }
+
+    #[tokio::test]
+    async fn test_probe_against_health_server() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let ctx = context("test_health_probe.pmtiles");
+        ctx.health.set_listener_connected(false);
+        tokio::spawn(async move { axum::serve(listener, router(ctx)).await });
+
+        let (code, body) = probe(&format!("http://{}/healthz", addr)).await.unwrap();
+        assert_eq!((code, body.as_str()), (200, "ok"));
+
+        let (code, body) = probe(&format!("http://{}/readyz", addr)).await.unwrap();
+        assert_eq!(code, 503);
+        assert!(body.contains(r#""ready":false"#));
+
+        let (code, body) = probe(&format!("http://{}/metrics", addr)).await.unwrap();
+        assert_eq!(code, 200);
+        assert!(body.contains("# TYPE jvt_notifications_received_total counter"));
+    }
+}
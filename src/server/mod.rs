@@ -0,0 +1,331 @@
+pub mod admin;
+pub mod archive;
+pub mod events;
+pub mod health;
+pub mod raw;
+pub mod render;
+pub mod tilejson;
+pub mod tiles;
+
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{Context, Result};
+use axum::Router;
+use axum::http::{header, HeaderValue, Method};
+use axum::routing::get;
+use tokio::sync::broadcast;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::{error, info, warn};
+use crate::Config;
+use crate::database::DatabasePool;
+use crate::rerender::Rerenderer;
+
+pub use archive::ArchiveSource;
+pub use events::TileEvent;
+
+/// Shared state for request handlers
+#[derive(Clone)]
+pub struct AppState {
+    pub archive: Arc<ArchiveSource>,
+    pub config: Arc<Config>,
+    /// Committed tile updates (and resyncs) relayed from the worker
+    pub events: broadcast::Sender<Arc<TileEvent>>,
+    /// Admin API, when `ADMIN_TOKEN` is set
+    pub admin: Option<admin::AdminState>,
+    /// Fallback for tiles missing from the archive, when `RENDER_ON_DEMAND` is set
+    pub renderer: Option<Arc<render::OnDemandRenderer>>,
+}
+
+impl AppState {
+    /// State for serving an archive
+    pub fn new(archive: Arc<ArchiveSource>, config: Config) -> Self {
+        Self {
+            archive,
+            config: Arc::new(config),
+            events: broadcast::channel(events::EVENT_BUFFER).0,
+            admin: None,
+            renderer: None,
+        }
+    }
+}
+
+/// Build the tile server routes
+pub fn router(state: AppState) -> Router {
+    let cors = cors_layer(&state.config.server.cors_allow_origin);
+    let archive_name = state.config.files.pmtiles_archive_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "archive.pmtiles".to_string());
+
+    let mut app = Router::new();
+    if let Some(admin) = state.admin.clone() {
+        app = app.nest_service("/admin", admin::router(admin));
+    }
+
+    app
+        .route("/tiles.json", get(tilejson::get_tilejson))
+        .route("/events", get(events::get_events))
+        .route(&format!("/{}", archive_name), get(raw::get_archive))
+        .route("/{z}/{x}/{y}", get(tiles::get_tile))
+        .layer(cors)
+        .with_state(state)
+}
+
+/// CORS for browser map clients
+fn cors_layer(allow_origin: &str) -> CorsLayer {
+    let origin = if allow_origin == "*" {
+        AllowOrigin::any()
+    } else {
+        match HeaderValue::from_str(allow_origin) {
+            Ok(value) => AllowOrigin::exact(value),
+            Err(_) => {
+                warn!("Invalid CORS_ALLOW_ORIGIN {:?}, allowing any origin", allow_origin);
+                AllowOrigin::any()
+            }
+        }
+    };
+
+    CorsLayer::new()
+        .allow_origin(origin)
+        .allow_methods([Method::GET, Method::HEAD])
+        .allow_headers([header::IF_NONE_MATCH, header::IF_MATCH, header::IF_RANGE, header::RANGE])
+        .expose_headers([
+            header::ETAG, header::CONTENT_ENCODING, header::CONTENT_LENGTH,
+            header::CONTENT_RANGE, header::ACCEPT_RANGES,
+        ])
+}
+
+/// Run the tile server until the process is stopped
+pub async fn serve(config: Config) -> Result<()> {
+    let archive = Arc::new(ArchiveSource::new(config.files.pmtiles_archive_path.clone()));
+    if !archive.reload_if_changed().await? {
+        warn!("PMTiles archive {} does not exist yet, serving 503 until it does",
+              archive.path().display());
+    }
+
+    // Pick up archive versions committed by the worker without a restart
+    let watched = archive.clone();
+    let interval = Duration::from_secs(config.server.reload_interval_secs.max(1));
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            if let Err(e) = watched.reload_if_changed().await {
+                error!("Failed to reload PMTiles archive: {}", e);
+            }
+        }
+    });
+
+    let addr = config.server.listen_addr.clone();
+    let mut state = AppState::new(archive.clone(), config.clone());
+    if let Some(database) = connect_database(&config).await {
+        state.admin = config.admin.token.clone().map(|token| {
+            info!("Admin API enabled at /admin");
+            admin::AdminState {
+                token: Arc::new(token),
+                rerenderer: Arc::new(Rerenderer::new(config.clone(), database.clone())),
+            }
+        });
+        if config.server.render_on_demand {
+            info!("Rendering tiles missing from the archive on demand");
+            state.renderer = Some(Arc::new(render::OnDemandRenderer::new(database, &config)));
+        }
+    }
+
+    // Push committed tiles to /events subscribers
+    tokio::spawn(events::relay_updates(config, archive, state.events.clone()));
+
+    let app = router(state);
+    let listener = tokio::net::TcpListener::bind(&addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", addr))?;
+    info!("Tile server listening on http://{}", addr);
+
+    axum::serve(listener, app).await.context("Tile server failed")
+}
+
+/// Database connection for the admin API and on-demand rendering, if either
+/// is enabled; the server keeps serving the archive without them
+async fn connect_database(config: &Config) -> Option<DatabasePool> {
+    if config.admin.token.is_none() && !config.server.render_on_demand {
+        return None;
+    }
+
+    match DatabasePool::new(&config.database.url).await {
+        Ok(database) => Some(database),
+        Err(e) => {
+            error!("Admin API and on-demand rendering disabled, database unavailable: {:#}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{Request, Response, StatusCode};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+    use crate::TileCoord;
+    use crate::database::TileUpdate;
+    use crate::tiles::PmtilesWriter;
+
+    async fn test_app(name: &str) -> (Router, Arc<ArchiveSource>, PmtilesWriter) {
+        let mut config = Config::default();
+        config.files.pmtiles_archive_path = std::env::temp_dir().join(name);
+        std::fs::remove_file(&config.files.pmtiles_archive_path).ok();
+
+        let mut writer = PmtilesWriter::new(config.clone());
+        writer.write_tiles(&[(TileCoord::new(14, 8234, 5425), b"tile-v1".to_vec())]).await.unwrap();
+
+        let archive = Arc::new(ArchiveSource::new(config.files.pmtiles_archive_path.clone()));
+        archive.reload_if_changed().await.unwrap();
+
+        (router(AppState::new(archive.clone(), config)), archive, writer)
+    }
+
+    async fn get(app: &Router, uri: &str, etag: Option<&str>) -> Response<Body> {
+        let headers: &[(header::HeaderName, &str)] = match etag {
+            Some(etag) => &[(header::IF_NONE_MATCH, etag)],
+            None => &[],
+        };
+        get_with(app, uri, headers).await
+    }
+
+    async fn get_with(app: &Router, uri: &str, headers: &[(header::HeaderName, &str)]) -> Response<Body> {
+        let mut request = Request::get(uri).header(header::ORIGIN, "https://map.example");
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+        app.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap()
+    }
+
+    async fn body_bytes(response: Response<Body>) -> Vec<u8> {
+        response.into_body().collect().await.unwrap().to_bytes().to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_serves_tiles_with_headers() {
+        let (app, _, _) = test_app("test_server_tiles.pmtiles").await;
+
+        let response = get(&app, "/14/8234/5425.mvt", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let headers = response.headers().clone();
+        assert_eq!(headers[header::CONTENT_TYPE], "application/vnd.mapbox-vector-tile");
+        assert_eq!(headers[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        let etag = headers[header::ETAG].to_str().unwrap().to_string();
+        assert!(etag.starts_with('"'));
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(tiles::content_etag(&body).to_str().unwrap(), etag);
+
+        let response = get(&app, "/14/8234/5425.mvt", Some(&etag)).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        assert_eq!(get(&app, "/14/8234/5426.mvt", None).await.status(), StatusCode::NO_CONTENT);
+        assert_eq!(get(&app, "/2/9/0.mvt", None).await.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(get(&app, "/14/8234/5425.png", None).await.status(), StatusCode::NOT_FOUND);
+
+        // The admin API only exists with ADMIN_TOKEN set
+        let response = app.clone().oneshot(Request::post("/admin/rerender").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_tilejson_from_archive_metadata() {
+        let (app, _, _) = test_app("test_server_tilejson.pmtiles").await;
+
+        let response = app.clone().oneshot(
+            Request::get("/tiles.json").header(header::HOST, "tiles.example:8080").body(Body::empty()).unwrap()
+        ).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let tilejson: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(tilejson["tilejson"], "3.0.0");
+        assert_eq!(tilejson["tiles"][0], "http://tiles.example:8080/{z}/{x}/{y}.mvt");
+        assert_eq!(tilejson["attribution"], "© OpenStreetMap contributors");
+        assert_eq!(tilejson["vector_layers"].as_array().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_serves_archive_byte_ranges() {
+        let (app, archive, mut writer) = test_app("test_server_ranges.pmtiles").await;
+        let uri = "/test_server_ranges.pmtiles";
+        let file = std::fs::read(archive.path()).unwrap();
+
+        let response = get(&app, uri, None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+        let etag = response.headers()[header::ETAG].to_str().unwrap().to_string();
+        assert_eq!(body_bytes(response).await, file);
+
+        let response = get_with(&app, uri, &[(header::RANGE, "bytes=0-126")]).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], format!("bytes 0-126/{}", file.len()));
+        assert_eq!(body_bytes(response).await, &file[..127]);
+
+        let response = get_with(&app, uri, &[(header::RANGE, "bytes=-10"), (header::IF_MATCH, &etag)]).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(body_bytes(response).await, &file[file.len() - 10..]);
+
+        let response = get_with(&app, uri, &[(header::RANGE, &format!("bytes={}-", file.len()))]).await;
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+        // After a commit the generation ETag changes and stale If-Match requests fail
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        writer.write_tiles(&[(TileCoord::new(14, 8234, 5425), b"tile-v2".to_vec())]).await.unwrap();
+        archive.reload_if_changed().await.unwrap();
+
+        let response = get_with(&app, uri, &[(header::RANGE, "bytes=0-126"), (header::IF_MATCH, &etag)]).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        assert_ne!(response.headers()[header::ETAG].to_str().unwrap(), etag);
+    }
+
+    #[tokio::test]
+    async fn test_streams_tile_update_events() {
+        let (_, archive, _) = test_app("test_server_events.pmtiles").await;
+        let state = AppState::new(archive, Config::default());
+        let app = router(state.clone());
+
+        let response = get(&app, "/events?minzoom=10&bbox=0,40,10,55", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/event-stream");
+
+        // Only the update touching the subscribed area comes through
+        for tiles in [vec!["14/4000/6000".to_string()], vec!["14/8234/5425".to_string(), "2/2/1".to_string()]] {
+            state.events.send(Arc::new(TileEvent::Tiles(TileUpdate { committed_at: chrono::Utc::now(), tiles }))).unwrap();
+        }
+        state.events.send(Arc::new(TileEvent::Resync)).unwrap();
+
+        let mut body = response.into_body();
+        let frame = body.frame().await.unwrap().unwrap().into_data().unwrap();
+        let text = String::from_utf8(frame.to_vec()).unwrap();
+        assert!(text.starts_with("event: tiles\n"));
+        assert!(text.contains(r#""tiles":["14/8234/5425"]"#));
+
+        // Every subscriber gets the resync, whatever it filters on
+        let frame = body.frame().await.unwrap().unwrap().into_data().unwrap();
+        assert_eq!(String::from_utf8(frame.to_vec()).unwrap(), "event: resync\ndata: reconnect\n\n");
+
+        assert_eq!(get(&app, "/events?minzoom=9&maxzoom=3", None).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_picks_up_new_archive_versions() {
+        let (app, archive, mut writer) = test_app("test_server_reload.pmtiles").await;
+
+        let before = get(&app, "/14/8234/5425.mvt", None).await.headers()[header::ETAG].clone();
+
+        // Let the modification time move on before the next commit
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        writer.write_tiles(&[(TileCoord::new(14, 8234, 5425), b"tile-v2".to_vec())]).await.unwrap();
+        assert!(archive.reload_if_changed().await.unwrap());
+        assert!(!archive.reload_if_changed().await.unwrap());
+
+        let after = get(&app, "/14/8234/5425.mvt", None).await.headers()[header::ETAG].clone();
+        assert_ne!(before, after);
+    }
+}
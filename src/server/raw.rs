@@ -0,0 +1,194 @@
+use std::fs::File;
+use std::sync::Arc;
+use axum::body::{Body, Bytes};
+use axum::extract::State;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use super::AppState;
+use super::tiles::if_none_match;
+
+/// Bytes read per chunk when streaming the archive
+const READ_CHUNK: u64 = 256 * 1024;
+
+/// `GET /<archive>.pmtiles` - the raw archive for pmtiles.js style clients.
+///
+/// Every response comes from a single archive generation: the ETag changes
+/// with each commit, `If-Match` lets clients detect that mid-session (412),
+/// and bytes are read from that generation's file handle even if a newer
+/// archive has been renamed over the path since.
+pub async fn get_archive(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let Some(archive) = state.archive.current() else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "PMTiles archive not available yet").into_response();
+    };
+
+    let len = archive.version.len;
+    let etag = HeaderValue::from_str(&archive.version.etag()).expect("etag is a valid header value");
+
+    if let Some(expected) = headers.get(header::IF_MATCH)
+        && !etag_matches(expected, &etag)
+    {
+        return (StatusCode::PRECONDITION_FAILED, [(header::ETAG, etag)]).into_response();
+    }
+
+    // A stale If-Range means the client's cached bytes are from another generation
+    let range_applies = headers.get(header::IF_RANGE).is_none_or(|value| value.as_bytes() == etag.as_bytes());
+    let range = match headers.get(header::RANGE).filter(|_| range_applies).and_then(|v| v.to_str().ok()) {
+        Some(value) => match parse_range(value, len) {
+            ByteRange::Full => None,
+            ByteRange::Partial(start, end) => Some((start, end)),
+            ByteRange::Unsatisfiable => {
+                return (
+                    StatusCode::RANGE_NOT_SATISFIABLE,
+                    [(header::CONTENT_RANGE, format!("bytes */{}", len))],
+                ).into_response();
+            }
+        },
+        None => None,
+    };
+
+    if range.is_none() && if_none_match(&headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+
+    let (status, start, end) = match range {
+        Some((start, end)) => (StatusCode::PARTIAL_CONTENT, start, end),
+        None => (StatusCode::OK, 0, len.saturating_sub(1)),
+    };
+    let body_len = if len == 0 { 0 } else { end - start + 1 };
+
+    let mut response = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/vnd.pmtiles")
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, etag)
+        .header(header::CACHE_CONTROL, "public, no-cache")
+        .header(header::CONTENT_LENGTH, body_len)
+        .body(file_body(archive.file.clone(), start, body_len))
+        .expect("valid response");
+
+    if status == StatusCode::PARTIAL_CONTENT {
+        let content_range = format!("bytes {}-{}/{}", start, end, len);
+        response.headers_mut().insert(
+            header::CONTENT_RANGE,
+            HeaderValue::from_str(&content_range).expect("valid content range"),
+        );
+    }
+
+    response
+}
+
+/// Outcome of parsing a `Range` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// Serve the whole file (no range, or unsupported/multi-range requests)
+    Full,
+    /// Inclusive byte offsets
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// Parse a single `bytes=` range against a file length
+pub fn parse_range(value: &str, len: u64) -> ByteRange {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+    let Some((start, end)) = spec.split_once('-').filter(|_| !spec.contains(',')) else {
+        return ByteRange::Full;
+    };
+
+    match (start.trim(), end.trim()) {
+        // Suffix range: the last N bytes
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(suffix) if suffix > 0 && len > 0 => ByteRange::Partial(len.saturating_sub(suffix), len - 1),
+            _ => ByteRange::Unsatisfiable,
+        },
+        (start, end) => {
+            let start = start.parse::<u64>();
+            let end = if end.is_empty() { Ok(u64::MAX) } else { end.parse::<u64>() };
+
+            match (start, end) {
+                (Ok(start), Ok(end)) if start < len && start <= end => ByteRange::Partial(start, end.min(len - 1)),
+                _ => ByteRange::Unsatisfiable,
+            }
+        }
+    }
+}
+
+/// Whether an `If-Match` header value matches the ETag (weak ETags never match)
+fn etag_matches(value: &HeaderValue, etag: &HeaderValue) -> bool {
+    value.to_str().is_ok_and(|value| {
+        value.split(',').map(str::trim).any(|candidate| candidate == "*" || candidate.as_bytes() == etag.as_bytes())
+    })
+}
+
+/// Stream `len` bytes from `start` using positional reads
+fn file_body(file: Arc<File>, start: u64, len: u64) -> Body {
+    let chunks = futures::stream::unfold((start, len), move |(offset, remaining)| {
+        let file = file.clone();
+        async move {
+            if remaining == 0 {
+                return None;
+            }
+
+            let size = remaining.min(READ_CHUNK);
+            let read = tokio::task::spawn_blocking(move || {
+                let mut buf = vec![0; size as usize];
+                read_exact_at(&file, &mut buf, offset).map(|()| Bytes::from(buf))
+            })
+            .await
+            .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+
+            let next = match read {
+                Ok(_) => (offset + size, remaining - size),
+                Err(_) => (offset, 0),
+            };
+            Some((read, next))
+        }
+    });
+
+    Body::from_stream(chunks)
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset)? {
+            0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), ByteRange::Partial(0, 99));
+        assert_eq!(parse_range("bytes=900-", 1000), ByteRange::Partial(900, 999));
+        assert_eq!(parse_range("bytes=-10", 1000), ByteRange::Partial(990, 999));
+        assert_eq!(parse_range("bytes=-5000", 1000), ByteRange::Partial(0, 999));
+        assert_eq!(parse_range("bytes=500-5000", 1000), ByteRange::Partial(500, 999));
+
+        assert_eq!(parse_range("bytes=1000-", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=9-3", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=a-3", 1000), ByteRange::Unsatisfiable);
+
+        // Unsupported forms fall back to the full body
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), ByteRange::Full);
+        assert_eq!(parse_range("items=0-1", 1000), ByteRange::Full);
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::OnceCell;
+use tracing::{debug, warn};
+use crate::{Config, TileCoord};
+use crate::database::{DatabasePool, RenderedTileStore};
+use crate::metrics::metrics;
+use crate::rerender::ZoomRange;
+use crate::tiles::MvtGenerator;
+use crate::worker::TileBatch;
+
+/// Tiles remembered as rendering empty, so ocean tiles are not re-rendered
+/// on every request (the archive does not store empty tiles)
+const MAX_KNOWN_EMPTY: usize = 100_000;
+
+/// Outcome of a render, shared by every request waiting on it
+type Rendered = Result<Arc<Vec<u8>>, String>;
+
+/// Renders tiles missing from the archive while the client waits, then
+/// stages them in `rendered_tiles` so the writer persists them.
+pub struct OnDemandRenderer {
+    generator: MvtGenerator,
+    staging: RenderedTileStore,
+    zooms: ZoomRange,
+    in_flight: InFlight,
+    known_empty: Mutex<HashSet<TileCoord>>,
+}
+
+impl OnDemandRenderer {
+    /// Create a renderer for the configured zoom range
+    pub fn new(database: DatabasePool, config: &Config) -> Self {
+        Self {
+            generator: MvtGenerator::new(database.clone(), config.clone()),
+            staging: RenderedTileStore::new(database, &format!("{}-serve", config.worker.worker_id)),
+            zooms: ZoomRange::new(config.tiles.min_zoom, config.tiles.max_zoom),
+            in_flight: InFlight::default(),
+            known_empty: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Whether tiles at this zoom can be rendered
+    pub fn renders_zoom(&self, z: u8) -> bool {
+        self.zooms.zooms().contains(&z)
+    }
+
+    /// Render a tile (once, however many requests ask concurrently) and queue
+    /// it for persistence. Empty tiles come back as an empty vec.
+    pub async fn render(&self, coord: &TileCoord) -> Rendered {
+        if self.known_empty.lock().unwrap_or_else(|e| e.into_inner()).contains(coord) {
+            return Ok(Arc::default());
+        }
+
+        self.in_flight.run(coord, || async {
+            let started = Instant::now();
+            let data = self.generator.generate_tile(coord).await.map_err(|e| {
+                metrics().tile_failed(coord.z);
+                format!("{:#}", e)
+            })?;
+            metrics().tile_rendered(coord.z, started.elapsed().as_secs_f64(), data.len());
+            debug!("Rendered missing tile {} on demand ({} bytes)", coord, data.len());
+
+            if data.is_empty() {
+                self.remember_empty(coord);
+            } else {
+                self.persist(coord, &data);
+            }
+            Ok(data)
+        }).await
+    }
+
+    fn remember_empty(&self, coord: &TileCoord) {
+        let mut known_empty = self.known_empty.lock().unwrap_or_else(|e| e.into_inner());
+        if known_empty.len() >= MAX_KNOWN_EMPTY {
+            known_empty.clear();
+        }
+        known_empty.insert(coord.clone());
+    }
+
+    /// Stage the tile for the writer in the background; losing it only
+    /// means rendering it again next time
+    fn persist(&self, coord: &TileCoord, data: &[u8]) {
+        let staging = self.staging.clone();
+        let tiles = vec![(coord.clone(), data.to_vec())];
+
+        tokio::spawn(async move {
+            let mut batch = TileBatch::new(PathBuf::from("on-demand"));
+            batch.add_tile(tiles[0].0.clone());
+            if let Err(e) = staging.stage(&tiles, &batch).await {
+                warn!("Failed to queue on-demand tile {} for persistence: {:#}", tiles[0].0, e);
+            }
+        });
+    }
+}
+
+/// Per-tile in-flight deduplication: concurrent callers for the same tile
+/// share one render
+#[derive(Default)]
+struct InFlight {
+    renders: Mutex<HashMap<TileCoord, Arc<OnceCell<Rendered>>>>,
+}
+
+impl InFlight {
+    async fn run<F, Fut>(&self, coord: &TileCoord, render: F) -> Rendered
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Vec<u8>, String>>,
+    {
+        let cell = self.lock().entry(coord.clone()).or_default().clone();
+        let result = cell.get_or_init(|| async { render().await.map(Arc::new) }).await.clone();
+
+        // Later requests render afresh rather than reuse this result
+        let mut renders = self.lock();
+        if renders.get(coord).is_some_and(|current| Arc::ptr_eq(current, &cell)) {
+            renders.remove(coord);
+        }
+
+        result
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<TileCoord, Arc<OnceCell<Rendered>>>> {
+        self.renders.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_concurrent_requests_render_once() {
+        let in_flight = Arc::new(InFlight::default());
+        let renders = Arc::new(AtomicUsize::new(0));
+        let coord = TileCoord::new(14, 8234, 5425);
+
+        let requests: Vec<_> = (0..8).map(|_| {
+            let (in_flight, renders, coord) = (in_flight.clone(), renders.clone(), coord.clone());
+            tokio::spawn(async move {
+                in_flight.run(&coord, || async {
+                    renders.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    Ok(b"tile".to_vec())
+                }).await
+            })
+        }).collect();
+
+        for request in requests {
+            assert_eq!(request.await.unwrap().unwrap().as_slice(), b"tile");
+        }
+        assert_eq!(renders.load(Ordering::SeqCst), 1);
+        assert!(in_flight.lock().is_empty());
+
+        // Once finished, the next request renders again
+        let result = in_flight.run(&coord, || async { Err("boom".to_string()) }).await;
+        assert_eq!(result.unwrap_err(), "boom");
+    }
+}
@@ -0,0 +1,49 @@
+use axum::Json;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use crate::tiles::TilesetMetadata;
+use super::AppState;
+
+/// `GET /tiles.json` - TileJSON 3.0 document for the served archive
+pub async fn get_tilejson(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let Some(archive) = state.archive.current() else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "PMTiles archive not available yet").into_response();
+    };
+
+    // Archives written elsewhere only have the header to go on
+    let metadata = archive.metadata.clone().unwrap_or_else(|| {
+        let header = archive.reader.get_header();
+        let mut metadata = TilesetMetadata::from_config(&state.config);
+        metadata.minzoom = header.min_zoom;
+        metadata.maxzoom = header.max_zoom;
+        metadata.bounds = [
+            f64::from(header.min_longitude), f64::from(header.min_latitude),
+            f64::from(header.max_longitude), f64::from(header.max_latitude),
+        ];
+        metadata.vector_layers.clear();
+        metadata
+    });
+
+    let tiles_url = format!("{}/{{z}}/{{x}}/{{y}}.mvt", base_url(&state, &headers));
+    (
+        [(header::CACHE_CONTROL, "public, no-cache")],
+        Json(metadata.to_tilejson(&tiles_url)),
+    ).into_response()
+}
+
+/// Public base URL from configuration, or from the request's Host header
+fn base_url(state: &AppState, headers: &HeaderMap) -> String {
+    if let Some(url) = &state.config.server.public_url {
+        return url.clone();
+    }
+
+    let host = headers.get(header::HOST)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("localhost");
+    let scheme = headers.get("x-forwarded-proto")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("http");
+
+    format!("{}://{}", scheme, host)
+}
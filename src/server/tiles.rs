@@ -0,0 +1,118 @@
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use pmtiles::{TileId, TileType};
+use sha2::{Digest, Sha256};
+use tracing::error;
+use crate::TileCoord;
+use super::AppState;
+use super::render::OnDemandRenderer;
+
+/// `GET /{z}/{x}/{y}.mvt` - serve one tile from the archive
+pub async fn get_tile(
+    State(state): State<AppState>,
+    Path((z, x, y)): Path<(u8, u32, String)>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(y) = y.strip_suffix(".mvt").and_then(|y| y.parse::<u32>().ok()) else {
+        return (StatusCode::NOT_FOUND, "Expected /{z}/{x}/{y}.mvt").into_response();
+    };
+    let coord = match TileCoord::try_new(z, x, y) {
+        Ok(coord) => coord,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    // Missing tiles (and new zoom levels) can be rendered on demand
+    let renderer = state.renderer.as_ref().filter(|renderer| renderer.renders_zoom(z));
+
+    let Some(archive) = state.archive.current() else {
+        return match renderer {
+            Some(renderer) => render_on_demand(renderer, &coord, &headers).await,
+            None => (StatusCode::SERVICE_UNAVAILABLE, "PMTiles archive not available yet").into_response(),
+        };
+    };
+
+    let header = archive.reader.get_header();
+    if (z < header.min_zoom || z > header.max_zoom) && renderer.is_none() {
+        return (StatusCode::NOT_FOUND, "Zoom level outside the tileset").into_response();
+    }
+
+    let tile_id = coord.to_tile_id().ok().and_then(TileId::new).expect("validated tile coordinates");
+    let data = match archive.reader.get_tile(tile_id).await {
+        Ok(Some(data)) if !data.is_empty() => data,
+        Ok(_) => return match renderer {
+            Some(renderer) => render_on_demand(renderer, &coord, &headers).await,
+            None => StatusCode::NO_CONTENT.into_response(),
+        },
+        Err(e) => {
+            error!("Failed to read tile {} from archive: {}", coord, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read tile").into_response();
+        }
+    };
+
+    let etag = content_etag(&data);
+    if if_none_match(&headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+
+    let mut response = (
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static(header.tile_type.content_type())),
+            (header::ETAG, etag),
+            (header::CACHE_CONTROL, HeaderValue::from_static("public, no-cache")),
+        ],
+        data,
+    ).into_response();
+
+    if let Some(encoding) = header.tile_compression.content_encoding() {
+        response.headers_mut().insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+    }
+
+    response
+}
+
+/// Serve a freshly rendered (uncompressed) tile
+async fn render_on_demand(renderer: &OnDemandRenderer, coord: &TileCoord, headers: &HeaderMap) -> Response {
+    let data = match renderer.render(coord).await {
+        Ok(data) if !data.is_empty() => data,
+        Ok(_) => return StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            error!("Failed to render tile {} on demand: {}", coord, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to render tile").into_response();
+        }
+    };
+
+    let etag = content_etag(&data);
+    if if_none_match(headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+
+    (
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static(TileType::Mvt.content_type())),
+            (header::ETAG, etag),
+            (header::CACHE_CONTROL, HeaderValue::from_static("public, no-cache")),
+        ],
+        data.as_ref().clone(),
+    ).into_response()
+}
+
+/// Strong ETag from the stored (encoded) tile bytes
+pub fn content_etag(data: &[u8]) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{}\"", etag_digest(data)))
+        .expect("hex digest is a valid header value")
+}
+
+/// The unquoted ETag value for tile bytes
+pub fn etag_digest(data: &[u8]) -> String {
+    hex::encode(&Sha256::digest(data)[..16])
+}
+
+/// Whether an `If-None-Match` header matches the ETag
+pub fn if_none_match(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    headers.get_all(header::IF_NONE_MATCH).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/").as_bytes() == etag.as_bytes())
+}
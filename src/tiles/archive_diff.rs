@@ -0,0 +1,262 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::Arc;
+use anyhow::{Context, Result};
+use futures::{Stream, StreamExt, TryStreamExt};
+use pmtiles::TileId;
+use serde::Serialize;
+use crate::TileCoord;
+use super::DecodedTile;
+use super::pmtiles_writer::{ArchiveReader, open_archive};
+
+/// Tile changes between two archives at one zoom
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ZoomDiff {
+    pub zoom: u8,
+    pub added: u64,
+    pub removed: u64,
+    pub changed: u64,
+    pub unchanged: u64,
+    /// Stored size of the zoom's tiles in each archive
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+impl ZoomDiff {
+    pub fn byte_delta(&self) -> i64 {
+        self.bytes_after as i64 - self.bytes_before as i64
+    }
+
+    fn add(&mut self, other: &ZoomDiff) {
+        self.added += other.added;
+        self.removed += other.removed;
+        self.changed += other.changed;
+        self.unchanged += other.unchanged;
+        self.bytes_before += other.bytes_before;
+        self.bytes_after += other.bytes_after;
+    }
+}
+
+/// Feature count change of a layer, summed over the tiles that differ
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct LayerDelta {
+    pub name: String,
+    pub features_before: u64,
+    pub features_after: u64,
+    /// Tiles whose feature count for the layer changed
+    pub tiles: u64,
+}
+
+/// Differences between two archives
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ArchiveDiff {
+    pub zooms: Vec<ZoomDiff>,
+    pub total: ZoomDiff,
+    /// Per-layer changes, when requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layers: Option<Vec<LayerDelta>>,
+}
+
+impl ArchiveDiff {
+    pub fn is_identical(&self) -> bool {
+        self.total.added + self.total.removed + self.total.changed == 0
+    }
+}
+
+/// Compare two archives tile by tile. Both directories are walked in tile id
+/// order side by side, and tiles whose stored bytes differ are decompressed
+/// so a change of compression alone does not count. With `layers`, differing
+/// tiles are decoded to sum feature counts per layer.
+pub async fn diff_archives(before: &Path, after: &Path, layers: bool) -> Result<ArchiveDiff> {
+    let (old, new) = (open_archive(before).await?, open_archive(after).await?);
+    let (mut old_ids, mut new_ids) = (tile_ids(old.clone()), tile_ids(new.clone()));
+    let mut zooms: BTreeMap<u8, ZoomDiff> = BTreeMap::new();
+    let mut layer_deltas: BTreeMap<String, LayerDelta> = BTreeMap::new();
+
+    let (mut next_old, mut next_new) = (old_ids.try_next().await?, new_ids.try_next().await?);
+    loop {
+        let id = match (next_old, next_new) {
+            (None, None) => break,
+            (Some(o), Some(n)) => o.min(n),
+            (Some(id), None) | (None, Some(id)) => id,
+        };
+        let coord = TileCoord::from_tile_id(id).map_err(anyhow::Error::msg)?;
+        let zoom = zooms.entry(coord.z).or_insert_with(|| ZoomDiff { zoom: coord.z, ..Default::default() });
+
+        let old_data = if next_old == Some(id) { Some(read(&old, id).await?) } else { None };
+        let new_data = if next_new == Some(id) { Some(read(&new, id).await?) } else { None };
+        zoom.bytes_before += old_data.as_ref().map_or(0, |data| data.len() as u64);
+        zoom.bytes_after += new_data.as_ref().map_or(0, |data| data.len() as u64);
+
+        let differs = match (&old_data, &new_data) {
+            (Some(o), Some(n)) if o == n => false,
+            (Some(_), Some(_)) => decompressed(&old, id).await? != decompressed(&new, id).await?,
+            _ => true,
+        };
+        match (&old_data, &new_data) {
+            (Some(_), Some(_)) if !differs => zoom.unchanged += 1,
+            (Some(_), Some(_)) => zoom.changed += 1,
+            (Some(_), None) => zoom.removed += 1,
+            _ => zoom.added += 1,
+        }
+
+        if layers && differs {
+            let old_counts = feature_counts(&old, &coord, old_data.is_some()).await?;
+            let new_counts = feature_counts(&new, &coord, new_data.is_some()).await?;
+            let names: BTreeSet<&String> = old_counts.keys().chain(new_counts.keys()).collect();
+            for name in names {
+                let (o, n) = (old_counts.get(name).copied().unwrap_or(0), new_counts.get(name).copied().unwrap_or(0));
+                let delta = layer_deltas.entry(name.clone()).or_insert_with(|| LayerDelta {
+                    name: name.clone(),
+                    ..Default::default()
+                });
+                delta.features_before += o;
+                delta.features_after += n;
+                delta.tiles += u64::from(o != n);
+            }
+        }
+
+        if next_old == Some(id) {
+            next_old = old_ids.try_next().await?;
+        }
+        if next_new == Some(id) {
+            next_new = new_ids.try_next().await?;
+        }
+    }
+
+    let mut total = ZoomDiff::default();
+    zooms.values().for_each(|zoom| total.add(zoom));
+
+    Ok(ArchiveDiff {
+        zooms: zooms.into_values().collect(),
+        total,
+        layers: layers.then(|| layer_deltas.into_values().collect()),
+    })
+}
+
+impl std::fmt::Display for ArchiveDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:>5} {:>10} {:>10} {:>10} {:>10} {:>14}", "zoom", "added", "removed", "changed", "unchanged", "bytes")?;
+        for zoom in &self.zooms {
+            write_zoom(f, &zoom.zoom.to_string(), zoom)?;
+        }
+        write_zoom(f, "total", &self.total)?;
+
+        if let Some(layers) = &self.layers {
+            writeln!(f)?;
+            writeln!(f, "{:<16} {:>12} {:>12} {:>10}", "layer", "features", "change", "tiles")?;
+            for layer in layers {
+                writeln!(f, "{:<16} {:>12} {:>+12} {:>10}", layer.name, layer.features_after,
+                         layer.features_after as i64 - layer.features_before as i64, layer.tiles)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_zoom(f: &mut std::fmt::Formatter<'_>, label: &str, zoom: &ZoomDiff) -> std::fmt::Result {
+    writeln!(f, "{:>5} {:>10} {:>10} {:>10} {:>10} {:>+14}",
+             label, zoom.added, zoom.removed, zoom.changed, zoom.unchanged, zoom.byte_delta())
+}
+
+/// Every addressed tile id of an archive, ascending
+fn tile_ids(reader: Arc<ArchiveReader>) -> impl Stream<Item = Result<u64>> + Unpin {
+    reader.entries()
+        .map_err(anyhow::Error::from)
+        .map_ok(|entry| {
+            let ids: Vec<Result<u64>> = entry.iter_coords().map(|id| Ok(id.value())).collect();
+            futures::stream::iter(ids)
+        })
+        .try_flatten()
+        .boxed()
+}
+
+/// A tile's stored (possibly compressed) bytes
+async fn read(reader: &ArchiveReader, id: u64) -> Result<Vec<u8>> {
+    let tile_id = TileId::new(id).context("Invalid tile id")?;
+    let data = reader.get_tile(tile_id).await?
+        .ok_or_else(|| anyhow::anyhow!("Archive entry {} has no data", id))?;
+    Ok(data.to_vec())
+}
+
+async fn decompressed(reader: &ArchiveReader, id: u64) -> Result<Vec<u8>> {
+    let tile_id = TileId::new(id).context("Invalid tile id")?;
+    Ok(reader.get_tile_decompressed(tile_id).await?.map(|data| data.to_vec()).unwrap_or_default())
+}
+
+/// Features per layer of a tile, empty if the archive lacks it
+async fn feature_counts(reader: &ArchiveReader, coord: &TileCoord, present: bool) -> Result<BTreeMap<String, u64>> {
+    if !present {
+        return Ok(BTreeMap::new());
+    }
+    let data = decompressed(reader, coord.to_tile_id().map_err(anyhow::Error::msg)?).await?;
+    let summary = DecodedTile::decode(coord.clone(), &data)?.summary();
+    Ok(summary.layers.into_iter().map(|layer| (layer.name, layer.features as u64)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geozero::mvt::{Message, Tile, tile};
+    use crate::Config;
+    use crate::tiles::TilesetMetadata;
+    use crate::tiles::pmtiles_writer::ArchiveBuilder;
+
+    /// A tile with `n` points in one layer
+    fn points(n: usize) -> Vec<u8> {
+        let feature = tile::Feature {
+            r#type: Some(tile::GeomType::Point as i32),
+            geometry: vec![9, 4096, 4096],
+            ..Default::default()
+        };
+        let layer = tile::Layer {
+            version: 2,
+            name: "points".to_string(),
+            features: vec![feature; n],
+            extent: Some(4096),
+            ..Default::default()
+        };
+        Tile { layers: vec![layer] }.encode_to_vec()
+    }
+
+    fn build(name: &str, tiles: &[(TileCoord, Vec<u8>)]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(name);
+        let mut builder = ArchiveBuilder::create(&path, &TilesetMetadata::from_config(&Config::default())).unwrap();
+        for (coord, data) in tiles {
+            builder.add(coord.to_tile_id().unwrap(), data).unwrap();
+        }
+        builder.finish().unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_diff_archives() {
+        let (a, b, c, d) = (TileCoord::new(0, 0, 0), TileCoord::new(1, 0, 0), TileCoord::new(1, 1, 0), TileCoord::new(1, 1, 1));
+        let before = build("test_diff_before.pmtiles", &[(a.clone(), points(1)), (b.clone(), points(1)), (c, points(1))]);
+        let after = build("test_diff_after.pmtiles", &[(a, points(1)), (b, points(2)), (d, points(1))]);
+
+        let diff = diff_archives(&before, &after, true).await.unwrap();
+        assert!(!diff.is_identical());
+        assert_eq!(diff.zooms.len(), 2);
+        assert_eq!((diff.zooms[0].zoom, diff.zooms[0].unchanged), (0, 1));
+        let z1 = &diff.zooms[1];
+        assert_eq!((z1.added, z1.removed, z1.changed, z1.unchanged), (1, 1, 1, 0));
+        assert!(z1.byte_delta() > 0);
+        assert_eq!(diff.layers.as_deref(), Some(&[LayerDelta {
+            name: "points".to_string(),
+            features_before: 2,
+            features_after: 3,
+            tiles: 3,
+        }][..]));
+
+        let json = serde_json::to_value(&diff).unwrap();
+        assert_eq!(json["total"]["added"], 1);
+
+        let same = diff_archives(&before, &before, false).await.unwrap();
+        assert!(same.is_identical());
+        assert_eq!(same.layers, None);
+
+        std::fs::remove_file(before).ok();
+        std::fs::remove_file(after).ok();
+    }
+}
@@ -0,0 +1,95 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+
+/// Exclusive OS file lock guarding a PMTiles archive against a second writer.
+///
+/// Commits replace the archive by rename, so the lock is taken on a sidecar
+/// `<archive>.lock` file rather than the archive itself. The lock file records
+/// the holder for error messages and the lock is released when dropped (or
+/// when the process dies).
+#[derive(Debug)]
+pub struct ArchiveLock {
+    path: PathBuf,
+    _file: File,
+}
+
+impl ArchiveLock {
+    /// Try to lock an archive; returns None if another process holds the lock
+    pub fn try_acquire(archive_path: &Path) -> Result<Option<Self>> {
+        let path = Self::lock_path(archive_path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .context("Failed to create PMTiles archive directory")?;
+        }
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to open lock file {}", path.display()))?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(None),
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("Failed to lock {}", path.display()));
+            }
+        }
+
+        file.set_len(0)?;
+        writeln!(file, "{}", holder_description())?;
+        file.sync_all()?;
+
+        Ok(Some(Self { path, _file: file }))
+    }
+
+    /// Who holds (or last held) the lock, as recorded in the lock file
+    pub fn holder(archive_path: &Path) -> Option<String> {
+        std::fs::read_to_string(Self::lock_path(archive_path))
+            .ok()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    }
+
+    /// Lock file path for an archive
+    pub fn lock_path(archive_path: &Path) -> PathBuf {
+        let mut name = archive_path.file_name().unwrap_or_default().to_os_string();
+        name.push(".lock");
+        archive_path.with_file_name(name)
+    }
+
+    /// Path of the held lock file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// "pid 1234 on host" for the current process
+fn holder_description() -> String {
+    let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "unknown host".to_string());
+    format!("pid {} on {}", std::process::id(), host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_is_exclusive_until_dropped() {
+        let archive = std::env::temp_dir().join("test_archive_lock.pmtiles");
+
+        let lock = ArchiveLock::try_acquire(&archive).unwrap().expect("first lock");
+        assert!(lock.path().ends_with("test_archive_lock.pmtiles.lock"));
+        assert!(ArchiveLock::try_acquire(&archive).unwrap().is_none());
+
+        let holder = ArchiveLock::holder(&archive).unwrap();
+        assert!(holder.starts_with(&format!("pid {}", std::process::id())));
+
+        drop(lock);
+        assert!(ArchiveLock::try_acquire(&archive).unwrap().is_some());
+    }
+}
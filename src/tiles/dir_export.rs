@@ -0,0 +1,268 @@
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use futures::TryStreamExt;
+use pmtiles::TileId;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use crate::{Config, TileCoord};
+use crate::rerender::ZoomRange;
+use super::mercator::BBox;
+use super::files::{gzip, remove_if_exists, replace_dir, sibling, write_atomic};
+use super::pmtiles_writer::{ArchiveReader, archive_metadata, open_archive};
+
+/// Tile URL written to `tiles.json` when none is given, relative to it
+pub const DEFAULT_TILES_URL: &str = "{z}/{x}/{y}.pbf";
+
+/// Log progress every this many tiles
+const LOG_EVERY: u64 = 1_000_000;
+
+/// Options of the export, kept in the directory for `update_dir`
+const OPTIONS_FILE: &str = "export.json";
+
+/// Which tiles a directory export covers and how they are written
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DirExportOptions {
+    /// Also write a gzipped `.pbf.gz` next to each tile
+    pub gzip: bool,
+    pub zooms: Option<ZoomRange>,
+    pub bbox: Option<BBox>,
+    /// Tile URL template for `tiles.json` (defaults to `DEFAULT_TILES_URL`)
+    pub tiles_url: Option<String>,
+}
+
+impl DirExportOptions {
+    fn includes(&self, coord: &TileCoord) -> bool {
+        self.zooms.is_none_or(|zooms| zooms.zooms().contains(&coord.z))
+            && self.bbox.as_ref().is_none_or(|bbox| coord.bounds_lonlat().intersects(bbox))
+    }
+}
+
+/// Tiles written and removed by a directory export
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DirExportStats {
+    pub written: u64,
+    pub removed: u64,
+}
+
+/// Write an archive's tiles to `{z}/{x}/{y}.pbf` files under a new
+/// directory, uncompressed, with a `tiles.json` describing them and the
+/// options recorded in `export.json`. The export
+/// goes to `<output>.tmp` and replaces `output` once complete; an existing
+/// `output` is only replaced if it is a previous export.
+pub async fn export_dir(archive: &Path, output: &Path, options: &DirExportOptions, config: &Config) -> Result<DirExportStats> {
+    if output.exists() && !is_export(output) {
+        anyhow::bail!("{} exists and is not an exported tile directory (no tiles.json); refusing to replace it", output.display());
+    }
+
+    let reader = open_archive(archive).await?;
+    let tmp_dir = sibling(output, ".tmp");
+    if tmp_dir.exists() {
+        std::fs::remove_dir_all(&tmp_dir)
+            .with_context(|| format!("Failed to remove {}", tmp_dir.display()))?;
+    }
+
+    let mut stats = DirExportStats::default();
+    let mut entries = reader.clone().entries();
+    while let Some(entry) = entries.try_next().await? {
+        for tile_id in entry.iter_coords() {
+            let coord = TileCoord::from_tile_id(tile_id.value()).map_err(anyhow::Error::msg)?;
+            if !options.includes(&coord) {
+                continue;
+            }
+            let data = read(&reader, &coord).await?
+                .ok_or_else(|| anyhow::anyhow!("Archive entry {} has no data", coord))?;
+            write_tile(&tmp_dir, &coord, &data, options.gzip)?;
+
+            stats.written += 1;
+            if stats.written.is_multiple_of(LOG_EVERY) {
+                info!("Exported {} tiles", stats.written);
+            }
+        }
+    }
+    write_tilejson(&reader, &tmp_dir, options, config).await?;
+    write_atomic(&tmp_dir.join(OPTIONS_FILE), &serde_json::to_vec_pretty(options)?)?;
+
+    replace_dir(&tmp_dir, output)?;
+    Ok(stats)
+}
+
+/// Bring an exported directory up to date with the archive for the given
+/// tiles: tiles in the archive are rewritten, tiles no longer in it are
+/// deleted, using the options of the export that created the directory.
+/// Files are replaced atomically, so the directory can be served while it
+/// is updated.
+pub async fn update_dir(archive: &Path, output: &Path, tiles: &[TileCoord], config: &Config) -> Result<DirExportStats> {
+    if !is_export(output) {
+        anyhow::bail!("{} is not an exported tile directory", output.display());
+    }
+    let options = &read_options(output)?;
+
+    let reader = open_archive(archive).await?;
+    let mut stats = DirExportStats::default();
+    for coord in tiles.iter().filter(|coord| options.includes(coord)) {
+        match read(&reader, coord).await? {
+            Some(data) => {
+                write_tile(output, coord, &data, options.gzip)?;
+                stats.written += 1;
+            }
+            None => {
+                let (pbf, gz) = tile_paths(output, coord);
+                if pbf.exists() || gz.exists() {
+                    remove_if_exists(&pbf)?;
+                    remove_if_exists(&gz)?;
+                    stats.removed += 1;
+                }
+            }
+        }
+    }
+    write_tilejson(&reader, output, options, config).await?;
+    Ok(stats)
+}
+
+/// Whether a directory looks like one `export_dir` wrote
+fn is_export(dir: &Path) -> bool {
+    dir.is_dir() && dir.join("tiles.json").is_file()
+}
+
+/// Options recorded by the export that wrote a directory
+pub fn read_options(dir: &Path) -> Result<DirExportOptions> {
+    let path = dir.join(OPTIONS_FILE);
+    let json = std::fs::read(&path)
+        .with_context(|| format!("Failed to read {}; run a full export first", path.display()))?;
+    serde_json::from_slice(&json).with_context(|| format!("Invalid {}", path.display()))
+}
+
+/// `{z}/{x}/{y}.pbf` and its `.pbf.gz` variant under a directory
+fn tile_paths(dir: &Path, coord: &TileCoord) -> (PathBuf, PathBuf) {
+    let pbf = dir.join(coord.z.to_string()).join(coord.x.to_string()).join(format!("{}.pbf", coord.y));
+    let gz = sibling(&pbf, ".gz");
+    (pbf, gz)
+}
+
+async fn read(reader: &ArchiveReader, coord: &TileCoord) -> Result<Option<Vec<u8>>> {
+    let tile_id = coord.to_tile_id().ok().and_then(TileId::new).ok_or_else(|| anyhow::anyhow!("Invalid tile {}", coord))?;
+    Ok(reader.get_tile_decompressed(tile_id).await?.map(|data| data.to_vec()))
+}
+
+/// Write a tile, and its gzipped variant or remove a stale one
+fn write_tile(dir: &Path, coord: &TileCoord, data: &[u8], gzipped: bool) -> Result<()> {
+    let (pbf, gz) = tile_paths(dir, coord);
+    if let Some(parent) = pbf.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    write_atomic(&pbf, data)?;
+    if gzipped {
+        write_atomic(&gz, &gzip(data)?)
+    } else {
+        remove_if_exists(&gz)
+    }
+}
+
+/// `tiles.json` for the archive, narrowed to the exported zooms and bounds
+async fn write_tilejson(reader: &ArchiveReader, dir: &Path, options: &DirExportOptions, config: &Config) -> Result<()> {
+    let mut metadata = archive_metadata(reader, config).await?;
+    if let Some(zooms) = options.zooms {
+        metadata.minzoom = metadata.minzoom.max(zooms.min);
+        metadata.maxzoom = metadata.maxzoom.min(zooms.max).max(metadata.minzoom);
+    }
+    if let Some(bbox) = &options.bbox {
+        let [min_lon, min_lat, max_lon, max_lat] = metadata.bounds;
+        metadata.bounds = [min_lon.max(bbox.min_x), min_lat.max(bbox.min_y), max_lon.min(bbox.max_x), max_lat.min(bbox.max_y)];
+    }
+
+    let tilejson = metadata.to_tilejson(options.tiles_url.as_deref().unwrap_or(DEFAULT_TILES_URL));
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    write_atomic(&dir.join("tiles.json"), &serde_json::to_vec_pretty(&tilejson)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+    use crate::tiles::TilesetMetadata;
+    use crate::tiles::pmtiles_writer::ArchiveBuilder;
+
+    fn build(path: &Path, tiles: &[(TileCoord, &[u8])]) {
+        let mut builder = ArchiveBuilder::create(path, &TilesetMetadata::from_config(&Config::default())).unwrap();
+        for (coord, data) in tiles {
+            builder.add(coord.to_tile_id().unwrap(), data).unwrap();
+        }
+        builder.finish().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_export_and_update_dir() {
+        let tmp = std::env::temp_dir();
+        let (archive, output) = (tmp.join("test_dir_export.pmtiles"), tmp.join("test_dir_export"));
+        let config = Config::default();
+        let (world, west, east) = (TileCoord::new(0, 0, 0), TileCoord::new(1, 0, 0), TileCoord::new(1, 1, 0));
+        build(&archive, &[(world.clone(), b"world"), (west.clone(), b"west"), (east.clone(), b"east")]);
+
+        let options = DirExportOptions {
+            gzip: true,
+            zooms: Some(ZoomRange::new(1, 1)),
+            bbox: Some(BBox::new(-180.0, 0.0, -10.0, 85.0)),
+            tiles_url: Some("https://tiles.example.com/{z}/{x}/{y}.pbf".to_string()),
+        };
+        let stats = export_dir(&archive, &output, &options, &config).await.unwrap();
+        assert_eq!(stats, DirExportStats { written: 1, removed: 0 });
+        assert_eq!(std::fs::read(output.join("1/0/0.pbf")).unwrap(), b"west");
+        let mut unzipped = Vec::new();
+        GzDecoder::new(std::fs::File::open(output.join("1/0/0.pbf.gz")).unwrap()).read_to_end(&mut unzipped).unwrap();
+        assert_eq!(unzipped, b"west");
+        assert!(!output.join("0/0/0.pbf").exists() && !output.join("1/1/0.pbf").exists());
+
+        let tilejson: serde_json::Value = serde_json::from_slice(&std::fs::read(output.join("tiles.json")).unwrap()).unwrap();
+        assert_eq!(tilejson["tiles"][0], "https://tiles.example.com/{z}/{x}/{y}.pbf");
+        assert_eq!((tilejson["minzoom"].as_u64(), tilejson["maxzoom"].as_u64()), (Some(1), Some(1)));
+        assert_eq!(tilejson["bounds"][2], -10.0);
+
+        // West is gone from the next archive and world and east changed, but
+        // the update keeps to the export's zoom and bbox
+        assert_eq!(read_options(&output).unwrap(), options);
+        build(&archive, &[(world.clone(), b"world 2"), (east.clone(), b"east 2"), (west.clone(), b"west 2")]);
+        let stats = update_dir(&archive, &output, &[world.clone(), west.clone(), east.clone()], &config).await.unwrap();
+        assert_eq!(stats, DirExportStats { written: 1, removed: 0 });
+        assert!(output.join("1/0/0.pbf.gz").exists());
+        assert!(!output.join("0/0/0.pbf").exists() && !output.join("1/1/0.pbf").exists());
+
+        build(&archive, &[(world.clone(), b"world 2"), (east.clone(), b"east")]);
+        let stats = update_dir(&archive, &output, &[world, west], &config).await.unwrap();
+        assert_eq!(stats, DirExportStats { written: 0, removed: 1 });
+        assert!(!output.join("1/0/0.pbf").exists() && !output.join("1/0/0.pbf.gz").exists());
+        let tilejson: serde_json::Value = serde_json::from_slice(&std::fs::read(output.join("tiles.json")).unwrap()).unwrap();
+        assert_eq!(tilejson["tiles"][0], "https://tiles.example.com/{z}/{x}/{y}.pbf");
+
+        std::fs::remove_dir_all(output).ok();
+        std::fs::remove_file(archive).ok();
+    }
+
+    #[tokio::test]
+    async fn test_force_only_replaces_exports() {
+        let tmp = std::env::temp_dir();
+        let (archive, output) = (tmp.join("test_dir_replace.pmtiles"), tmp.join("test_dir_replace"));
+        let config = Config::default();
+        build(&archive, &[(TileCoord::new(0, 0, 0), b"world")]);
+        std::fs::remove_dir_all(&output).ok();
+
+        // Not an export: left alone
+        std::fs::create_dir_all(&output).unwrap();
+        std::fs::write(output.join("notes.txt"), b"keep").unwrap();
+        assert!(export_dir(&archive, &output, &DirExportOptions::default(), &config).await.is_err());
+        assert!(output.join("notes.txt").exists());
+
+        // A previous export is swapped for the new one
+        std::fs::write(output.join("tiles.json"), b"{}").unwrap();
+        export_dir(&archive, &output, &DirExportOptions::default(), &config).await.unwrap();
+        assert!(!output.join("notes.txt").exists());
+        assert_eq!(std::fs::read(output.join("0/0/0.pbf")).unwrap(), b"world");
+        assert!(!sibling(&output, ".tmp").exists() && !sibling(&output, ".old").exists());
+
+        std::fs::remove_dir_all(output).ok();
+        std::fs::remove_file(archive).ok();
+    }
+}
@@ -0,0 +1,68 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+
+/// Gzip a tile at the default level
+pub fn gzip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// `<path><suffix>` in the same directory
+pub fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+pub fn remove_if_exists(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("Failed to remove {}", path.display())),
+    }
+}
+
+/// Write a file via `<path>.tmp` so readers never see it half written
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp_path = sibling(path, ".tmp");
+    std::fs::write(&tmp_path, data).with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+    std::fs::rename(&tmp_path, path).with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+/// Replace directory `target` with `replacement` and delete the old one.
+/// On Linux the two are exchanged atomically, so `target` never goes
+/// missing; elsewhere the old directory is moved aside first.
+pub fn replace_dir(replacement: &Path, target: &Path) -> Result<()> {
+    if !target.exists() {
+        return std::fs::rename(replacement, target)
+            .with_context(|| format!("Failed to create {}", target.display()));
+    }
+
+    let old = if exchange(replacement, target).is_ok() {
+        replacement.to_path_buf()
+    } else {
+        let old = sibling(target, ".old");
+        if old.exists() {
+            std::fs::remove_dir_all(&old).with_context(|| format!("Failed to remove {}", old.display()))?;
+        }
+        std::fs::rename(target, &old).with_context(|| format!("Failed to move {} aside", target.display()))?;
+        std::fs::rename(replacement, target).with_context(|| format!("Failed to replace {}", target.display()))?;
+        old
+    };
+    std::fs::remove_dir_all(&old).with_context(|| format!("Failed to remove {}", old.display()))
+}
+
+#[cfg(target_os = "linux")]
+fn exchange(a: &Path, b: &Path) -> std::io::Result<()> {
+    use rustix::fs::{CWD, RenameFlags, renameat_with};
+    renameat_with(CWD, a, CWD, b, RenameFlags::EXCHANGE).map_err(std::io::Error::from)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn exchange(_a: &Path, _b: &Path) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
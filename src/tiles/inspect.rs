@@ -0,0 +1,221 @@
+use std::collections::BTreeSet;
+use anyhow::{Context, Result};
+use geozero::GeozeroDatasource;
+use geozero::geojson::GeoJsonWriter;
+use geozero::mvt::{Message, Tile};
+use serde::Serialize;
+use serde_json::Value;
+use crate::TileCoord;
+use super::mercator::{tile_x_to_lon, tile_y_to_lat};
+
+/// MVT extent assumed when a layer does not declare one
+const DEFAULT_EXTENT: u32 = 4096;
+
+/// A decoded vector tile
+pub struct DecodedTile {
+    pub coord: TileCoord,
+    pub bytes: usize,
+    pub tile: Tile,
+}
+
+/// What a tile contains, per layer
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TileSummary {
+    pub bytes: usize,
+    pub layers: Vec<LayerSummary>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LayerSummary {
+    pub name: String,
+    pub features: usize,
+    /// Attribute keys used by the layer's features, sorted
+    pub keys: Vec<String>,
+}
+
+/// How a layer differs between two versions of a tile
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LayerChange {
+    pub name: String,
+    /// Feature counts before and after; None where the layer is missing
+    pub before: Option<usize>,
+    pub after: Option<usize>,
+    pub added_keys: Vec<String>,
+    pub removed_keys: Vec<String>,
+}
+
+impl DecodedTile {
+    /// Decode uncompressed MVT bytes
+    pub fn decode(coord: TileCoord, data: &[u8]) -> Result<Self> {
+        let tile = Tile::decode(data).with_context(|| format!("Tile {} is not a valid MVT", coord))?;
+        Ok(Self { coord, bytes: data.len(), tile })
+    }
+
+    /// Layer names, feature counts and attribute keys
+    pub fn summary(&self) -> TileSummary {
+        let layers = self.tile.layers.iter().map(|layer| {
+            let keys: BTreeSet<&str> = layer.features.iter()
+                .flat_map(|feature| feature.tags.chunks(2))
+                .filter_map(|tag| layer.keys.get(tag[0] as usize))
+                .map(String::as_str)
+                .collect();
+
+            LayerSummary {
+                name: layer.name.clone(),
+                features: layer.features.len(),
+                keys: keys.into_iter().map(str::to_string).collect(),
+            }
+        }).collect();
+
+        TileSummary { bytes: self.bytes, layers }
+    }
+
+    /// All features as one GeoJSON FeatureCollection in lon/lat, each
+    /// feature carrying its layer name in a `layer` member
+    pub fn to_geojson(&self) -> Result<Value> {
+        let mut features = Vec::new();
+
+        for layer in &self.tile.layers {
+            let mut json = Vec::new();
+            layer.clone().process(&mut GeoJsonWriter::new(&mut json))
+                .with_context(|| format!("Failed to decode layer {} of tile {}", layer.name, self.coord))?;
+            let mut collection: Value = serde_json::from_slice(&json)
+                .with_context(|| format!("Failed to convert layer {} to GeoJSON", layer.name))?;
+
+            let extent = f64::from(layer.extent.unwrap_or(DEFAULT_EXTENT));
+            let Some(layer_features) = collection["features"].as_array_mut() else {
+                continue;
+            };
+            for mut feature in layer_features.drain(..) {
+                to_lonlat(&mut feature["geometry"]["coordinates"], &self.coord, extent);
+                feature["layer"] = Value::String(layer.name.clone());
+                features.push(feature);
+            }
+        }
+
+        Ok(serde_json::json!({ "type": "FeatureCollection", "features": features }))
+    }
+}
+
+impl std::fmt::Display for TileSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} bytes, {} layers", self.bytes, self.layers.len())?;
+        for layer in &self.layers {
+            write!(f, "\n  {}: {} features, keys: {}", layer.name, layer.features, layer.keys.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Layers whose feature counts or keys differ between two summaries
+pub fn diff_layers(before: &TileSummary, after: &TileSummary) -> Vec<LayerChange> {
+    let names: BTreeSet<&str> = before.layers.iter().chain(&after.layers).map(|l| l.name.as_str()).collect();
+    let find = |summary: &TileSummary, name: &str| summary.layers.iter().find(|l| l.name == name).cloned();
+
+    names.into_iter().filter_map(|name| {
+        let (old, new) = (find(before, name), find(after, name));
+        let keys = |layer: &Option<LayerSummary>| -> BTreeSet<String> {
+            layer.iter().flat_map(|l| l.keys.iter().cloned()).collect()
+        };
+        let (old_keys, new_keys) = (keys(&old), keys(&new));
+
+        let change = LayerChange {
+            name: name.to_string(),
+            before: old.map(|l| l.features),
+            after: new.map(|l| l.features),
+            added_keys: new_keys.difference(&old_keys).cloned().collect(),
+            removed_keys: old_keys.difference(&new_keys).cloned().collect(),
+        };
+        let changed = change.before != change.after || !change.added_keys.is_empty() || !change.removed_keys.is_empty();
+        changed.then_some(change)
+    }).collect()
+}
+
+impl std::fmt::Display for LayerChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let count = |n: Option<usize>| n.map(|n| n.to_string()).unwrap_or_else(|| "-".to_string());
+        write!(f, "{}: {} -> {} features", self.name, count(self.before), count(self.after))?;
+        if !self.added_keys.is_empty() {
+            write!(f, ", added keys: {}", self.added_keys.join(", "))?;
+        }
+        if !self.removed_keys.is_empty() {
+            write!(f, ", removed keys: {}", self.removed_keys.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Convert nested GeoJSON coordinates from tile space to lon/lat in place
+fn to_lonlat(coordinates: &mut Value, coord: &TileCoord, extent: f64) {
+    let Some(items) = coordinates.as_array_mut() else {
+        return;
+    };
+
+    if let [Value::Number(x), Value::Number(y), ..] = items.as_slice() {
+        let x = f64::from(coord.x) + x.as_f64().unwrap_or_default() / extent;
+        let y = f64::from(coord.y) + y.as_f64().unwrap_or_default() / extent;
+        *coordinates = serde_json::json!([tile_x_to_lon(x, coord.z), tile_y_to_lat(y, coord.z)]);
+    } else {
+        items.iter_mut().for_each(|item| to_lonlat(item, coord, extent));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geozero::mvt::tile;
+
+    /// A tile with a named point at the center of the tile
+    fn sample_tile(name: &str) -> Vec<u8> {
+        let layer = tile::Layer {
+            version: 2,
+            name: "points".to_string(),
+            features: vec![tile::Feature {
+                id: Some(1),
+                tags: vec![0, 0],
+                r#type: Some(tile::GeomType::Point as i32),
+                // MoveTo(1) to (2048, 2048), zigzag encoded
+                geometry: vec![9, 4096, 4096],
+            }],
+            keys: vec!["name".to_string()],
+            values: vec![tile::Value { string_value: Some(name.to_string()), ..Default::default() }],
+            extent: Some(4096),
+        };
+        Tile { layers: vec![layer] }.encode_to_vec()
+    }
+
+    #[test]
+    fn test_summary_and_geojson() {
+        let data = sample_tile("Oslo");
+        let tile = DecodedTile::decode(TileCoord::new(0, 0, 0), &data).unwrap();
+
+        let summary = tile.summary();
+        assert_eq!(summary.bytes, data.len());
+        assert_eq!(summary.layers, vec![LayerSummary {
+            name: "points".to_string(),
+            features: 1,
+            keys: vec!["name".to_string()],
+        }]);
+
+        let geojson = tile.to_geojson().unwrap();
+        let feature = &geojson["features"][0];
+        assert_eq!(feature["layer"], "points");
+        assert_eq!(feature["properties"]["name"], "Oslo");
+        assert_eq!(feature["geometry"]["type"], "Point");
+        let lonlat = feature["geometry"]["coordinates"].as_array().unwrap();
+        assert!(lonlat[0].as_f64().unwrap().abs() < 1e-9 && lonlat[1].as_f64().unwrap().abs() < 1e-9);
+
+        assert!(DecodedTile::decode(TileCoord::new(0, 0, 0), b"\xff\xff").is_err());
+    }
+
+    #[test]
+    fn test_diff_layers() {
+        let before = DecodedTile::decode(TileCoord::new(0, 0, 0), &sample_tile("a")).unwrap().summary();
+        assert!(diff_layers(&before, &before).is_empty());
+
+        let empty = DecodedTile::decode(TileCoord::new(0, 0, 0), &[]).unwrap().summary();
+        let changes = diff_layers(&before, &empty);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].to_string(), "points: 1 -> - features, removed keys: name");
+    }
+}
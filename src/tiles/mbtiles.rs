@@ -0,0 +1,383 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use futures::TryStreamExt;
+use pmtiles::Compression;
+use rusqlite::{Connection, params};
+use tokio::sync::mpsc::Sender;
+use tracing::info;
+use crate::{Config, TileCoord};
+use crate::worker::ProgressTracker;
+use super::TilesetMetadata;
+use super::files::{gzip, remove_if_exists, sibling};
+use super::pmtiles_writer::{ArchiveBuilder, ArchiveReader, archive_metadata, archive_stats, open_archive};
+
+/// Tiles written per SQLite transaction
+const BATCH_TILES: usize = 10_000;
+
+/// Log progress every this many tiles
+const LOG_EVERY: u64 = 1_000_000;
+
+/// Write an archive's tiles to a new MBTiles file. Tiles are streamed in
+/// tile id order, stored gzipped with TMS rows as the MBTiles spec expects,
+/// and the tile index is built once all rows are in. SQLite runs on a
+/// blocking thread fed with batches of tiles.
+pub async fn export_mbtiles(archive: &Path, output: &Path, config: &Config) -> Result<u64> {
+    let reader = open_archive(archive).await?;
+    let metadata = archive_metadata(&reader, config).await?;
+    let total = archive_stats(archive)?.tile_count;
+
+    let tmp_path = sibling(output, ".tmp");
+    let (sender, mut receiver) = tokio::sync::mpsc::channel::<Vec<(TileCoord, Vec<u8>)>>(2);
+    let writer = tokio::task::spawn_blocking({
+        let tmp_path = tmp_path.clone();
+        move || -> Result<Connection> {
+            let mut conn = create_mbtiles(&tmp_path, &metadata)?;
+            while let Some(batch) = receiver.blocking_recv() {
+                insert_tiles(&mut conn, &batch)?;
+            }
+            Ok(conn)
+        }
+    });
+
+    let read = read_tiles(reader, total, sender).await;
+    let conn = writer.await.context("MBTiles writer panicked")??;
+    let tiles = read?;
+
+    let output = output.to_path_buf();
+    tokio::task::spawn_blocking(move || finish_mbtiles(conn, &tmp_path, &output))
+        .await
+        .context("MBTiles writer panicked")??;
+    Ok(tiles)
+}
+
+/// Stream an archive's tiles, gzipped, to the MBTiles writer in batches
+async fn read_tiles(reader: Arc<ArchiveReader>, total: u64, sender: Sender<Vec<(TileCoord, Vec<u8>)>>) -> Result<u64> {
+    let gzipped = reader.get_header().tile_compression == Compression::Gzip;
+    let mut progress = ProgressTracker::new(total);
+    let mut batch = Vec::with_capacity(BATCH_TILES);
+    let mut entries = reader.clone().entries();
+
+    while let Some(entry) = entries.try_next().await? {
+        for tile_id in entry.iter_coords() {
+            let coord = TileCoord::from_tile_id(tile_id.value()).map_err(anyhow::Error::msg)?;
+            let data = if gzipped {
+                reader.get_tile(tile_id).await?.map(|data| data.to_vec())
+            } else {
+                reader.get_tile_decompressed(tile_id).await?.map(|data| gzip(&data)).transpose()?
+            };
+            let data = data.ok_or_else(|| anyhow::anyhow!("Archive entry {} has no data", coord))?;
+            batch.push((coord, data));
+
+            if batch.len() >= BATCH_TILES {
+                let tiles = batch.len();
+                sender.send(std::mem::replace(&mut batch, Vec::with_capacity(BATCH_TILES))).await
+                    .map_err(|_| anyhow::anyhow!("MBTiles writer stopped"))?;
+                advance(&mut progress, tiles, "Exported");
+            }
+        }
+    }
+    progress.advance(batch.len() as u64);
+    sender.send(batch).await.map_err(|_| anyhow::anyhow!("MBTiles writer stopped"))?;
+    Ok(progress.done())
+}
+
+/// New, unindexed MBTiles file with its metadata rows
+fn create_mbtiles(path: &Path, metadata: &TilesetMetadata) -> Result<Connection> {
+    remove_if_exists(path)?;
+    let conn = Connection::open(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    conn.execute_batch(
+        "PRAGMA journal_mode = OFF;
+         PRAGMA synchronous = OFF;
+         CREATE TABLE metadata (name TEXT, value TEXT);
+         CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);",
+    )?;
+    for (name, value) in mbtiles_metadata(metadata)? {
+        conn.execute("INSERT INTO metadata (name, value) VALUES (?1, ?2)", [name, value])?;
+    }
+    Ok(conn)
+}
+
+/// Index a written MBTiles file and move it into place
+fn finish_mbtiles(conn: Connection, tmp_path: &Path, output: &Path) -> Result<()> {
+    conn.execute_batch(
+        "CREATE UNIQUE INDEX tile_index ON tiles (zoom_level, tile_column, tile_row);
+         CREATE UNIQUE INDEX name ON metadata (name);",
+    )
+    .context("Failed to index MBTiles tiles")?;
+    conn.close().map_err(|(_, e)| e)?;
+
+    std::fs::rename(tmp_path, output)
+        .with_context(|| format!("Failed to replace {}", output.display()))
+}
+
+/// Build a PMTiles archive from an MBTiles file. MBTiles rows have no
+/// useful order, so their tile ids are first sorted on disk in a scratch
+/// SQLite file next to the output, then the tiles are looked up one by one
+/// in that order and written clustered. Lookups go by rowid when `tiles` is
+/// a table, so they don't depend on the file having a tile index.
+pub fn import_mbtiles(input: &Path, output: &Path, config: &Config) -> Result<u64> {
+    if !input.exists() {
+        return Err(anyhow::anyhow!("{} does not exist", input.display()));
+    }
+
+    let order_path = sibling(output, ".import.sqlite");
+    remove_if_exists(&order_path)?;
+    let mut conn = Connection::open(&order_path)
+        .with_context(|| format!("Failed to create {}", order_path.display()))?;
+    conn.execute_batch(
+        "PRAGMA journal_mode = OFF;
+         PRAGMA synchronous = OFF;
+         CREATE TABLE ids (tile_id INTEGER PRIMARY KEY, zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, source_rowid INTEGER);",
+    )?;
+    conn.execute("ATTACH DATABASE ?1 AS mbtiles", [input.to_string_lossy()])
+        .with_context(|| format!("Failed to open MBTiles {}", input.display()))?;
+
+    // Deduplicated MBTiles files make `tiles` a view, which has no rowid
+    let is_table = conn.query_row(
+        "SELECT type = 'table' FROM mbtiles.sqlite_master WHERE name = 'tiles'", [], |row| row.get::<_, bool>(0),
+    )
+    .with_context(|| format!("{} has no tiles table", input.display()))?;
+
+    let metadata = {
+        let mut stmt = conn.prepare("SELECT name, value FROM mbtiles.metadata")
+            .with_context(|| format!("{} has no metadata table", input.display()))?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<rusqlite::Result<BTreeMap<_, _>>>()?;
+        metadata_from_mbtiles(&rows, config)
+    };
+
+    // Pass 1: tile ids, sorted by the ids table's primary key
+    let tx = conn.transaction()?;
+    let total = {
+        let rowid = if is_table { "rowid" } else { "NULL" };
+        let mut select = tx.prepare(&format!("SELECT zoom_level, tile_column, tile_row, {} FROM mbtiles.tiles", rowid))?;
+        let mut insert = tx.prepare(
+            "INSERT INTO ids (tile_id, zoom_level, tile_column, tile_row, source_rowid) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        let mut rows = select.query([])?;
+        let mut total = 0u64;
+        while let Some(row) = rows.next()? {
+            let (z, x, tms_y): (u8, u32, u32) = (row.get(0)?, row.get(1)?, row.get(2)?);
+            let coord = TileCoord::from_tms(z, x, tms_y)
+                .map_err(|e| anyhow::anyhow!("Invalid MBTiles tile {}/{}/{}: {}", z, x, tms_y, e))?;
+            let source_rowid: Option<i64> = row.get(3)?;
+            insert.execute(params![coord.to_tile_id().map_err(anyhow::Error::msg)? as i64, z, x, tms_y, source_rowid])
+                .with_context(|| format!("Duplicate MBTiles tile {}", coord))?;
+            total += 1;
+        }
+        total
+    };
+    tx.commit()?;
+    info!("Importing {} tiles from {}", total, input.display());
+
+    // Pass 2: tile data in tile id order
+    let mut builder = ArchiveBuilder::create(output, &metadata)?;
+    let mut progress = ProgressTracker::new(total);
+    {
+        let mut ids = conn.prepare("SELECT tile_id, zoom_level, tile_column, tile_row, source_rowid FROM ids ORDER BY tile_id")?;
+        let mut rows = ids.query([])?;
+        while let Some(row) = rows.next()? {
+            let tile_id: i64 = row.get(0)?;
+            let data: Vec<u8> = match row.get::<_, Option<i64>>(4)? {
+                Some(rowid) => conn.prepare_cached("SELECT tile_data FROM mbtiles.tiles WHERE rowid = ?1")?
+                    .query_row([rowid], |row| row.get(0))?,
+                None => conn.prepare_cached(
+                    "SELECT tile_data FROM mbtiles.tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+                )?
+                .query_row(params![row.get::<_, i64>(1)?, row.get::<_, i64>(2)?, row.get::<_, i64>(3)?], |row| row.get(0))?,
+            };
+            builder.add(tile_id as u64, &gunzip(&data)?)?;
+            advance(&mut progress, 1, "Imported");
+        }
+    }
+    let tiles = builder.finish()?;
+
+    drop(conn);
+    remove_if_exists(&order_path)?;
+    Ok(tiles)
+}
+
+/// MBTiles `metadata` rows for a tileset
+fn mbtiles_metadata(metadata: &TilesetMetadata) -> Result<Vec<(String, String)>> {
+    let [center_lon, center_lat, center_zoom] = metadata.center();
+    let join = |values: &[f64]| values.iter().map(f64::to_string).collect::<Vec<_>>().join(",");
+
+    Ok(vec![
+        ("name".to_string(), metadata.name.clone()),
+        ("format".to_string(), "pbf".to_string()),
+        ("type".to_string(), metadata.tileset_type.clone()),
+        ("version".to_string(), metadata.version.clone()),
+        ("attribution".to_string(), metadata.attribution.clone()),
+        ("minzoom".to_string(), metadata.minzoom.to_string()),
+        ("maxzoom".to_string(), metadata.maxzoom.to_string()),
+        ("bounds".to_string(), join(&metadata.bounds)),
+        ("center".to_string(), join(&[center_lon, center_lat, center_zoom])),
+        ("json".to_string(), serde_json::to_string(&serde_json::json!({ "vector_layers": metadata.vector_layers }))?),
+    ])
+}
+
+/// Tileset metadata from MBTiles `metadata` rows, falling back to the
+/// configuration for anything missing or unreadable
+fn metadata_from_mbtiles(rows: &BTreeMap<String, String>, config: &Config) -> TilesetMetadata {
+    let mut metadata = TilesetMetadata::from_config(config);
+    let get = |name: &str| rows.get(name).map(|value| value.trim());
+
+    if let Some(name) = get("name") {
+        metadata.name = name.to_string();
+    }
+    if let Some(attribution) = get("attribution") {
+        metadata.attribution = attribution.to_string();
+    }
+    if let Some(zoom) = get("minzoom").and_then(|v| v.parse().ok()) {
+        metadata.minzoom = zoom;
+    }
+    if let Some(zoom) = get("maxzoom").and_then(|v| v.parse().ok()) {
+        metadata.maxzoom = zoom;
+    }
+    if let Some(bounds) = get("bounds")
+        .and_then(|v| v.split(',').map(|n| n.trim().parse().ok()).collect::<Option<Vec<f64>>>())
+        .and_then(|v| <[f64; 4]>::try_from(v).ok())
+    {
+        metadata.bounds = bounds;
+    }
+    if let Some(layers) = get("json")
+        .and_then(|json| serde_json::from_str::<serde_json::Value>(json).ok())
+        .and_then(|json| serde_json::from_value(json["vector_layers"].clone()).ok())
+    {
+        metadata.vector_layers = layers;
+    }
+
+    metadata
+}
+
+fn insert_tiles(conn: &mut Connection, tiles: &[(TileCoord, Vec<u8>)]) -> Result<()> {
+    let tx = conn.transaction()?;
+    {
+        let mut insert = tx.prepare_cached(
+            "INSERT INTO tiles (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for (coord, data) in tiles {
+            insert.execute(params![coord.z, coord.x, coord.tms_y(), data])?;
+        }
+    }
+    tx.commit().context("Failed to write MBTiles tiles")
+}
+
+fn advance(progress: &mut ProgressTracker, tiles: usize, verb: &str) {
+    let before = progress.done();
+    progress.advance(tiles as u64);
+    if before / LOG_EVERY != progress.done() / LOG_EVERY {
+        info!("{} {}", verb, progress);
+    }
+}
+
+/// Undo the gzip most MBTiles writers apply to vector tiles
+fn gunzip(data: &[u8]) -> Result<Vec<u8>> {
+    if !data.starts_with(&[0x1f, 0x8b]) {
+        return Ok(data.to_vec());
+    }
+    let mut out = Vec::new();
+    GzDecoder::new(data).read_to_end(&mut out).context("Invalid gzipped tile")?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pmtiles::AsyncPmTilesReader;
+    use super::super::pmtiles_writer::read_tile;
+
+    #[tokio::test]
+    async fn test_mbtiles_round_trip() {
+        let dir = std::env::temp_dir();
+        let (archive, mbtiles, imported) = (
+            dir.join("test_mbtiles_source.pmtiles"),
+            dir.join("test_mbtiles_export.mbtiles"),
+            dir.join("test_mbtiles_import.pmtiles"),
+        );
+        let config = Config::default();
+        let mut metadata = TilesetMetadata::from_config(&config);
+        metadata.name = "Round trip".to_string();
+
+        let tiles = [(TileCoord::new(0, 0, 0), b"world".to_vec()), (TileCoord::new(1, 0, 0), b"north-west".to_vec())];
+        let mut builder = ArchiveBuilder::create(&archive, &metadata).unwrap();
+        for (coord, data) in &tiles {
+            builder.add(coord.to_tile_id().unwrap(), data).unwrap();
+        }
+        builder.finish().unwrap();
+
+        assert_eq!(export_mbtiles(&archive, &mbtiles, &config).await.unwrap(), 2);
+
+        // Rows use TMS y and gzipped data
+        let conn = Connection::open(&mbtiles).unwrap();
+        let data: Vec<u8> = conn.query_row(
+            "SELECT tile_data FROM tiles WHERE zoom_level = 1 AND tile_column = 0 AND tile_row = 1", [], |row| row.get(0),
+        ).unwrap();
+        assert_eq!(gunzip(&data).unwrap(), b"north-west");
+        let format: String = conn.query_row("SELECT value FROM metadata WHERE name = 'format'", [], |row| row.get(0)).unwrap();
+        assert_eq!(format, "pbf");
+        drop(conn);
+
+        assert_eq!(import_mbtiles(&mbtiles, &imported, &config).unwrap(), 2);
+        for (coord, data) in &tiles {
+            assert_eq!(read_tile(&imported, coord).await.unwrap().as_ref(), Some(data));
+        }
+        let reader = AsyncPmTilesReader::new_with_path(&imported).await.unwrap();
+        let metadata: TilesetMetadata = serde_json::from_str(&reader.get_metadata().await.unwrap()).unwrap();
+        assert_eq!(metadata.name, "Round trip");
+        assert_eq!(metadata.vector_layers.len(), 3);
+        assert!(!sibling(&imported, ".import.sqlite").exists());
+
+        for path in [archive, mbtiles, imported] {
+            std::fs::remove_file(path).ok();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_import_deduplicated_mbtiles() {
+        let dir = std::env::temp_dir();
+        let (mbtiles, imported) = (dir.join("test_mbtiles_dedup.mbtiles"), dir.join("test_mbtiles_dedup.pmtiles"));
+        remove_if_exists(&mbtiles).unwrap();
+
+        // `tiles` as a view over map and images, as deduplicating writers lay it out
+        let conn = Connection::open(&mbtiles).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE metadata (name TEXT, value TEXT);
+             CREATE TABLE map (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_id TEXT);
+             CREATE TABLE images (tile_id TEXT, tile_data BLOB);
+             CREATE VIEW tiles AS SELECT zoom_level, tile_column, tile_row, tile_data FROM map JOIN images USING (tile_id);
+             INSERT INTO images VALUES ('sea', X'736561');
+             INSERT INTO map VALUES (1, 0, 0, 'sea'), (1, 1, 1, 'sea'), (0, 0, 0, 'sea');",
+        ).unwrap();
+        drop(conn);
+
+        assert_eq!(import_mbtiles(&mbtiles, &imported, &Config::default()).unwrap(), 3);
+        for coord in [TileCoord::new(0, 0, 0), TileCoord::new(1, 0, 1), TileCoord::new(1, 1, 0)] {
+            assert_eq!(read_tile(&imported, &coord).await.unwrap().as_deref(), Some(&b"sea"[..]));
+        }
+
+        for path in [mbtiles, imported] {
+            std::fs::remove_file(path).ok();
+        }
+    }
+
+    #[test]
+    fn test_metadata_from_mbtiles() {
+        let rows = BTreeMap::from([
+            ("name".to_string(), "Oslo".to_string()),
+            ("minzoom".to_string(), "4".to_string()),
+            ("bounds".to_string(), "10.5, 59.8, 10.9, 60.0".to_string()),
+            ("json".to_string(), "not json".to_string()),
+        ]);
+        let metadata = metadata_from_mbtiles(&rows, &Config::default());
+        assert_eq!(metadata.name, "Oslo");
+        assert_eq!((metadata.minzoom, metadata.maxzoom), (4, 14));
+        assert_eq!(metadata.bounds, [10.5, 59.8, 10.9, 60.0]);
+        assert_eq!(metadata.vector_layers.len(), 3);
+    }
+}
@@ -0,0 +1,126 @@
+use std::f64::consts::PI;
+use serde::{Deserialize, Serialize};
+
+/// Half the width of the EPSG:3857 world in meters
+pub const ORIGIN_SHIFT: f64 = 20_037_508.342_789_244;
+
+/// Latitude limit of the WebMercator projection
+pub const MAX_LATITUDE: f64 = 85.051_128_779_806_59;
+
+/// Axis-aligned bounding box, either in lon/lat degrees or EPSG:3857 meters
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BBox {
+    pub min_x: f64,
+    pub min_y: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+}
+
+impl BBox {
+    pub fn new(min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Self {
+        Self { min_x, min_y, max_x, max_y }
+    }
+
+    /// The whole WebMercator world in lon/lat
+    pub fn world() -> Self {
+        Self::new(-180.0, -MAX_LATITUDE, 180.0, MAX_LATITUDE)
+    }
+
+    /// Parse from "min_x,min_y,max_x,max_y"
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let values = s.split(',')
+            .map(|v| v.trim().parse::<f64>().map_err(|_| format!("Invalid bbox value: {}", v)))
+            .collect::<Result<Vec<f64>, String>>()?;
+
+        if values.len() != 4 {
+            return Err(format!("Invalid bbox (expected min_x,min_y,max_x,max_y): {}", s));
+        }
+        if values[0] > values[2] || values[1] > values[3] {
+            return Err(format!("Invalid bbox (min greater than max): {}", s));
+        }
+
+        Ok(Self::new(values[0], values[1], values[2], values[3]))
+    }
+
+    /// Whether a point lies inside the box (edges inclusive)
+    pub fn contains_point(&self, x: f64, y: f64) -> bool {
+        x >= self.min_x && x <= self.max_x && y >= self.min_y && y <= self.max_y
+    }
+
+    /// Whether two boxes overlap (touching edges do not count)
+    pub fn intersects(&self, other: &BBox) -> bool {
+        self.min_x < other.max_x && other.min_x < self.max_x
+            && self.min_y < other.max_y && other.min_y < self.max_y
+    }
+}
+
+/// Longitude of the western edge of tile column `x` at zoom `z`
+pub fn tile_x_to_lon(x: f64, z: u8) -> f64 {
+    x / tiles_per_side(z) * 360.0 - 180.0
+}
+
+/// Latitude of the northern edge of tile row `y` at zoom `z`
+pub fn tile_y_to_lat(y: f64, z: u8) -> f64 {
+    let n = PI * (1.0 - 2.0 * y / tiles_per_side(z));
+    n.sinh().atan().to_degrees()
+}
+
+/// Fractional tile column for a longitude
+pub fn lon_to_tile_x(lon: f64, z: u8) -> f64 {
+    (lon + 180.0) / 360.0 * tiles_per_side(z)
+}
+
+/// Fractional tile row for a latitude (clamped to the projection limit)
+pub fn lat_to_tile_y(lat: f64, z: u8) -> f64 {
+    let lat = lat.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+    (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * tiles_per_side(z)
+}
+
+/// Project lon/lat degrees to EPSG:3857 meters
+pub fn lonlat_to_mercator(lon: f64, lat: f64) -> (f64, f64) {
+    let x = lon * ORIGIN_SHIFT / 180.0;
+    let lat = lat.clamp(-MAX_LATITUDE, MAX_LATITUDE);
+    let y = ((90.0 + lat) * PI / 360.0).tan().ln() / (PI / 180.0) * ORIGIN_SHIFT / 180.0;
+    (x, y)
+}
+
+/// Unproject EPSG:3857 meters to lon/lat degrees
+pub fn mercator_to_lonlat(x: f64, y: f64) -> (f64, f64) {
+    let lon = x / ORIGIN_SHIFT * 180.0;
+    let lat = (2.0 * (y / ORIGIN_SHIFT * PI).exp().atan() - PI / 2.0).to_degrees();
+    (lon, lat)
+}
+
+/// Number of tiles along one axis at zoom `z`
+pub fn tiles_per_side(z: u8) -> f64 {
+    (1u64 << z) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mercator_round_trip() {
+        let (x, y) = lonlat_to_mercator(10.75, 59.91);
+        let (lon, lat) = mercator_to_lonlat(x, y);
+        assert!((lon - 10.75).abs() < 1e-9);
+        assert!((lat - 59.91).abs() < 1e-9);
+
+        let (x, y) = lonlat_to_mercator(180.0, MAX_LATITUDE);
+        assert!((x - ORIGIN_SHIFT).abs() < 1e-6);
+        assert!((y - ORIGIN_SHIFT).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_bbox_parsing() {
+        let bbox = BBox::parse("10.5,59.8,10.9,60.0").unwrap();
+        assert_eq!(bbox, BBox::new(10.5, 59.8, 10.9, 60.0));
+        assert!(bbox.contains_point(10.75, 59.9));
+        assert!(!bbox.contains_point(11.0, 59.9));
+
+        assert!(BBox::parse("10.5,59.8,10.9").is_err());
+        assert!(BBox::parse("10.9,59.8,10.5,60.0").is_err());
+        assert!(BBox::parse("a,b,c,d").is_err());
+    }
+}
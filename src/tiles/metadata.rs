@@ -0,0 +1,181 @@
+use std::collections::BTreeMap;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use crate::Config;
+use crate::worker::tile_batch::ReplicationInfo;
+
+/// Attribute types used in `vector_layers` field descriptions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FieldType {
+    String,
+    Number,
+    Boolean,
+}
+
+/// One MVT layer as described in TileJSON `vector_layers`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VectorLayer {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub minzoom: u8,
+    pub maxzoom: u8,
+    pub fields: BTreeMap<String, FieldType>,
+}
+
+/// Metadata JSON stored in the PMTiles archive
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TilesetMetadata {
+    pub name: String,
+    pub attribution: String,
+    #[serde(rename = "type")]
+    pub tileset_type: String,
+    pub format: String,
+    pub version: String,
+    pub minzoom: u8,
+    pub maxzoom: u8,
+    /// [min_lon, min_lat, max_lon, max_lat]
+    pub bounds: [f64; 4],
+    pub vector_layers: Vec<VectorLayer>,
+    /// Latest OSM replication state reflected in the archive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replication_sequence: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replication_timestamp: Option<DateTime<Utc>>,
+}
+
+impl TilesetMetadata {
+    /// Metadata for the tileset described by the configuration
+    pub fn from_config(config: &Config) -> Self {
+        let tiles = &config.tiles;
+        let bounds = &tiles.bounds;
+
+        Self {
+            name: tiles.name.clone(),
+            attribution: tiles.attribution.clone(),
+            tileset_type: "baselayer".to_string(),
+            format: "pbf".to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            minzoom: tiles.min_zoom,
+            maxzoom: tiles.max_zoom,
+            bounds: [bounds.min_x, bounds.min_y, bounds.max_x, bounds.max_y],
+            vector_layers: vector_layers(tiles.min_zoom, tiles.max_zoom),
+            replication_sequence: None,
+            replication_timestamp: None,
+        }
+    }
+
+    /// Advance the recorded replication state; older information is ignored
+    pub fn record_replication(&mut self, replication: &ReplicationInfo) {
+        if replication.timestamp > self.replication_timestamp {
+            self.replication_timestamp = replication.timestamp;
+        }
+        if replication.sequence > self.replication_sequence {
+            self.replication_sequence = replication.sequence;
+        }
+    }
+
+    /// Replication state recorded in the metadata
+    pub fn replication(&self) -> ReplicationInfo {
+        ReplicationInfo {
+            sequence: self.replication_sequence,
+            timestamp: self.replication_timestamp,
+        }
+    }
+
+    /// Center of the bounds at a zoom suitable for a first look
+    pub fn center(&self) -> [f64; 3] {
+        let [min_lon, min_lat, max_lon, max_lat] = self.bounds;
+        let zoom = self.minzoom.max(self.maxzoom.min(2));
+        [(min_lon + max_lon) / 2.0, (min_lat + max_lat) / 2.0, f64::from(zoom)]
+    }
+
+    /// TileJSON 3.0 document for clients such as MapLibre
+    pub fn to_tilejson(&self, tiles_url: &str) -> serde_json::Value {
+        let mut tilejson = json!({
+            "tilejson": "3.0.0",
+            "tiles": [tiles_url],
+            "name": self.name,
+            "attribution": self.attribution,
+            "version": self.version,
+            "scheme": "xyz",
+            "minzoom": self.minzoom,
+            "maxzoom": self.maxzoom,
+            "bounds": self.bounds,
+            "center": self.center(),
+            "vector_layers": self.vector_layers,
+        });
+
+        if let Some(timestamp) = self.replication_timestamp {
+            tilejson["replication_timestamp"] = json!(timestamp);
+        }
+
+        tilejson
+    }
+}
+
+/// Layers rendered from the osm2pgsql tables
+pub fn vector_layers(min_zoom: u8, max_zoom: u8) -> Vec<VectorLayer> {
+    let layer = |id: &str, description: &str, minzoom: u8, fields: &[(&str, FieldType)]| VectorLayer {
+        id: id.to_string(),
+        description: Some(description.to_string()),
+        minzoom: minzoom.clamp(min_zoom, max_zoom),
+        maxzoom: max_zoom,
+        fields: fields.iter().map(|(name, kind)| (name.to_string(), *kind)).collect(),
+    };
+
+    use FieldType::{Number, String};
+    vec![
+        layer("polygons", "Areas from planet_osm_polygon", min_zoom, &[
+            ("osm_id", Number), ("name", String), ("landuse", String), ("natural", String),
+            ("building", String), ("leisure", String), ("amenity", String), ("waterway", String),
+            ("boundary", String), ("admin_level", String), ("way_area", Number),
+        ]),
+        layer("lines", "Linear features from planet_osm_line", min_zoom, &[
+            ("osm_id", Number), ("name", String), ("highway", String), ("railway", String),
+            ("waterway", String), ("ref", String), ("bridge", String), ("tunnel", String),
+            ("layer", String),
+        ]),
+        layer("points", "Points of interest and places from planet_osm_point", min_zoom, &[
+            ("osm_id", Number), ("name", String), ("place", String), ("amenity", String),
+            ("shop", String), ("tourism", String), ("population", String),
+        ]),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_round_trip_and_replication() {
+        let mut metadata = TilesetMetadata::from_config(&Config::default());
+        assert_eq!((metadata.minzoom, metadata.maxzoom), (0, 14));
+        assert_eq!(metadata.vector_layers.len(), 3);
+
+        let newer: DateTime<Utc> = "2026-10-18T10:00:00Z".parse().unwrap();
+        let older: DateTime<Utc> = "2026-10-18T09:00:00Z".parse().unwrap();
+        metadata.record_replication(&ReplicationInfo { sequence: Some(7), timestamp: Some(newer) });
+        metadata.record_replication(&ReplicationInfo { sequence: Some(6), timestamp: Some(older) });
+        metadata.record_replication(&ReplicationInfo::default());
+        assert_eq!(metadata.replication(), ReplicationInfo { sequence: Some(7), timestamp: Some(newer) });
+
+        let json = serde_json::to_string(&metadata).unwrap();
+        assert!(json.contains(r#""type":"baselayer""#));
+        assert_eq!(serde_json::from_str::<TilesetMetadata>(&json).unwrap(), metadata);
+    }
+
+    #[test]
+    fn test_tilejson_document() {
+        let metadata = TilesetMetadata::from_config(&Config::default());
+        let tilejson = metadata.to_tilejson("https://tiles.example/{z}/{x}/{y}.mvt");
+
+        assert_eq!(tilejson["tilejson"], "3.0.0");
+        assert_eq!(tilejson["tiles"][0], "https://tiles.example/{z}/{x}/{y}.mvt");
+        assert_eq!(tilejson["maxzoom"], 14);
+        assert_eq!(tilejson["vector_layers"][1]["id"], "lines");
+        assert_eq!(tilejson["vector_layers"][1]["fields"]["highway"], "String");
+        assert_eq!(tilejson["center"].as_array().unwrap().len(), 3);
+    }
+}
@@ -1,5 +1,16 @@
+pub mod archive_diff;
+pub mod archive_lock;
+pub mod dir_export;
+pub mod files;
+pub mod inspect;
+pub mod mercator;
+pub mod mbtiles;
+pub mod metadata;
 pub mod mvt_generator;
 pub mod pmtiles_writer;
 
+pub use archive_lock::ArchiveLock;
+pub use inspect::DecodedTile;
+pub use metadata::TilesetMetadata;
 pub use mvt_generator::MvtGenerator;
 pub use pmtiles_writer::PmtilesWriter; 
\ No newline at end of file
@@ -1,8 +1,10 @@
 use anyhow::Result;
 use crate::{TileCoord, Config};
 use crate::database::DatabasePool;
+use crate::metrics::metrics;
 
 /// MVT (Mapbox Vector Tiles) generator
+#[allow(dead_code)] // Used once PostGIS rendering is implemented
 pub struct MvtGenerator {
     database: DatabasePool,
     config: Config,
@@ -22,7 +24,7 @@ impl MvtGenerator {
         // 2. Query geometries from planet_osm_* tables
         // 3. Use geozero + mvt crates to generate the tile
         
-        tracing::debug!("Generating MVT tile for {}", coord.to_string());
+        tracing::debug!("Generating MVT tile for {}", coord);
         
         // Placeholder implementation
         Ok(vec![])
@@ -33,12 +35,15 @@ impl MvtGenerator {
         let mut results = Vec::new();
         
         for coord in coords {
+            let started = std::time::Instant::now();
             match self.generate_tile(coord).await {
                 Ok(tile_data) => {
+                    metrics().tile_rendered(coord.z, started.elapsed().as_secs_f64(), tile_data.len());
                     results.push((coord.clone(), tile_data));
                 }
                 Err(e) => {
-                    tracing::error!("Failed to generate tile {}: {}", coord.to_string(), e);
+                    metrics().tile_failed(coord.z);
+                    tracing::error!("Failed to generate tile {}: {}", coord, e);
                     // Continue with other tiles instead of failing the entire batch
                 }
             }
@@ -1,10 +1,37 @@
-use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anyhow::{Context, Result};
+use futures::TryStreamExt;
+use pmtiles::{AsyncPmTilesReader, HashMapCache, MmapBackend, PmTilesStreamWriter, PmTilesWriter, TileId, TileType};
 use crate::{TileCoord, Config};
+use crate::worker::tile_batch::ReplicationInfo;
+use super::{ArchiveLock, TilesetMetadata};
 
 /// PMTiles archive writer for incremental updates
+///
+/// PMTiles archives are immutable once finalized, so each commit merges the
+/// existing archive with the updated tiles into a temporary file and atomically
+/// renames it over the old archive. Readers holding the previous file keep a
+/// consistent view until they reopen it.
+///
+/// Writing requires the archive's exclusive file lock, which is taken on the
+/// first commit (or explicitly with `lock`) and held until `unlock` or drop.
 pub struct PmtilesWriter {
-    archive_path: std::path::PathBuf,
+    archive_path: PathBuf,
     config: Config,
+    lock: Option<ArchiveLock>,
+    replication: ReplicationInfo,
+}
+
+/// Counters for a single archive commit
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CommitStats {
+    pub added: u64,
+    pub replaced: u64,
+    pub removed: u64,
+    pub unchanged: u64,
 }
 
 impl PmtilesWriter {
@@ -13,49 +40,354 @@ impl PmtilesWriter {
         Self {
             archive_path: config.files.pmtiles_archive_path.clone(),
             config,
+            lock: None,
+            replication: ReplicationInfo::default(),
         }
     }
 
-    /// Write a batch of tiles to the PMTiles archive
-    pub async fn write_tiles(&mut self, tiles: &[(TileCoord, Vec<u8>)]) -> Result<()> {
-        tracing::info!("Writing {} tiles to PMTiles archive: {}", 
-                      tiles.len(), self.archive_path.display());
+    /// Replication state of the tiles being written, recorded in the archive
+    /// metadata on the next commit (older states never overwrite newer ones)
+    pub fn set_replication(&mut self, replication: ReplicationInfo) {
+        self.replication = replication;
+    }
 
-        // TODO: Implement actual PMTiles writing using pmtiles-rs
-        // This would involve:
-        // 1. Open/create the PMTiles archive
-        // 2. Write tile data in append mode
-        // 3. Update metadata and directory structure
-        
-        for (coord, _data) in tiles {
-            tracing::debug!("Writing tile {} to archive", coord.to_string());
+    /// Take the archive's exclusive file lock, failing if another instance holds it
+    pub fn lock(&mut self) -> Result<()> {
+        if self.lock.is_some() {
+            return Ok(());
         }
 
-        Ok(())
+        match ArchiveLock::try_acquire(&self.archive_path)? {
+            Some(lock) => {
+                tracing::info!("Locked PMTiles archive via {}", lock.path().display());
+                self.lock = Some(lock);
+                Ok(())
+            }
+            None => Err(anyhow::anyhow!(
+                "PMTiles archive {} is locked by another jvt instance ({}); refusing to write",
+                self.archive_path.display(),
+                ArchiveLock::holder(&self.archive_path).unwrap_or_else(|| "unknown holder".to_string()),
+            )),
+        }
+    }
+
+    /// Release the archive's file lock
+    pub fn unlock(&mut self) {
+        if self.lock.take().is_some() {
+            tracing::info!("Released PMTiles archive lock for {}", self.archive_path.display());
+        }
+    }
+
+    /// Write a batch of tiles to the PMTiles archive as one atomic commit.
+    /// Tiles with empty data are removed from the archive.
+    pub async fn write_tiles(&mut self, tiles: &[(TileCoord, Vec<u8>)]) -> Result<CommitStats> {
+        if tiles.is_empty() {
+            return Ok(CommitStats::default());
+        }
+        tracing::info!("Writing {} tiles to PMTiles archive: {}",
+                      tiles.len(), self.archive_path.display());
+        self.lock()?;
+
+        let updates: BTreeMap<u64, Vec<u8>> = tiles
+            .iter()
+            .map(|(coord, data)| Ok((coord.to_tile_id().map_err(anyhow::Error::msg)?, data.clone())))
+            .collect::<Result<_>>()?;
+
+        let merge = ArchiveMerge {
+            archive_path: self.archive_path.clone(),
+            tmp_path: self.temp_path(),
+            metadata: TilesetMetadata::from_config(&self.config),
+            replication: self.replication.clone(),
+        };
+        // Merging, compressing and syncing the archive is blocking file I/O,
+        // so it runs on the blocking pool rather than a runtime worker
+        let runtime = tokio::runtime::Handle::current();
+        let stats = tokio::task::spawn_blocking(move || runtime.block_on(merge.commit(updates)))
+            .await
+            .context("PMTiles commit task failed")??;
+
+        tracing::info!("Committed PMTiles archive: {} added, {} replaced, {} removed, {} unchanged",
+                      stats.added, stats.replaced, stats.removed, stats.unchanged);
+
+        Ok(stats)
+    }
+
+    fn temp_path(&self) -> PathBuf {
+        let mut name = self.archive_path.file_name().unwrap_or_default().to_os_string();
+        name.push(".tmp");
+        self.archive_path.with_file_name(name)
     }
 
     /// Get statistics about the PMTiles archive
     pub async fn get_stats(&self) -> Result<ArchiveStats> {
-        let metadata = std::fs::metadata(&self.archive_path).ok();
-        
-        Ok(ArchiveStats {
-            file_size: metadata.as_ref().map(|m| m.len()).unwrap_or(0),
-            tile_count: 0, // TODO: Read from PMTiles metadata
-            last_modified: metadata.and_then(|m| m.modified().ok()),
-        })
+        archive_stats(&self.archive_path)
     }
 
     /// Check if the archive exists and is valid
     pub fn validate_archive(&self) -> Result<bool> {
-        if !self.archive_path.exists() {
-            tracing::info!("PMTiles archive does not exist, will be created: {}", 
+        let exists = check_archive(&self.archive_path)?;
+        if !exists {
+            tracing::info!("PMTiles archive does not exist, will be created: {}",
                           self.archive_path.display());
-            return Ok(false);
         }
+        Ok(exists)
+    }
+}
+
+/// PMTiles v3 fixed header length
+const HEADER_LEN: usize = 127;
+
+/// Check an archive's header: Ok(false) if it does not exist, an error if it
+/// is not a PMTiles v3 archive
+pub fn check_archive(path: &Path) -> Result<bool> {
+    Ok(read_header(path)?.is_some())
+}
+
+/// Size and tile count of an archive (zeros if it does not exist yet)
+pub fn archive_stats(path: &Path) -> Result<ArchiveStats> {
+    let Some(header) = read_header(path)? else {
+        return Ok(ArchiveStats { file_size: 0, tile_count: 0, last_modified: None });
+    };
+    let metadata = std::fs::metadata(path)?;
+
+    // Number of addressed tiles, at offset 72 of the v3 header
+    let tile_count = u64::from_le_bytes(header[72..80].try_into().expect("8 byte slice"));
+
+    Ok(ArchiveStats {
+        file_size: metadata.len(),
+        tile_count,
+        last_modified: metadata.modified().ok(),
+    })
+}
+
+/// Memory mapped reader used by the archive tools
+pub type ArchiveReader = AsyncPmTilesReader<MmapBackend, HashMapCache>;
 
-        // TODO: Validate PMTiles format
-        Ok(true)
+/// Open an archive for reading
+pub async fn open_archive(path: &Path) -> Result<Arc<ArchiveReader>> {
+    let reader = AsyncPmTilesReader::new_with_cached_path(HashMapCache::default(), path)
+        .await
+        .with_context(|| format!("Failed to open PMTiles archive {}", path.display()))?;
+    Ok(Arc::new(reader))
+}
+
+/// An archive's tileset metadata; archives not written by jvt get the
+/// configured tileset with the zooms and bounds from their header
+pub async fn archive_metadata(reader: &ArchiveReader, config: &Config) -> Result<TilesetMetadata> {
+    if let Ok(metadata) = serde_json::from_str::<TilesetMetadata>(&reader.get_metadata().await?) {
+        return Ok(metadata);
     }
+
+    let header = reader.get_header();
+    let mut metadata = TilesetMetadata::from_config(config);
+    metadata.minzoom = header.min_zoom;
+    metadata.maxzoom = header.max_zoom;
+    metadata.bounds = [header.min_longitude, header.min_latitude, header.max_longitude, header.max_latitude]
+        .map(f64::from);
+    Ok(metadata)
+}
+
+/// A tile's uncompressed data from an archive; None if it is not stored
+pub async fn read_tile(path: &Path, coord: &TileCoord) -> Result<Option<Vec<u8>>> {
+    let reader = AsyncPmTilesReader::new_with_path(path)
+        .await
+        .with_context(|| format!("Failed to open PMTiles archive {}", path.display()))?;
+    let tile_id = coord.to_tile_id().ok().and_then(TileId::new)
+        .ok_or_else(|| anyhow::anyhow!("Invalid tile {}", coord))?;
+
+    Ok(reader.get_tile_decompressed(tile_id).await?.map(|data| data.to_vec()))
+}
+
+/// Read and verify an archive's fixed header
+fn read_header(path: &Path) -> Result<Option<[u8; HEADER_LEN]>> {
+    use std::io::Read;
+
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to open {}", path.display())),
+    };
+
+    let mut header = [0u8; HEADER_LEN];
+    file.read_exact(&mut header)
+        .with_context(|| format!("{} is too short to be a PMTiles archive", path.display()))?;
+
+    if &header[..7] != b"PMTiles" || header[7] != 3 {
+        return Err(anyhow::anyhow!("{} is not a PMTiles v3 archive", path.display()));
+    }
+
+    Ok(Some(header))
+}
+
+/// One archive commit, owning what it needs to run off the async runtime
+struct ArchiveMerge {
+    archive_path: PathBuf,
+    tmp_path: PathBuf,
+    metadata: TilesetMetadata,
+    replication: ReplicationInfo,
+}
+
+impl ArchiveMerge {
+    /// Build the merged archive next to the old one, make it durable and
+    /// rename it into place
+    async fn commit(self, updates: BTreeMap<u64, Vec<u8>>) -> Result<CommitStats> {
+        let stats = self.merge_into(&self.tmp_path, updates).await
+            .with_context(|| format!("Failed to build archive {}", self.tmp_path.display()))?;
+
+        // Make the new archive durable before it replaces the old one
+        File::open(&self.tmp_path)
+            .and_then(|file| file.sync_all())
+            .with_context(|| format!("Failed to sync {}", self.tmp_path.display()))?;
+        std::fs::rename(&self.tmp_path, &self.archive_path)
+            .with_context(|| format!("Failed to replace {}", self.archive_path.display()))?;
+
+        Ok(stats)
+    }
+
+    /// Stream the current archive into `out_path`, substituting updated tiles
+    async fn merge_into(&self, out_path: &Path, mut updates: BTreeMap<u64, Vec<u8>>) -> Result<CommitStats> {
+        if let Some(parent) = out_path.parent() {
+            std::fs::create_dir_all(parent)
+                .context("Failed to create PMTiles archive directory")?;
+        }
+
+        let existing = if self.archive_path.exists() {
+            Some(Arc::new(
+                AsyncPmTilesReader::new_with_cached_path(HashMapCache::default(), &self.archive_path)
+                    .await
+                    .context("Failed to open existing PMTiles archive")?,
+            ))
+        } else {
+            None
+        };
+
+        // Carry the replication state forward from the previous version
+        let mut metadata = self.metadata.clone();
+        if let Some(reader) = &existing
+            && let Ok(json) = reader.get_metadata().await
+            && let Ok(previous) = serde_json::from_str::<TilesetMetadata>(&json)
+        {
+            metadata.record_replication(&previous.replication());
+        }
+        metadata.record_replication(&self.replication);
+
+        let mut out = create_archive(out_path, &metadata)?;
+        let mut stats = CommitStats::default();
+
+        if let Some(reader) = existing {
+            let mut entries = reader.clone().entries();
+            while let Some(entry) = entries.try_next().await? {
+                for tile_id in entry.iter_coords() {
+                    let id = tile_id.value();
+
+                    // Keep output in tile id order by adding new tiles that sort first
+                    while let Some((&next_id, _)) = updates.first_key_value()
+                        && next_id < id
+                    {
+                        let (_, data) = updates.pop_first().unwrap_or_default();
+                        add_tile(&mut out, next_id, &data)?;
+                        stats.added += u64::from(!data.is_empty());
+                    }
+
+                    if let Some(data) = updates.remove(&id) {
+                        add_tile(&mut out, id, &data)?;
+                        if data.is_empty() {
+                            stats.removed += 1;
+                        } else {
+                            stats.replaced += 1;
+                        }
+                    } else {
+                        let data = reader.get_tile_decompressed(tile_id).await?
+                            .ok_or_else(|| anyhow::anyhow!("Archive entry {} has no data", id))?;
+                        add_tile(&mut out, id, &data)?;
+                        stats.unchanged += 1;
+                    }
+                }
+            }
+        }
+
+        for (id, data) in updates {
+            add_tile(&mut out, id, &data)?;
+            stats.added += u64::from(!data.is_empty());
+        }
+
+        out.finalize()?;
+        Ok(stats)
+    }
+}
+
+/// Start a new archive at `path` described by `metadata`
+fn create_archive(path: &Path, metadata: &TilesetMetadata) -> Result<PmTilesStreamWriter<File>> {
+    let [min_lon, min_lat, max_lon, max_lat] = metadata.bounds;
+    let [center_lon, center_lat, center_zoom] = metadata.center();
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+
+    Ok(PmTilesWriter::new(TileType::Mvt)
+        .min_zoom(metadata.minzoom)
+        .max_zoom(metadata.maxzoom)
+        .bounds(min_lon as f32, min_lat as f32, max_lon as f32, max_lat as f32)
+        .center(center_lon as f32, center_lat as f32)
+        .center_zoom(center_zoom as u8)
+        .metadata(&serde_json::to_string(metadata)?)
+        .create(file)?)
+}
+
+/// Builds a complete archive in one pass from tiles in ascending tile id
+/// order, for seeding and imports. The archive is written next to `path` and
+/// renamed into place by `finish`.
+pub struct ArchiveBuilder {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    out: PmTilesStreamWriter<File>,
+    tiles: u64,
+}
+
+impl ArchiveBuilder {
+    /// Start building an archive that will replace `path`
+    pub fn create(path: &Path, metadata: &TilesetMetadata) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .context("Failed to create PMTiles archive directory")?;
+        }
+
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".build");
+        let tmp_path = path.with_file_name(name);
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            out: create_archive(&tmp_path, metadata)?,
+            tmp_path,
+            tiles: 0,
+        })
+    }
+
+    /// Add a tile; ids must ascend
+    pub fn add(&mut self, tile_id: u64, data: &[u8]) -> Result<()> {
+        add_tile(&mut self.out, tile_id, data)?;
+        self.tiles += u64::from(!data.is_empty());
+        Ok(())
+    }
+
+    /// Finalize, sync and move the archive into place, returning the tile count
+    pub fn finish(self) -> Result<u64> {
+        self.out.finalize()?;
+        File::open(&self.tmp_path)
+            .and_then(|file| file.sync_all())
+            .with_context(|| format!("Failed to sync {}", self.tmp_path.display()))?;
+        std::fs::rename(&self.tmp_path, &self.path)
+            .with_context(|| format!("Failed to replace {}", self.path.display()))?;
+        Ok(self.tiles)
+    }
+}
+
+/// Add a tile by PMTiles id (empty data is dropped by the writer)
+fn add_tile(out: &mut PmTilesStreamWriter<File>, tile_id: u64, data: &[u8]) -> Result<()> {
+    let tile_id = TileId::new(tile_id)
+        .ok_or_else(|| anyhow::anyhow!("Invalid PMTiles tile id: {}", tile_id))?;
+    out.add_tile(tile_id.into(), data)?;
+    Ok(())
 }
 
 #[derive(Debug)]
@@ -67,7 +399,134 @@ pub struct ArchiveStats {
 
 impl std::fmt::Display for ArchiveStats {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "PMTiles Archive: {} bytes, {} tiles", 
+        write!(f, "PMTiles Archive: {} bytes, {} tiles",
                self.file_size, self.tile_count)
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config(name: &str) -> Config {
+        let mut config = Config::default();
+        config.files.pmtiles_archive_path = std::env::temp_dir().join(name);
+        std::fs::remove_file(&config.files.pmtiles_archive_path).ok();
+        config
+    }
+
+    #[tokio::test]
+    async fn test_commits_merge_with_existing_archive() {
+        let config = test_config("test_writer_merge.pmtiles");
+        let path = config.files.pmtiles_archive_path.clone();
+        let mut writer = PmtilesWriter::new(config);
+
+        let a = TileCoord::new(14, 8234, 5425);
+        let b = TileCoord::new(10, 515, 339);
+        let c = TileCoord::new(0, 0, 0);
+
+        let stats = writer.write_tiles(&[(a.clone(), b"a1".to_vec()), (b.clone(), b"b1".to_vec())]).await.unwrap();
+        assert_eq!(stats.added, 2);
+
+        // Second commit replaces one tile, removes another and adds a third
+        let stats = writer.write_tiles(&[
+            (a.clone(), b"a2".to_vec()),
+            (b.clone(), Vec::new()),
+            (c.clone(), b"c1".to_vec()),
+        ]).await.unwrap();
+        assert_eq!(stats, CommitStats { added: 1, replaced: 1, removed: 1, unchanged: 0 });
+
+        assert_eq!(read_tile(&path, &a).await.unwrap().as_deref(), Some(&b"a2"[..]));
+        assert_eq!(read_tile(&path, &b).await.unwrap(), None);
+        assert_eq!(read_tile(&path, &c).await.unwrap().as_deref(), Some(&b"c1"[..]));
+
+        // Untouched tiles survive later commits
+        let stats = writer.write_tiles(&[(b.clone(), b"b2".to_vec())]).await.unwrap();
+        assert_eq!(stats.unchanged, 2);
+        assert_eq!(read_tile(&path, &a).await.unwrap().as_deref(), Some(&b"a2"[..]));
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_writes_metadata_with_latest_replication() {
+        let config = test_config("test_writer_metadata.pmtiles");
+        let path = config.files.pmtiles_archive_path.clone();
+        let mut writer = PmtilesWriter::new(config);
+        let timestamp = "2026-10-18T10:00:00Z".parse().unwrap();
+
+        writer.set_replication(ReplicationInfo { sequence: Some(42), timestamp: Some(timestamp) });
+        writer.write_tiles(&[(TileCoord::new(0, 0, 0), b"t".to_vec())]).await.unwrap();
+
+        // A later commit without replication info keeps the recorded state
+        writer.set_replication(ReplicationInfo::default());
+        writer.write_tiles(&[(TileCoord::new(1, 0, 0), b"t".to_vec())]).await.unwrap();
+
+        let reader = AsyncPmTilesReader::new_with_path(&path).await.unwrap();
+        let metadata: TilesetMetadata = serde_json::from_str(&reader.get_metadata().await.unwrap()).unwrap();
+        assert_eq!(metadata.replication_sequence, Some(42));
+        assert_eq!(metadata.replication_timestamp, Some(timestamp));
+        assert_eq!(metadata.maxzoom, 14);
+        assert_eq!(metadata.vector_layers[0].id, "polygons");
+        assert_eq!(reader.get_header().max_zoom, 14);
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_check_archive_header() {
+        let config = test_config("test_writer_check.pmtiles");
+        let path = config.files.pmtiles_archive_path.clone();
+        assert!(!check_archive(&path).unwrap());
+
+        let mut writer = PmtilesWriter::new(config);
+        writer.write_tiles(&[(TileCoord::new(0, 0, 0), b"t".to_vec())]).await.unwrap();
+        assert!(writer.validate_archive().unwrap());
+
+        let stats = writer.get_stats().await.unwrap();
+        assert_eq!(stats.tile_count, 1);
+        assert!(stats.file_size > HEADER_LEN as u64);
+
+        std::fs::write(&path, b"not an archive").unwrap();
+        assert!(check_archive(&path).is_err());
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_refuses_to_write_when_locked_elsewhere() {
+        let config = test_config("test_writer_locked.pmtiles");
+        let path = config.files.pmtiles_archive_path.clone();
+
+        let _other = ArchiveLock::try_acquire(&path).unwrap().unwrap();
+        let mut writer = PmtilesWriter::new(config);
+
+        let err = writer.write_tiles(&[(TileCoord::new(0, 0, 0), b"t".to_vec())]).await.unwrap_err();
+        assert!(err.to_string().contains("locked by another jvt instance"));
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_archive_builder() {
+        let config = test_config("test_writer_builder.pmtiles");
+        let path = config.files.pmtiles_archive_path.clone();
+        let mut metadata = TilesetMetadata::from_config(&config);
+        metadata.maxzoom = 10;
+
+        let a = TileCoord::new(0, 0, 0);
+        let b = TileCoord::new(10, 515, 339);
+        let mut builder = ArchiveBuilder::create(&path, &metadata).unwrap();
+        builder.add(a.to_tile_id().unwrap(), b"a").unwrap();
+        builder.add(b.to_tile_id().unwrap(), b"b").unwrap();
+        assert!(!path.exists());
+        assert_eq!(builder.finish().unwrap(), 2);
+
+        assert_eq!(read_tile(&path, &a).await.unwrap().as_deref(), Some(&b"a"[..]));
+        assert_eq!(read_tile(&path, &b).await.unwrap().as_deref(), Some(&b"b"[..]));
+        assert_eq!(archive_stats(&path).unwrap().tile_count, 2);
+        let reader = AsyncPmTilesReader::new_with_path(&path).await.unwrap();
+        assert_eq!(reader.get_header().max_zoom, 10);
+
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,305 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use tracing::{info, warn};
+use crate::{Config, TileCoord};
+use crate::database::{RenderedTileStore, TileUpdatePublisher};
+use crate::tiles::{MvtGenerator, PmtilesWriter};
+use crate::tiles::pmtiles_writer::CommitStats;
+use super::{CommitLog, TileBatch, WorkerHealth};
+use super::progress::{format_duration, ProgressTracker};
+
+/// Executes tile batches in chunks. By default every rendered chunk is
+/// committed to the archive on its own, so a failure part way through a
+/// batch keeps the chunks before it. Since every commit rewrites the whole
+/// archive, `commit_bytes` can buffer chunks and commit once that many bytes
+/// are rendered; the buffer is also flushed before a failure is returned.
+/// Each commit is recorded in the `CommitLog`.
+///
+/// In multi-worker mode, workers without the writer role stage rendered
+/// chunks in the `rendered_tiles` table instead of touching the archive.
+pub struct BatchExecutor {
+    generator: MvtGenerator,
+    writer: PmtilesWriter,
+    staging: Option<RenderedTileStore>,
+    publisher: Option<TileUpdatePublisher>,
+    commit_log: Option<CommitLog>,
+    health: Option<WorkerHealth>,
+    chunk_size: usize,
+    commit_bytes: usize,
+}
+
+/// Outcome of executing a batch
+#[derive(Debug, Default, Clone)]
+pub struct BatchReport {
+    pub chunks: usize,
+    /// Archive commits made for the batch
+    pub commits: usize,
+    pub tiles_committed: u64,
+    pub tiles_failed: u64,
+}
+
+impl BatchExecutor {
+    /// Create a new batch executor
+    pub fn new(generator: MvtGenerator, writer: PmtilesWriter, config: &Config) -> Self {
+        Self {
+            generator,
+            writer,
+            staging: None,
+            publisher: None,
+            commit_log: None,
+            health: None,
+            chunk_size: config.worker.chunk_size.max(1),
+            commit_bytes: config.worker.commit_bytes,
+        }
+    }
+
+    /// Stage rendered chunks for the writer (`Some`) or commit them directly (`None`)
+    pub fn set_staging(&mut self, staging: Option<RenderedTileStore>) {
+        self.staging = staging;
+    }
+
+    /// Whether chunks go to staging rather than into the archive
+    pub fn is_staging(&self) -> bool {
+        self.staging.is_some()
+    }
+
+    /// Announce committed tiles to tile servers
+    pub fn with_publisher(mut self, publisher: TileUpdatePublisher) -> Self {
+        self.publisher = Some(publisher);
+        self
+    }
+
+    /// Record every commit in the audit table and change feed
+    pub fn with_commit_log(mut self, commit_log: CommitLog) -> Self {
+        self.commit_log = Some(commit_log);
+        self
+    }
+
+    /// Report chunk progress so long batches don't look stuck
+    pub fn with_health(mut self, health: WorkerHealth) -> Self {
+        self.health = Some(health);
+        self
+    }
+
+    /// Take the archive's file lock before acting as the writer
+    pub fn lock_archive(&mut self) -> Result<()> {
+        self.writer.lock()
+    }
+
+    /// Release the archive's file lock
+    pub fn unlock_archive(&mut self) {
+        self.writer.unlock();
+    }
+
+    /// Commit already rendered tiles of a batch to the archive, recording
+    /// the OSM data they reflect in the archive metadata, then announce and
+    /// record the commit. `started_at` is when work on the tiles began. A
+    /// tile given more than once (staged twice) is committed with its last
+    /// render.
+    pub async fn commit(&mut self, tiles: &[(TileCoord, Vec<u8>)], batch: &TileBatch, started_at: DateTime<Utc>) -> Result<CommitStats> {
+        let tiles = latest_renders(tiles);
+        self.writer.set_replication(batch.replication.clone());
+        let stats = self.writer.write_tiles(&tiles).await?;
+        let finished_at = Utc::now();
+
+        let coords: Vec<TileCoord> = tiles.iter().map(|(coord, _)| coord.clone()).collect();
+        self.publish(&coords).await;
+        if let Some(commit_log) = &self.commit_log {
+            commit_log.record(batch, &coords, started_at, finished_at).await
+                .context("Failed to record committed tiles")?;
+        }
+        Ok(stats)
+    }
+
+    /// Publish committed tiles; failures only cost clients a refresh, so they are logged
+    async fn publish(&self, coords: &[TileCoord]) {
+        let Some(publisher) = &self.publisher else {
+            return;
+        };
+
+        if let Err(e) = publisher.publish(coords).await {
+            warn!("Failed to publish {} updated tiles: {}", coords.len(), e);
+        }
+    }
+
+    /// Render a batch chunk by chunk, logging progress and ETA, and commit it
+    pub async fn execute(&mut self, batch: &TileBatch) -> Result<BatchReport> {
+        let chunks = batch.chunks(self.chunk_size);
+        let mut progress = ProgressTracker::new(batch.len() as u64);
+        let mut report = BatchReport::default();
+        let mut pending = Pending { tiles: Vec::new(), started_at: Utc::now() };
+
+        info!("Executing {} in {} chunks of up to {} tiles",
+              batch.summary(), chunks.len(), self.chunk_size);
+
+        for (index, chunk) in chunks.iter().enumerate() {
+            let result = self.execute_chunk(chunk, batch, &mut pending, &mut report).await
+                .with_context(|| format!(
+                    "Failed to execute chunk {}/{} ({} of {} tiles already rendered)",
+                    index + 1, chunks.len(), progress.done(), progress.total(),
+                ));
+            if let Err(e) = result {
+                // Keep what was rendered before the failure
+                if let Err(flush) = self.flush(&mut pending, batch, &mut report).await {
+                    warn!("Failed to commit tiles rendered before the failure: {:#}", flush);
+                }
+                return Err(e);
+            }
+
+            progress.advance(chunk.len() as u64);
+            if let Some(health) = &self.health {
+                health.heartbeat();
+            }
+            report.chunks += 1;
+
+            info!("Batch progress: chunk {}/{}, {}", index + 1, chunks.len(), progress);
+        }
+        self.flush(&mut pending, batch, &mut report).await?;
+
+        info!("Batch complete: {} tiles committed in {} commits, {} failed in {}",
+              report.tiles_committed, report.commits, report.tiles_failed, format_duration(progress.elapsed()));
+
+        Ok(report)
+    }
+
+    /// Render one chunk and stage or commit it, or buffer it for a later commit
+    async fn execute_chunk(
+        &mut self,
+        chunk: &[TileCoord],
+        batch: &TileBatch,
+        pending: &mut Pending,
+        report: &mut BatchReport,
+    ) -> Result<()> {
+        let rendered = self.generator.generate_tiles(chunk).await?;
+
+        let failed = (chunk.len() - rendered.len()) as u64;
+        if failed > 0 {
+            warn!("{} of {} tiles in the chunk failed to render", failed, chunk.len());
+        }
+        report.tiles_failed += failed;
+
+        if rendered.is_empty() {
+            return Ok(());
+        }
+        match &self.staging {
+            Some(staging) => {
+                staging.stage(&rendered, batch).await?;
+                report.tiles_committed += rendered.len() as u64;
+            }
+            None => {
+                pending.tiles.extend(rendered);
+                if pending.tiles.iter().map(|(_, data)| data.len()).sum::<usize>() >= self.commit_bytes {
+                    self.flush(pending, batch, report).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Commit buffered tiles, if any
+    async fn flush(&mut self, pending: &mut Pending, batch: &TileBatch, report: &mut BatchReport) -> Result<()> {
+        if pending.tiles.is_empty() {
+            return Ok(());
+        }
+        let tiles = std::mem::take(&mut pending.tiles);
+        let started_at = std::mem::replace(&mut pending.started_at, Utc::now());
+        self.commit(&tiles, batch, started_at).await?;
+        report.commits += 1;
+        report.tiles_committed += tiles.len() as u64;
+        Ok(())
+    }
+}
+
+/// The last render of each tile, in their original order
+fn latest_renders(tiles: &[(TileCoord, Vec<u8>)]) -> Cow<'_, [(TileCoord, Vec<u8>)]> {
+    let mut seen = HashSet::with_capacity(tiles.len());
+    if tiles.iter().all(|(coord, _)| seen.insert(coord)) {
+        return Cow::Borrowed(tiles);
+    }
+
+    seen.clear();
+    let mut latest: Vec<_> = tiles.iter().rev().filter(|(coord, _)| seen.insert(coord)).cloned().collect();
+    latest.reverse();
+    Cow::Owned(latest)
+}
+
+/// Rendered tiles waiting for the next commit, and when rendering them began
+struct Pending {
+    tiles: Vec<(TileCoord, Vec<u8>)>,
+    started_at: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{AuditLog, DatabasePool, RenderedTileStore};
+    use crate::tiles::pmtiles_writer::read_tile;
+
+    #[tokio::test]
+    async fn test_commit_tile_staged_twice() {
+        let Some(database) = DatabasePool::for_test("test_commit_staged_twice").await else {
+            return;
+        };
+        let mut config = Config::default();
+        config.files.pmtiles_archive_path = std::env::temp_dir().join("test_commit_staged_twice.pmtiles");
+        let archive = config.files.pmtiles_archive_path.clone();
+        std::fs::remove_file(&archive).ok();
+        let mut executor = BatchExecutor::new(MvtGenerator::new(database.clone(), config.clone()), PmtilesWriter::new(config.clone()), &config)
+            .with_commit_log(CommitLog::new(AuditLog::new(database.clone()), None, WorkerHealth::new()));
+
+        // Two workers rendered the same tile
+        let staging = RenderedTileStore::new(database.clone(), "renderer");
+        let tile = TileCoord::new(3, 1, 2);
+        let mut batch = TileBatch::new("test".into());
+        batch.add_tile(tile.clone());
+        staging.stage(&[(tile.clone(), b"first".to_vec())], &batch).await.unwrap();
+        staging.stage(&[(tile.clone(), b"second".to_vec())], &batch).await.unwrap();
+
+        let staged = staging.take(10).await.unwrap().unwrap();
+        assert_eq!(staged.tiles.len(), 2);
+        executor.commit(&staged.tiles, &staged.batch, Utc::now()).await.unwrap();
+        staging.remove(&staged).await.unwrap();
+
+        assert_eq!(read_tile(&archive, &tile).await.unwrap().as_deref(), Some(&b"second"[..]));
+        assert_eq!(database.query("SELECT * FROM changed_tiles", &[]).await.unwrap().len(), 1);
+        assert!(staging.take(10).await.unwrap().is_none());
+
+        std::fs::remove_file(archive).ok();
+    }
+
+    #[tokio::test]
+    async fn test_chunks_before_a_failure_stay_committed() {
+        let Some(database) = DatabasePool::for_test("test_chunks_before_failure").await else {
+            return;
+        };
+        let mut config = Config::default();
+        config.worker.chunk_size = 1;
+        config.files.pmtiles_archive_path = std::env::temp_dir().join("test_chunks_before_failure.pmtiles");
+        let archive = config.files.pmtiles_archive_path.clone();
+        std::fs::remove_file(&archive).ok();
+
+        let (first, second) = (TileCoord::new(3, 1, 2), TileCoord::new(3, 2, 2));
+        let mut writer = PmtilesWriter::new(config.clone());
+        writer.write_tiles(&[(first.clone(), b"old".to_vec()), (second.clone(), b"old".to_vec())]).await.unwrap();
+
+        let mut executor = BatchExecutor::new(MvtGenerator::new(database.clone(), config.clone()), writer, &config)
+            .with_commit_log(CommitLog::new(AuditLog::new(database.clone()), None, WorkerHealth::new()));
+
+        // The out-of-grid tile sorts last and fails to commit
+        let mut batch = TileBatch::new("test".into());
+        batch.add_tile(first.clone());
+        batch.add_tile(second.clone());
+        batch.add_tile(TileCoord::new(1, 5, 0));
+        assert!(executor.execute(&batch).await.is_err());
+
+        // Both earlier chunks replaced the old tiles (the generator renders them empty)
+        assert_eq!(read_tile(&archive, &first).await.unwrap(), None);
+        assert_eq!(read_tile(&archive, &second).await.unwrap(), None);
+        assert_eq!(database.query("SELECT * FROM changed_tile_batches", &[]).await.unwrap().len(), 2);
+        assert_eq!(database.query("SELECT * FROM changed_tiles", &[]).await.unwrap().len(), 2);
+
+        std::fs::remove_file(archive).ok();
+    }
+}
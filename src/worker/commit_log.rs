@@ -0,0 +1,56 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use tracing::info;
+use crate::TileCoord;
+use crate::database::AuditLog;
+use crate::feed::ChangeFeed;
+use crate::metrics::metrics;
+use super::{TileBatch, WorkerHealth};
+use super::progress::format_duration;
+
+/// Records every archive commit: an audit row with its tiles, a change
+/// manifest, and the lag it reached the archive with. Runs once per commit,
+/// so tiles committed before a batch fails are accounted for too.
+pub struct CommitLog {
+    audit: AuditLog,
+    feed: Option<ChangeFeed>,
+    health: WorkerHealth,
+}
+
+impl CommitLog {
+    pub fn new(audit: AuditLog, feed: Option<ChangeFeed>, health: WorkerHealth) -> Self {
+        Self { audit, feed, health }
+    }
+
+    /// Record tiles of `batch` just committed to the archive and return the
+    /// audit row id
+    pub async fn record(
+        &self,
+        batch: &TileBatch,
+        tiles: &[TileCoord],
+        started_at: DateTime<Utc>,
+        finished_at: DateTime<Utc>,
+    ) -> Result<i64> {
+        let batch_id = self.audit.record_batch(batch, tiles, started_at, finished_at).await?;
+        if let Some(feed) = &self.feed {
+            feed.emit(batch_id, finished_at, &batch.replication, tiles).await;
+        }
+        self.record_lag(batch_id, batch, finished_at);
+        Ok(batch_id)
+    }
+
+    /// Report how far a committed batch lags behind OSM (data age) and how
+    /// long its tiles spent in jvt (pipeline lag)
+    fn record_lag(&self, batch_id: i64, batch: &TileBatch, finished_at: DateTime<Utc>) {
+        let pipeline_lag = (finished_at - batch.created_at).as_seconds_f64().max(0.0);
+        self.health.batch_committed(batch.replication.timestamp, pipeline_lag);
+        metrics().batch_committed(batch.replication.timestamp, finished_at, pipeline_lag);
+
+        match batch.replication.age_at(finished_at) {
+            Some(age) => info!("Recorded batch {} in audit table (data age {}, pipeline lag {:.1}s)",
+                               batch_id, format_duration(age.to_std().unwrap_or_default()), pipeline_lag),
+            None => info!("Recorded batch {} in audit table (data age unknown, pipeline lag {:.1}s)",
+                          batch_id, pipeline_lag),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use anyhow::{Context, Result};
+use flate2::read::MultiGzDecoder;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Compression applied to a dirty tiles file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl FileCompression {
+    /// Detect compression from the leading magic bytes
+    pub fn detect(header: &[u8]) -> Self {
+        if header.starts_with(&GZIP_MAGIC) {
+            FileCompression::Gzip
+        } else if header.starts_with(&ZSTD_MAGIC) {
+            FileCompression::Zstd
+        } else {
+            FileCompression::None
+        }
+    }
+}
+
+impl std::fmt::Display for FileCompression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileCompression::None => write!(f, "uncompressed"),
+            FileCompression::Gzip => write!(f, "gzip"),
+            FileCompression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+/// Sniff the compression of a file without consuming it
+pub fn detect_compression<P: AsRef<Path>>(path: P) -> Result<FileCompression> {
+    let path = path.as_ref();
+    let mut file = File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+
+    let mut header = [0u8; 4];
+    let mut read = 0;
+    while read < header.len() {
+        match file.read(&mut header[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+
+    Ok(FileCompression::detect(&header[..read]))
+}
+
+/// Open a possibly compressed file as a buffered line reader
+pub fn open_reader<P: AsRef<Path>>(path: P) -> Result<(Box<dyn BufRead + Send>, FileCompression)> {
+    let path = path.as_ref();
+    let compression = detect_compression(path)?;
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open dirty tiles file: {}", path.display()))?;
+
+    let reader: Box<dyn BufRead + Send> = match compression {
+        FileCompression::None => Box::new(BufReader::new(file)),
+        FileCompression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(file))),
+        FileCompression::Zstd => {
+            let decoder = zstd::Decoder::new(file)
+                .with_context(|| format!("Failed to initialise zstd decoder for {}", path.display()))?;
+            Box::new(BufReader::new(decoder))
+        }
+    };
+
+    Ok((reader, compression))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_detect_compression() {
+        assert_eq!(FileCompression::detect(&[0x1f, 0x8b, 0x08]), FileCompression::Gzip);
+        assert_eq!(FileCompression::detect(&ZSTD_MAGIC), FileCompression::Zstd);
+        assert_eq!(FileCompression::detect(b"14/8"), FileCompression::None);
+        assert_eq!(FileCompression::detect(b""), FileCompression::None);
+    }
+
+    #[test]
+    fn test_open_compressed_readers() {
+        let content = "14/8234/5425\n12/2058/1356\n";
+        let temp_dir = std::env::temp_dir();
+
+        let gz_path = temp_dir.join("test_decompress.txt.gz");
+        let mut encoder = flate2::write::GzEncoder::new(
+            File::create(&gz_path).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(content.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        let zst_path = temp_dir.join("test_decompress.txt.zst");
+        let compressed = zstd::encode_all(content.as_bytes(), 3).unwrap();
+        std::fs::write(&zst_path, compressed).unwrap();
+
+        for (path, expected) in [(&gz_path, FileCompression::Gzip), (&zst_path, FileCompression::Zstd)] {
+            let (mut reader, compression) = open_reader(path).unwrap();
+            let mut decoded = String::new();
+            reader.read_to_string(&mut decoded).unwrap();
+
+            assert_eq!(compression, expected);
+            assert_eq!(decoded, content);
+            std::fs::remove_file(path).ok();
+        }
+    }
+}
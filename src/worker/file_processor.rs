@@ -1,10 +1,15 @@
-use std::path::Path;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::io::{BufRead, Lines};
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use tracing::{info, warn, error, debug};
 use crate::{TileCoord, Config};
+use crate::config::DirtyTilesFormat;
+use crate::metrics::metrics;
 use super::TileBatch;
+use super::tile_batch::ReplicationInfo;
+use super::decompress::{detect_compression, open_reader, FileCompression};
 
 /// Processor for dirty tiles files generated by OSM2PGSQL
 pub struct DirtyTilesProcessor {
@@ -17,81 +22,121 @@ impl DirtyTilesProcessor {
         Self { config }
     }
 
-    /// Process a dirty tiles file and return a tile batch
+    /// Process a dirty tiles file into a single tile batch.
+    /// This holds every tile in memory - use `stream_file` for large expiry lists.
     pub fn process_file<P: AsRef<Path>>(&self, file_path: P) -> Result<TileBatch> {
         let file_path = file_path.as_ref();
-        info!("Processing dirty tiles file: {}", file_path.display());
-
-        // Open and read the file
-        let file = File::open(file_path)
-            .with_context(|| format!("Failed to open dirty tiles file: {}", file_path.display()))?;
-
-        let reader = BufReader::new(file);
         let mut batch = TileBatch::new(file_path.to_path_buf());
-        let mut _line_count = 0;
-        let mut error_count = 0;
-
-        // Process each line
-        for (line_number, line_result) in reader.lines().enumerate() {
-            _line_count += 1;
-            
-            match line_result {
-                Ok(line) => {
-                    let line = line.trim();
-                    
-                    // Skip empty lines and comments
-                    if line.is_empty() || line.starts_with('#') {
-                        continue;
-                    }
 
-                    match self.parse_tile_line(line) {
-                        Ok(coord) => {
-                            // Filter by max zoom if configured
-                            if coord.z <= self.config.tiles.max_zoom {
-                                batch.add_tile(coord);
-                            } else {
-                                debug!("Skipping tile {} (zoom {} > max {})", 
-                                      coord.to_string(), coord.z, self.config.tiles.max_zoom);
-                            }
-                        }
-                        Err(e) => {
-                            error_count += 1;
-                            if error_count <= 10 { // Limit error spam
-                                warn!("Line {}: Failed to parse tile coordinate '{}': {}", 
-                                      line_number + 1, line, e);
-                            } else if error_count == 11 {
-                                warn!("More than 10 parse errors, suppressing further warnings...");
-                            }
-                            
-                            // Write to dead letter file if too many errors
-                            if error_count <= 100 {
-                                self.write_to_dead_letter(&format!("{}:{}", file_path.display(), line))?;
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    error!("Failed to read line {} from {}: {}", 
-                           line_number + 1, file_path.display(), e);
-                    return Err(e.into());
-                }
+        for chunk in self.stream_file_with_chunk_size(file_path, usize::MAX)? {
+            for coord in chunk?.tiles {
+                batch.add_tile(coord);
             }
         }
 
         let summary = batch.summary();
         info!("Processed dirty tiles file: {}", summary);
-        
-        if error_count > 0 {
-            warn!("Encountered {} parse errors in {}", error_count, file_path.display());
-        }
 
         Ok(batch)
     }
 
-    /// Parse a single line containing a tile coordinate
-    fn parse_tile_line(&self, line: &str) -> Result<TileCoord> {
-        TileCoord::from_str(line)
-            .map_err(|e| anyhow::anyhow!("Invalid tile format: {}", e))
+    /// Stream a dirty tiles file in a single pass, yielding batches of at most
+    /// `worker.max_batch_size` tiles. Memory use is bounded by the chunk size,
+    /// not the file size. Gzip and zstd compressed files are decompressed on the fly.
+    pub fn stream_file<P: AsRef<Path>>(&self, file_path: P) -> Result<TileStream<'_>> {
+        self.stream_file_with_chunk_size(file_path, self.config.worker.max_batch_size)
+    }
+
+    fn stream_file_with_chunk_size<P: AsRef<Path>>(&self, file_path: P, chunk_size: usize) -> Result<TileStream<'_>> {
+        let file_path = file_path.as_ref();
+        let (reader, compression) = open_reader(file_path)?;
+        info!("Streaming dirty tiles file: {} ({})", file_path.display(), compression);
+
+        Ok(TileStream {
+            processor: self,
+            path: file_path.to_path_buf(),
+            lines: reader.lines(),
+            format: self.config.files.dirty_tiles_format,
+            chunk_size: chunk_size.max(1),
+            replication: ReplicationInfo::default(),
+            opened_at: Utc::now(),
+            stats: StreamStats::default(),
+            finished: false,
+        })
+    }
+
+    /// Parse one line of a dirty tiles file into the batch, tracking errors in `stats`
+    fn handle_line(
+        &self,
+        line: &str,
+        format: &mut DirtyTilesFormat,
+        batch: &mut TileBatch,
+        stats: &mut StreamStats,
+    ) -> Result<()> {
+        let line = line.trim();
+
+        // Files may declare their addressing scheme in a header comment
+        if let Some(declared) = parse_format_directive(line) {
+            if self.config.files.dirty_tiles_format == DirtyTilesFormat::Auto {
+                debug!("{} declares format {:?}", batch.source_file.display(), declared);
+                *format = declared;
+            }
+            return Ok(());
+        }
+
+        // Skip empty lines and comments
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+
+        match self.parse_tile_line(line, *format) {
+            Ok(coord) => {
+                // Filter by max zoom if configured
+                if coord.z <= self.config.tiles.max_zoom {
+                    stats.tiles_parsed += 1;
+                    batch.add_tile(coord);
+                } else {
+                    stats.skipped_zoom += 1;
+                    debug!("Skipping tile {} (zoom {} > max {})", 
+                          coord, coord.z, self.config.tiles.max_zoom);
+                }
+            }
+            Err(e) => {
+                stats.parse_errors += 1;
+                metrics().parse_errors.inc();
+                if stats.parse_errors <= 10 { // Limit error spam
+                    warn!("Line {}: Failed to parse tile coordinate '{}': {}", 
+                          stats.lines_read, line, e);
+                } else if stats.parse_errors == 11 {
+                    warn!("More than 10 parse errors, suppressing further warnings...");
+                }
+                
+                // Write to dead letter file if too many errors
+                if stats.parse_errors <= 100 {
+                    self.write_to_dead_letter(&format!("{}:{}", batch.source_file.display(), line))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parse a single line containing a tile coordinate in the given addressing scheme
+    fn parse_tile_line(&self, line: &str, format: DirtyTilesFormat) -> Result<TileCoord> {
+        let format = match format {
+            DirtyTilesFormat::Auto if is_quadkey(line) => DirtyTilesFormat::Quadkey,
+            DirtyTilesFormat::Auto => DirtyTilesFormat::Xyz,
+            other => other,
+        };
+
+        let result = match format {
+            DirtyTilesFormat::Quadkey => TileCoord::from_quadkey(line),
+            DirtyTilesFormat::Tms => line.parse::<TileCoord>()
+                .and_then(|tms| TileCoord::from_tms(tms.z, tms.x, tms.y)),
+            _ => line.parse::<TileCoord>(),
+        };
+
+        result.map_err(|e| anyhow::anyhow!("Invalid tile format: {}", e))
     }
 
     /// Write unparseable lines to dead letter file for investigation
@@ -114,21 +159,11 @@ impl DirtyTilesProcessor {
 
         writeln!(file, "{}", line)
             .context("Failed to write to dead letter file")?;
+        metrics().dead_letter_lines.inc();
 
         Ok(())
     }
 
-    /// Get the total number of lines in a file (for progress tracking)
-    pub fn count_lines<P: AsRef<Path>>(&self, file_path: P) -> Result<usize> {
-        let file = File::open(file_path.as_ref())
-            .context("Failed to open file for line counting")?;
-        
-        let reader = BufReader::new(file);
-        let count = reader.lines().count();
-        
-        Ok(count)
-    }
-
     /// Validate that a dirty tiles file exists and is readable
     pub fn validate_file<P: AsRef<Path>>(&self, file_path: P) -> Result<FileInfo> {
         let file_path = file_path.as_ref();
@@ -141,35 +176,178 @@ impl DirtyTilesProcessor {
         }
 
         let size_bytes = metadata.len();
-        let line_count = self.count_lines(file_path)?;
+        let compression = detect_compression(file_path)?;
 
         Ok(FileInfo {
             path: file_path.to_path_buf(),
             size_bytes,
-            line_count,
+            compression,
             modified: metadata.modified().ok(),
         })
     }
+
+    /// Dirty tiles files in the dirty tiles directory modified at or after
+    /// `since`, oldest first. Used to catch up on files whose notification
+    /// was lost while the listener was disconnected.
+    pub fn files_modified_since(&self, since: SystemTime) -> Result<Vec<PathBuf>> {
+        let files = &self.config.files;
+        let entries = std::fs::read_dir(&files.dirty_tiles_path)
+            .with_context(|| format!("Failed to list {}", files.dirty_tiles_path.display()))?;
+
+        let mut found = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let path = entry.path();
+            // Dirty tiles files are `*.txt`, possibly compressed; skip the
+            // dead letter file and anything else sharing the directory
+            let is_dirty_tiles = path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.ends_with(".txt") || name.contains(".txt."));
+            if !is_dirty_tiles || path == files.dead_letter_path {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            if let Ok(modified) = metadata.modified()
+                && metadata.is_file()
+                && modified >= since
+            {
+                found.push((modified, path));
+            }
+        }
+
+        found.sort();
+        Ok(found.into_iter().map(|(_, path)| path).collect())
+    }
+}
+
+/// Quadkeys are non-empty strings of base-4 digits
+fn is_quadkey(line: &str) -> bool {
+    !line.is_empty() && line.bytes().all(|b| (b'0'..=b'3').contains(&b))
+}
+
+/// Parse a `# format: tms` style header comment
+fn parse_format_directive(line: &str) -> Option<DirtyTilesFormat> {
+    let directive = line.strip_prefix('#')?.trim();
+    let value = directive.strip_prefix("format")?.trim_start().strip_prefix(':')?;
+    value.parse().ok()
+}
+
+/// Single-pass iterator over a dirty tiles file, yielding bounded-size batches
+pub struct TileStream<'a> {
+    processor: &'a DirtyTilesProcessor,
+    path: PathBuf,
+    lines: Lines<Box<dyn BufRead + Send>>,
+    format: DirtyTilesFormat,
+    chunk_size: usize,
+    replication: ReplicationInfo,
+    /// When the file was picked up; batches count pipeline lag from here
+    opened_at: DateTime<Utc>,
+    stats: StreamStats,
+    finished: bool,
+}
+
+impl TileStream<'_> {
+    /// Attach replication metadata to every batch the stream yields
+    pub fn with_replication(mut self, replication: ReplicationInfo) -> Self {
+        self.replication = replication;
+        self
+    }
+
+    /// Counters for the lines consumed so far
+    pub fn stats(&self) -> &StreamStats {
+        &self.stats
+    }
+
+    fn finish(&mut self) {
+        self.finished = true;
+        info!("Finished streaming {}: {}", self.path.display(), self.stats);
+
+        if self.stats.parse_errors > 0 {
+            warn!("Encountered {} parse errors in {}", self.stats.parse_errors, self.path.display());
+        }
+    }
+}
+
+impl Iterator for TileStream<'_> {
+    type Item = Result<TileBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let mut batch = TileBatch::new(self.path.clone());
+        batch.created_at = self.opened_at;
+        batch.replication = self.replication.clone();
+
+        while batch.len() < self.chunk_size {
+            match self.lines.next() {
+                Some(Ok(line)) => {
+                    self.stats.lines_read += 1;
+                    if let Err(e) = self.processor.handle_line(&line, &mut self.format, &mut batch, &mut self.stats) {
+                        self.finished = true;
+                        return Some(Err(e));
+                    }
+                }
+                Some(Err(e)) => {
+                    error!("Failed to read line {} from {}: {}", 
+                           self.stats.lines_read + 1, self.path.display(), e);
+                    self.finished = true;
+                    return Some(Err(e.into()));
+                }
+                None => {
+                    self.finish();
+                    break;
+                }
+            }
+        }
+
+        if batch.is_empty() {
+            return None;
+        }
+
+        self.stats.chunks += 1;
+        debug!("Yielding chunk {} from {}: {}", self.stats.chunks, self.path.display(), batch.summary());
+        Some(Ok(batch))
+    }
+}
+
+/// Counters collected while streaming a dirty tiles file
+#[derive(Debug, Default, Clone)]
+pub struct StreamStats {
+    pub lines_read: usize,
+    pub tiles_parsed: usize,
+    pub skipped_zoom: usize,
+    pub parse_errors: usize,
+    pub chunks: usize,
+}
+
+impl std::fmt::Display for StreamStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} lines, {} tiles in {} chunks, {} above max zoom, {} parse errors",
+               self.lines_read, self.tiles_parsed, self.chunks, self.skipped_zoom, self.parse_errors)
+    }
 }
 
 #[derive(Debug)]
 pub struct FileInfo {
     pub path: std::path::PathBuf,
     pub size_bytes: u64,
-    pub line_count: usize,
+    pub compression: FileCompression,
     pub modified: Option<std::time::SystemTime>,
 }
 
 impl std::fmt::Display for FileInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} ({} bytes, {} lines)", 
-               self.path.display(), self.size_bytes, self.line_count)
+        write!(f, "{} ({} bytes, {})", 
+               self.path.display(), self.size_bytes, self.compression)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs::File;
     use std::io::Write;
 
     #[test]
@@ -183,7 +361,7 @@ mod tests {
             writeln!(file, "14/8234/5425").unwrap();
             writeln!(file, "12/2058/1356").unwrap();
             writeln!(file, "# This is a comment").unwrap();
-            writeln!(file, "").unwrap(); // Empty line
+            writeln!(file).unwrap(); // Empty line
             writeln!(file, "10/515/339").unwrap();
             writeln!(file, "invalid_line").unwrap(); // Should be ignored
         }
@@ -206,13 +384,122 @@ mod tests {
         let config = Config::default();
         let processor = DirtyTilesProcessor::new(config);
         
+        let auto = DirtyTilesFormat::Auto;
+        
         // Valid formats
-        assert!(processor.parse_tile_line("14/8234/5425").is_ok());
-        assert!(processor.parse_tile_line("0/0/0").is_ok());
+        assert!(processor.parse_tile_line("14/8234/5425", auto).is_ok());
+        assert!(processor.parse_tile_line("0/0/0", auto).is_ok());
         
         // Invalid formats
-        assert!(processor.parse_tile_line("invalid").is_err());
-        assert!(processor.parse_tile_line("14/8234").is_err());
-        assert!(processor.parse_tile_line("14/8234/5425/extra").is_err());
+        assert!(processor.parse_tile_line("invalid", auto).is_err());
+        assert!(processor.parse_tile_line("14/8234", auto).is_err());
+        assert!(processor.parse_tile_line("14/8234/5425/extra", auto).is_err());
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_parse_tile_line_formats() {
+        let config = Config::default();
+        let processor = DirtyTilesProcessor::new(config);
+        let expected = TileCoord::new(3, 3, 5);
+        
+        // Quadkeys are detected automatically
+        assert_eq!(processor.parse_tile_line("213", DirtyTilesFormat::Auto).unwrap(), expected);
+        assert_eq!(processor.parse_tile_line("213", DirtyTilesFormat::Quadkey).unwrap(), expected);
+        
+        // TMS flips the y axis
+        assert_eq!(processor.parse_tile_line("3/3/2", DirtyTilesFormat::Tms).unwrap(), expected);
+        assert_eq!(processor.parse_tile_line("3/3/5", DirtyTilesFormat::Xyz).unwrap(), expected);
+        
+        assert!(processor.parse_tile_line("3/3/5", DirtyTilesFormat::Quadkey).is_err());
+    }
+
+    #[test]
+    fn test_process_declared_tms_file() {
+        let test_file = std::env::temp_dir().join("test_dirty_tiles_tms.txt");
+        
+        {
+            let mut file = File::create(&test_file).unwrap();
+            writeln!(file, "# format: tms").unwrap();
+            writeln!(file, "3/3/2").unwrap();
+            writeln!(file, "14/8234/10958").unwrap();
+        }
+
+        let processor = DirtyTilesProcessor::new(Config::default());
+        let batch = processor.process_file(&test_file).unwrap();
+        
+        assert_eq!(batch.len(), 2);
+        assert!(batch.tiles.contains(&TileCoord::new(3, 3, 5)));
+        assert!(batch.tiles.contains(&TileCoord::new(14, 8234, 5425)));
+
+        std::fs::remove_file(test_file).ok();
+    }
+
+    #[test]
+    fn test_stream_file_bounded_chunks() {
+        let test_file = std::env::temp_dir().join("test_dirty_tiles_stream.txt");
+        
+        {
+            let mut file = File::create(&test_file).unwrap();
+            for x in 0..25 {
+                writeln!(file, "14/{}/5425", x).unwrap();
+            }
+        }
+
+        let mut config = Config::default();
+        config.worker.max_batch_size = 10;
+        let processor = DirtyTilesProcessor::new(config);
+        
+        let mut stream = processor.stream_file(&test_file).unwrap();
+        let batches: Vec<TileBatch> = (&mut stream).map(|chunk| chunk.unwrap()).collect();
+        let sizes: Vec<usize> = batches.iter().map(|batch| batch.len()).collect();
+        
+        assert_eq!(sizes, vec![10, 10, 5]);
+        // Every batch counts pipeline lag from when the file was opened
+        assert!(batches.iter().all(|batch| batch.created_at == batches[0].created_at));
+        assert_eq!(stream.stats().tiles_parsed, 25);
+        assert_eq!(stream.stats().chunks, 3);
+
+        std::fs::remove_file(test_file).ok();
+    }
+
+    #[test]
+    fn test_stream_compressed_file() {
+        let test_file = std::env::temp_dir().join("test_dirty_tiles_stream.txt.zst");
+        let content = "14/8234/5425\n12/2058/1356\n10/515/339\n";
+        std::fs::write(&test_file, zstd::encode_all(content.as_bytes(), 3).unwrap()).unwrap();
+
+        let processor = DirtyTilesProcessor::new(Config::default());
+        let info = processor.validate_file(&test_file).unwrap();
+        assert_eq!(info.compression, FileCompression::Zstd);
+        
+        let batch = processor.process_file(&test_file).unwrap();
+        assert_eq!(batch.len(), 3);
+        assert!(batch.tiles.contains(&TileCoord::new(12, 2058, 1356)));
+
+        std::fs::remove_file(test_file).ok();
+    }
+
+    #[test]
+    fn test_files_modified_since() {
+        let dir = std::env::temp_dir().join(format!("jvt_catch_up_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut config = Config::default();
+        config.files.dirty_tiles_path = dir.clone();
+        config.files.dead_letter_path = dir.join("dead_letter_tiles.txt");
+
+        std::fs::write(dir.join("dirty_tiles.old.txt"), "14/8234/5425\n").unwrap();
+        let since = SystemTime::now();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        std::fs::write(dir.join("dirty_tiles.new.txt"), "14/8234/5425\n").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        std::fs::write(dir.join("rerender.new.txt.gz"), "").unwrap();
+        std::fs::write(dir.join("dead_letter_tiles.txt"), "14/8234/5425\n").unwrap();
+        std::fs::write(dir.join("osm2pgsql-replication"), "").unwrap();
+
+        let processor = DirtyTilesProcessor::new(config);
+        let files = processor.files_modified_since(since).unwrap();
+        assert_eq!(files, vec![dir.join("dirty_tiles.new.txt"), dir.join("rerender.new.txt.gz")]);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
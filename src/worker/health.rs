@@ -0,0 +1,105 @@
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Liveness information shared between the worker loop and the health endpoints
+#[derive(Clone, Default)]
+pub struct WorkerHealth {
+    inner: Arc<Mutex<HealthState>>,
+}
+
+/// Snapshot of the worker's progress
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HealthState {
+    pub started_at: Option<DateTime<Utc>>,
+    /// Last time the worker loop or a batch chunk made progress
+    pub last_heartbeat_at: Option<DateTime<Utc>>,
+    pub last_batch_at: Option<DateTime<Utc>>,
+    pub batches_committed: u64,
+    /// None when the worker does not use a LISTEN connection
+    pub listener_connected: Option<bool>,
+    /// OSM data timestamp of the newest committed batch
+    pub data_timestamp: Option<DateTime<Utc>>,
+    /// Seconds since `data_timestamp`, as of the snapshot
+    pub data_age_secs: Option<i64>,
+    /// Seconds from picking up the last batch's tiles to committing them
+    pub pipeline_lag_secs: Option<f64>,
+}
+
+impl WorkerHealth {
+    /// Health state for a worker starting now
+    pub fn new() -> Self {
+        let health = Self::default();
+        health.update(|state| {
+            state.started_at = Some(Utc::now());
+            state.last_heartbeat_at = state.started_at;
+        });
+        health
+    }
+
+    /// Record that the worker is making progress
+    pub fn heartbeat(&self) {
+        self.update(|state| state.last_heartbeat_at = Some(Utc::now()));
+    }
+
+    /// Record a successfully committed batch, its OSM data timestamp and pipeline lag
+    pub fn batch_committed(&self, data_timestamp: Option<DateTime<Utc>>, pipeline_lag_secs: f64) {
+        self.update(|state| {
+            let now = Some(Utc::now());
+            state.last_batch_at = now;
+            state.last_heartbeat_at = now;
+            state.batches_committed += 1;
+            state.data_timestamp = state.data_timestamp.max(data_timestamp);
+            state.pipeline_lag_secs = Some(pipeline_lag_secs);
+        });
+    }
+
+    /// Record whether the notification listener is still subscribed
+    pub fn set_listener_connected(&self, connected: bool) {
+        self.update(|state| state.listener_connected = Some(connected));
+    }
+
+    /// Current state
+    pub fn snapshot(&self) -> HealthState {
+        let mut state = self.inner.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        state.data_age_secs = state.data_timestamp.map(|at| (Utc::now() - at).num_seconds());
+        state
+    }
+
+    /// Whether the worker has made no progress for longer than `stuck_after_secs`
+    pub fn is_stuck(&self, now: DateTime<Utc>, stuck_after_secs: u64) -> bool {
+        self.snapshot().last_heartbeat_at
+            .is_some_and(|at| (now - at).num_seconds() > stuck_after_secs as i64)
+    }
+
+    fn update(&self, f: impl FnOnce(&mut HealthState)) {
+        f(&mut self.inner.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stuck_detection() {
+        let health = WorkerHealth::new();
+        let now = Utc::now();
+
+        assert!(!health.is_stuck(now, 60));
+        assert!(health.is_stuck(now + chrono::Duration::seconds(61), 60));
+
+        let data_timestamp = now - chrono::Duration::seconds(90);
+        health.batch_committed(Some(data_timestamp), 4.5);
+        health.batch_committed(None, 2.0);
+        let state = health.snapshot();
+        assert_eq!(state.batches_committed, 2);
+        assert!(state.last_batch_at.is_some());
+        assert_eq!(state.listener_connected, None);
+
+        // Batches without replication info keep the newest known data timestamp
+        assert_eq!(state.data_timestamp, Some(data_timestamp));
+        assert!(state.data_age_secs.is_some_and(|age| age >= 90));
+        assert_eq!(state.pipeline_lag_secs, Some(2.0));
+    }
+}
@@ -1,5 +1,18 @@
+pub mod batch_executor;
+pub mod commit_log;
+pub mod decompress;
 pub mod file_processor;
+pub mod health;
+pub mod progress;
+pub mod runner;
+pub mod scheduler;
 pub mod tile_batch;
 
-pub use file_processor::DirtyTilesProcessor;
-pub use tile_batch::TileBatch; 
\ No newline at end of file
+pub use batch_executor::BatchExecutor;
+pub use commit_log::CommitLog;
+pub use file_processor::{DirtyTilesProcessor, TileStream};
+pub use health::WorkerHealth;
+pub use progress::ProgressTracker;
+pub use runner::Worker;
+pub use scheduler::TileScheduler;
+pub use tile_batch::TileBatch;
\ No newline at end of file
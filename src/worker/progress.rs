@@ -0,0 +1,116 @@
+use std::time::{Duration, Instant};
+
+/// Tracks completion of a fixed amount of work for progress and ETA logging
+#[derive(Debug, Clone)]
+pub struct ProgressTracker {
+    total: u64,
+    done: u64,
+    started: Instant,
+}
+
+impl ProgressTracker {
+    /// Start tracking `total` units of work
+    pub fn new(total: u64) -> Self {
+        Self { total, done: 0, started: Instant::now() }
+    }
+
+    /// Record completed units of work
+    pub fn advance(&mut self, units: u64) {
+        self.done = (self.done + units).min(self.total);
+    }
+
+    pub fn done(&self) -> u64 {
+        self.done
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Completion in percent (100 for empty work)
+    pub fn percent(&self) -> f64 {
+        if self.total == 0 {
+            return 100.0;
+        }
+        self.done as f64 / self.total as f64 * 100.0
+    }
+
+    /// Units completed per second so far
+    pub fn rate(&self) -> f64 {
+        let secs = self.elapsed().as_secs_f64();
+        if secs <= 0.0 {
+            return 0.0;
+        }
+        self.done as f64 / secs
+    }
+
+    /// Estimated time remaining at the current rate, if any progress was made
+    pub fn eta(&self) -> Option<Duration> {
+        self.eta_at(self.elapsed())
+    }
+
+    fn eta_at(&self, elapsed: Duration) -> Option<Duration> {
+        if self.done == 0 {
+            return None;
+        }
+
+        let remaining = self.total - self.done;
+        let per_unit = elapsed.as_secs_f64() / self.done as f64;
+        Some(Duration::from_secs_f64(per_unit * remaining as f64))
+    }
+}
+
+impl std::fmt::Display for ProgressTracker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{} ({:.1}%), {:.1}/s, ETA {}",
+               self.done, self.total, self.percent(), self.rate(),
+               self.eta().map(format_duration).unwrap_or_else(|| "unknown".to_string()))
+    }
+}
+
+/// Format a duration as "1h02m03s", "2m03s" or "3s"
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, secs % 3600 / 60, secs % 60);
+
+    if hours > 0 {
+        format!("{}h{:02}m{:02}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m{:02}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_percent_and_eta() {
+        let mut progress = ProgressTracker::new(200);
+        assert_eq!(progress.percent(), 0.0);
+        assert!(progress.eta_at(Duration::from_secs(10)).is_none());
+
+        progress.advance(50);
+        assert_eq!(progress.percent(), 25.0);
+        assert_eq!(progress.eta_at(Duration::from_secs(10)), Some(Duration::from_secs(30)));
+
+        progress.advance(500);
+        assert_eq!(progress.done(), 200);
+        assert_eq!(progress.eta_at(Duration::from_secs(10)), Some(Duration::ZERO));
+
+        assert_eq!(ProgressTracker::new(0).percent(), 100.0);
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(3)), "3s");
+        assert_eq!(format_duration(Duration::from_secs(123)), "2m03s");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1h02m03s");
+    }
+}
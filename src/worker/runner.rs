@@ -0,0 +1,563 @@
+use std::time::SystemTime;
+use anyhow::Result;
+use tokio::time::{sleep, Duration, Instant, MissedTickBehavior};
+use tracing::{debug, error, info, warn};
+use crate::Config;
+use crate::config::{IngestMode, LockConflict};
+use crate::database::{
+    AuditLog, DatabasePool, NotificationListener, NotificationPayload, RenderedTileStore, ReplicationProperties, TileQueue,
+    TileUpdatePublisher, WriterLock,
+};
+use crate::database::listener::TileNotification;
+use crate::feed::ChangeFeed;
+use crate::metrics::metrics;
+use crate::replication::ReplicationSupervisor;
+use crate::tiles::{ArchiveLock, MvtGenerator, PmtilesWriter};
+use super::{BatchExecutor, CommitLog, DirtyTilesProcessor, TileBatch, TileScheduler, WorkerHealth};
+use super::progress::format_duration;
+use super::tile_batch::ReplicationInfo;
+
+/// How often the writer prunes `changed_tiles` past its retention
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Upper bound on the wait between listener reconnect attempts
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Slack when catching up after a reconnect, covering files written just
+/// before their notification was lost
+const CATCH_UP_MARGIN: Duration = Duration::from_secs(60);
+
+/// The tile worker pipeline: ingest dirty tiles, throttle low zooms,
+/// render and commit batches, and record them in the audit table.
+///
+/// With `MULTI_WORKER` enabled several workers share the `dirty_tiles` queue.
+/// The worker holding the writer advisory lock turns notified files into queue
+/// rows, commits tiles staged by the others and flushes deferred low zooms;
+/// the rest only render. If the writer dies its lock is released with its
+/// database session and the next worker to ask takes over.
+///
+/// A single worker takes the same lock (plus the archive's file lock) at
+/// startup, so a second instance started by mistake refuses to run or
+/// stands by instead of corrupting the archive. Either way the writer also
+/// commits tiles `jvt serve` rendered on demand and staged.
+pub struct Worker {
+    config: Config,
+    processor: DirtyTilesProcessor,
+    scheduler: TileScheduler,
+    executor: BatchExecutor,
+    audit: AuditLog,
+    queue: Option<TileQueue>,
+    staging: RenderedTileStore,
+    writer_lock: WriterLock,
+    replication: ReplicationProperties,
+    health: WorkerHealth,
+    last_prune: Option<Instant>,
+}
+
+impl Worker {
+    /// Assemble the pipeline from configuration
+    pub fn new(config: Config, database: DatabasePool) -> Result<Self> {
+        // Create low zoom refresh scheduler (restores deferred tiles from last run)
+        let scheduler = TileScheduler::new(&config)?;
+        info!("Tile scheduler initialized ({} deferred tiles pending)", scheduler.pending());
+        let health = WorkerHealth::new();
+
+        // Create batch executor (renders tiles and commits them to the archive in chunks)
+        let executor = BatchExecutor::new(
+            MvtGenerator::new(database.clone(), config.clone()),
+            PmtilesWriter::new(config.clone()),
+            &config,
+        )
+        .with_publisher(TileUpdatePublisher::new(database.clone(), &config.database.updates_channel))
+        .with_commit_log(CommitLog::new(AuditLog::new(database.clone()), ChangeFeed::from_config(&config)?, health.clone()))
+        .with_health(health.clone());
+
+        let multi_worker = config.worker.multi_worker;
+        let queue = (multi_worker || config.worker.ingest_mode == IngestMode::Queue).then(|| {
+            info!("Claiming tiles from the dirty_tiles queue as {}", config.worker.worker_id);
+            TileQueue::new(database.clone(), &config.worker.worker_id, config.worker.claim_lease_secs)
+        });
+
+        let writer_lock = WriterLock::new(database.clone(), &config.files.pmtiles_archive_path);
+        if multi_worker {
+            info!("Multi-worker mode: competing for writer lock {}", writer_lock.key());
+        }
+        let staging = RenderedTileStore::new(database.clone(), &config.worker.worker_id);
+
+        Ok(Self {
+            processor: DirtyTilesProcessor::new(config.clone()),
+            scheduler,
+            executor,
+            audit: AuditLog::new(database.clone()),
+            replication: ReplicationProperties::new(database),
+            queue,
+            staging,
+            writer_lock,
+            health,
+            last_prune: None,
+            config,
+        })
+    }
+
+    /// Main worker loop - listen for notifications and process tiles
+    pub async fn run(&mut self, listener: &mut NotificationListener) -> Result<()> {
+        if !self.config.worker.multi_worker {
+            self.wait_for_writer_role().await?;
+        }
+
+        info!("Starting worker loop (timeout: {}s)", self.config.worker.batch_timeout_secs);
+        // Last time the listener was known to be delivering notifications
+        let mut alive_at = SystemTime::now();
+
+        loop {
+            self.health.heartbeat();
+            self.health.set_listener_connected(listener.is_connected());
+            if !self.check_role().await? {
+                continue;
+            }
+
+            match listener.wait_for_notification(
+                Duration::from_secs(self.config.worker.batch_timeout_secs)
+            ).await {
+                Ok(Some(notification)) => {
+                    alive_at = SystemTime::now();
+                    metrics().notifications_received.inc();
+                    info!("Received notification: {} bytes payload",
+                          notification.payload.len());
+
+                    match self.process_notification(&notification).await {
+                        Ok(()) => {
+                            info!("Successfully processed notification");
+                        }
+                        Err(e) => {
+                            error!("Failed to process notification: {}", e);
+                            // Continue loop - don't exit on processing errors
+                        }
+                    }
+                }
+                Ok(None) => {
+                    // Timeout occurred - this is normal
+                    alive_at = SystemTime::now();
+                    self.debug_worker_status();
+                }
+                Err(e) => {
+                    error!("Notification listener failed: {:#}", e);
+                    self.health.set_listener_connected(false);
+                    *listener = self.reconnect_listener().await;
+                    self.catch_up_files(alive_at - CATCH_UP_MARGIN).await;
+                    alive_at = SystemTime::now();
+                }
+            }
+
+            self.run_pending_work().await;
+        }
+    }
+
+    /// Open a new listener connection, retrying with exponential backoff
+    /// until the database is reachable again
+    async fn reconnect_listener(&self) -> NotificationListener {
+        let database = &self.config.database;
+        let mut backoff = Duration::from_secs(1);
+        loop {
+            sleep(backoff).await;
+            self.health.heartbeat();
+            match NotificationListener::new(&database.url, &database.notification_channel).await {
+                Ok(listener) => {
+                    info!("Reconnected notification listener on channel {}", database.notification_channel);
+                    return listener;
+                }
+                Err(e) => {
+                    backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+                    warn!("Failed to reconnect notification listener: {:#}; retrying in {}s", e, backoff.as_secs());
+                }
+            }
+        }
+    }
+
+    /// Process dirty tiles files written while the listener was down, since
+    /// their notifications were lost with the connection. Files processed
+    /// twice just re-render the same tiles.
+    async fn catch_up_files(&mut self, since: SystemTime) {
+        let files = match self.processor.files_modified_since(since) {
+            Ok(files) => files,
+            Err(e) => {
+                error!("Failed to list dirty tiles files to catch up on: {:#}", e);
+                return;
+            }
+        };
+        if !files.is_empty() {
+            info!("Catching up on {} dirty tiles files written while the listener was down", files.len());
+        }
+        for file in files {
+            let payload = NotificationPayload { file, sequence: None, timestamp: None, tile_count: None };
+            if let Err(e) = self.process_file(&payload).await {
+                error!("Failed to process {}: {:#}", payload.file.display(), e);
+            }
+        }
+    }
+
+    /// Replication loop for `jvt replicate`: run osm2pgsql on an interval and
+    /// feed each expire file straight into the pipeline. Only the writer runs
+    /// replication; in multi-worker mode the others keep rendering.
+    pub async fn run_replication(&mut self, supervisor: &ReplicationSupervisor, once: bool) -> Result<()> {
+        if !self.config.worker.multi_worker {
+            self.wait_for_writer_role().await?;
+        }
+
+        let interval = Duration::from_secs(self.config.replication.interval_secs.max(1));
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        info!("Starting replication loop (interval: {}s)", interval.as_secs());
+
+        loop {
+            ticker.tick().await;
+            self.health.heartbeat();
+            if !self.check_role().await? {
+                continue;
+            }
+
+            let result = if self.is_writer() {
+                self.replicate_once(supervisor).await
+            } else {
+                debug!("Not the writer, skipping replication run");
+                Ok(())
+            };
+
+            if let Err(e) = &result {
+                error!("Replication run failed: {:#}", e);
+            }
+
+            self.run_pending_work().await;
+
+            if once {
+                return result;
+            }
+        }
+    }
+
+    /// Run one replication update and process the tiles it expired
+    async fn replicate_once(&mut self, supervisor: &ReplicationSupervisor) -> Result<()> {
+        let run = supervisor.run_once().await?;
+
+        match run.payload() {
+            Some(payload) => self.process_file(&payload).await,
+            None => {
+                info!("Replication run expired no tiles");
+                Ok(())
+            }
+        }
+    }
+
+    /// Queue, staging and deferred tile work done after every loop iteration
+    async fn run_pending_work(&mut self) {
+        if let Err(e) = self.drain_queue().await {
+            error!("Failed to process queued tiles: {}", e);
+        }
+
+        if let Err(e) = self.commit_staged().await {
+            error!("Failed to commit staged tiles: {}", e);
+        }
+
+        // Flush any deferred low zoom tiles whose refresh interval has elapsed
+        if let Err(e) = self.flush_deferred_tiles().await {
+            error!("Failed to flush deferred tiles: {}", e);
+        }
+
+        if let Err(e) = self.prune_changed_tiles().await {
+            error!("Failed to prune changed tiles: {}", e);
+        }
+    }
+
+    /// Drop `changed_tiles` history past its retention, at most once an hour
+    async fn prune_changed_tiles(&mut self) -> Result<()> {
+        let days = self.config.worker.changed_tiles_retention_days;
+        if days == 0 || !self.is_writer() || self.last_prune.is_some_and(|at| at.elapsed() < PRUNE_INTERVAL) {
+            return Ok(());
+        }
+        self.last_prune = Some(Instant::now());
+
+        let cutoff = chrono::Utc::now() - chrono::Duration::days(days as i64);
+        let pruned = self.audit.prune_changed_tiles(cutoff).await?;
+        if pruned > 0 {
+            info!("Pruned {} changed tiles recorded before {}", pruned, cutoff);
+        }
+        Ok(())
+    }
+
+    /// Progress information for health endpoints
+    pub fn health(&self) -> WorkerHealth {
+        self.health.clone()
+    }
+
+    /// Whether this worker commits to the archive
+    pub fn is_writer(&self) -> bool {
+        self.writer_lock.is_held()
+    }
+
+    /// Take or confirm the writer role and route rendered tiles accordingly.
+    /// If the lock cannot be checked the role is dropped until it can.
+    async fn update_role(&mut self) -> Result<bool> {
+        let writer = match self.writer_lock.try_acquire().await {
+            Ok(writer) => writer,
+            Err(e) => {
+                self.set_role(false);
+                return Err(e);
+            }
+        };
+
+        // The advisory lock only covers instances sharing this database;
+        // the file lock also catches ones pointed at another database
+        if writer && let Err(e) = self.executor.lock_archive() {
+            error!("{}", e);
+            self.set_role(false);
+            self.writer_lock.release().await?;
+            return Ok(false);
+        }
+
+        self.set_role(writer);
+        Ok(writer)
+    }
+
+    fn set_role(&mut self, writer: bool) {
+        if !writer {
+            self.executor.unlock_archive();
+        }
+        let stage = !writer && self.config.worker.multi_worker;
+        self.executor.set_staging(stage.then(|| self.staging.clone()));
+    }
+
+    /// Check the writer role at the top of a loop iteration; returns whether
+    /// this worker may process batches. A single worker that lost the lock to
+    /// another instance stands by (or exits, per `LOCK_CONFLICT`) until it is
+    /// back, and skips the iteration while the lock cannot be checked.
+    async fn check_role(&mut self) -> Result<bool> {
+        let writer = match self.update_role().await {
+            Ok(writer) => writer,
+            Err(e) => {
+                error!("Failed to check writer lock, dropping the writer role: {:#}", e);
+                if self.config.worker.multi_worker {
+                    return Ok(true);
+                }
+                sleep(Duration::from_secs(self.config.worker.batch_timeout_secs)).await;
+                return Ok(false);
+            }
+        };
+
+        if !writer && !self.config.worker.multi_worker {
+            warn!("Lost the writer role for {}", self.config.files.pmtiles_archive_path.display());
+            self.wait_for_writer_role().await?;
+        }
+        Ok(true)
+    }
+
+    /// Single worker startup: own the archive before doing anything
+    async fn wait_for_writer_role(&mut self) -> Result<()> {
+        let archive = self.config.files.pmtiles_archive_path.clone();
+
+        loop {
+            if self.update_role().await? {
+                info!("This instance owns {}", archive.display());
+                return Ok(());
+            }
+
+            let holder = match self.writer_lock.holder().await {
+                Ok(Some(holder)) => holder,
+                Ok(None) => ArchiveLock::holder(&archive).unwrap_or_else(|| "unknown holder".to_string()),
+                Err(e) => format!("holder lookup failed: {}", e),
+            };
+
+            match self.config.worker.on_lock_conflict {
+                LockConflict::Refuse => {
+                    return Err(anyhow::anyhow!(
+                        "Another jvt instance is writing {} ({}); refusing to start. \
+                         Set LOCK_CONFLICT=standby to wait for it instead",
+                        archive.display(), holder,
+                    ));
+                }
+                LockConflict::Standby => {
+                    warn!("Another jvt instance is writing {} ({}); standing by, retrying in {}s",
+                          archive.display(), holder, self.config.worker.batch_timeout_secs);
+                    sleep(Duration::from_secs(self.config.worker.batch_timeout_secs)).await;
+                }
+            }
+        }
+    }
+
+    /// Process a single notification
+    pub async fn process_notification(&mut self, notification: &TileNotification) -> Result<()> {
+        // Parse the notification to get file path and replication metadata
+        let payload = NotificationListener::parse_notification(notification)?;
+        self.process_file(&payload).await
+    }
+
+    /// Stream a dirty tiles file through the pipeline
+    pub async fn process_file(&mut self, payload: &NotificationPayload) -> Result<()> {
+        let dirty_tiles_file = &payload.file;
+
+        if !self.is_writer() {
+            debug!("Not the writer, leaving {} to the writer", dirty_tiles_file.display());
+            return Ok(());
+        }
+
+        // Validate the file
+        let file_info = self.processor.validate_file(dirty_tiles_file)?;
+        info!("Processing dirty tiles file: {}", file_info);
+
+        // Stream the file in bounded-size batches
+        let replication = self.replication_state(payload).await;
+        let mut stream = self.processor.stream_file(dirty_tiles_file)?
+            .with_replication(replication);
+        let mut processed = 0;
+
+        for batch in &mut stream {
+            // Park throttled low zoom tiles until their refresh interval elapses
+            let batch = self.scheduler.defer(batch?)?;
+
+            if batch.is_empty() {
+                continue;
+            }
+
+            if self.config.worker.multi_worker && let Some(queue) = &self.queue {
+                // Share the work: every worker renders from the queue
+                let tiles: Vec<_> = batch.tiles.iter().cloned().collect();
+                queue.enqueue(&tiles, &dirty_tiles_file.display().to_string(), &batch.replication).await?;
+            } else {
+                Self::process_batch(&mut self.executor, &batch).await?;
+            }
+            processed += batch.len();
+        }
+
+        let stats = stream.stats();
+        if stats.tiles_parsed == 0 {
+            warn!("No valid tiles found in {}", dirty_tiles_file.display());
+            return Ok(());
+        }
+
+        let tile_lines = (stats.tiles_parsed + stats.skipped_zoom + stats.parse_errors) as u64;
+        if let Some(expected) = payload.tile_count
+            && expected != tile_lines
+        {
+            warn!("Notification announced {} tiles but {} were read from {}",
+                  expected, tile_lines, dirty_tiles_file.display());
+        }
+
+        info!("Processed {} tiles from {} ({} deferred pending)",
+              processed, dirty_tiles_file.display(), self.scheduler.pending());
+
+        Ok(())
+    }
+
+    /// OSM data state for a notified file: from the payload if it carries a
+    /// timestamp, otherwise what osm2pgsql recorded in the database
+    async fn replication_state(&self, payload: &NotificationPayload) -> ReplicationInfo {
+        let notified = ReplicationInfo { sequence: payload.sequence, timestamp: payload.timestamp };
+        if notified.timestamp.is_some() {
+            return notified;
+        }
+
+        match self.replication.read().await {
+            Ok(state) => {
+                if let Some(age) = state.age_at(chrono::Utc::now()) {
+                    info!("Database reflects OSM data up to {} (sequence {:?}, {} old)",
+                          state.timestamp.unwrap_or_default(), state.sequence,
+                          format_duration(age.to_std().unwrap_or_default()));
+                }
+                ReplicationInfo { sequence: notified.sequence.or(state.sequence), timestamp: state.timestamp }
+            }
+            Err(e) => {
+                warn!("Failed to read osm2pgsql replication state: {:#}", e);
+                notified
+            }
+        }
+    }
+
+    /// Claim and process queued tiles until the queue is empty
+    pub async fn drain_queue(&mut self) -> Result<()> {
+        let Some(queue) = self.queue.clone() else {
+            return Ok(());
+        };
+
+        // With several workers, low zooms were already throttled when the writer queued them
+        let defer = !self.config.worker.multi_worker;
+
+        while let Some(claim) = queue.claim(self.config.worker.max_batch_size).await? {
+            let deferred = if defer { self.scheduler.defer(claim.batch.clone()) } else { Ok(claim.batch.clone()) };
+            let result = match deferred {
+                Ok(batch) if batch.is_empty() => Ok(()),
+                Ok(batch) => Self::process_batch(&mut self.executor, &batch).await,
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(()) => {
+                    queue.complete(&claim).await?;
+                    // Keep staged tiles flowing into the archive during long drains
+                    self.commit_staged().await?;
+                }
+                Err(e) => {
+                    // Hand the tiles back so this or another worker retries them
+                    queue.release(&claim).await?;
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Commit tiles rendered by other workers or on demand (writer only)
+    pub async fn commit_staged(&mut self) -> Result<()> {
+        let staging = self.staging.clone();
+        if !self.is_writer() {
+            return Ok(());
+        }
+
+        while let Some(staged) = staging.take(self.config.worker.chunk_size.max(1)).await? {
+            self.executor.commit(&staged.tiles, &staged.batch, chrono::Utc::now()).await?;
+            staging.remove(&staged).await?;
+            info!("Committed {} staged tiles", staged.tiles.len());
+        }
+
+        Ok(())
+    }
+
+    /// Process deferred tiles that are due for a refresh
+    pub async fn flush_deferred_tiles(&mut self) -> Result<()> {
+        if !self.is_writer() {
+            return Ok(());
+        }
+
+        // Deferred tiles are only released once they are committed, so a
+        // failed flush is retried on the next loop
+        let now = chrono::Utc::now();
+        if let Some(batch) = self.scheduler.due(now) {
+            Self::process_batch(&mut self.executor, &batch).await?;
+            self.scheduler.commit_flush(&batch, now)?;
+            info!("Processed {} deferred tiles", batch.len());
+        }
+
+        Ok(())
+    }
+
+    /// Log worker status during idle periods
+    fn debug_worker_status(&self) {
+        let state = self.health.snapshot();
+        debug!("Worker heartbeat - waiting for notifications ({} deferred tiles pending, \
+                {} batches committed, last at {:?})...",
+               self.scheduler.pending(), state.batches_committed, state.last_batch_at);
+    }
+
+    /// Render and store a batch of tiles. Each archive commit is recorded in
+    /// the audit table and change feed as it happens; staged tiles are
+    /// recorded once the writer commits them.
+    async fn process_batch(executor: &mut BatchExecutor, batch: &TileBatch) -> Result<()> {
+        let summary = batch.summary();
+        info!("Tile batch ready: {}", summary);
+
+        let started_at = chrono::Utc::now();
+        executor.execute(batch).await?;
+        metrics().batch_seconds.observe((chrono::Utc::now() - started_at).as_seconds_f64());
+
+        Ok(())
+    }
+}
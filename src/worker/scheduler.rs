@@ -0,0 +1,283 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn, debug};
+use crate::config::SchedulingConfig;
+use crate::{Config, TileCoord};
+use super::TileBatch;
+
+/// Throttles re-rendering of low zoom tiles according to the per-zoom refresh policy.
+///
+/// Tiles at throttled zooms are parked in a deferred set and released together
+/// once their zoom's interval has elapsed since the last flush. The deferred set
+/// is persisted so parked tiles survive a worker restart.
+pub struct TileScheduler {
+    policy: SchedulingConfig,
+    state_path: PathBuf,
+    state: DeferredState,
+}
+
+/// On-disk representation of the deferred set
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DeferredState {
+    /// Last time each zoom level was flushed
+    last_flush: BTreeMap<u8, DateTime<Utc>>,
+    /// Parked tiles in "z/x/y" format, grouped by zoom level
+    tiles: BTreeMap<u8, BTreeSet<String>>,
+}
+
+impl TileScheduler {
+    /// Create a scheduler, restoring any deferred tiles left by a previous run
+    pub fn new(config: &Config) -> Result<Self> {
+        let state_path = config.files.deferred_tiles_path.clone();
+        let state = Self::load_state(&state_path)?;
+
+        let scheduler = Self {
+            policy: config.scheduling.clone(),
+            state_path,
+            state,
+        };
+
+        if scheduler.pending() > 0 {
+            info!("Restored {} deferred tiles from {}",
+                  scheduler.pending(), scheduler.state_path.display());
+        }
+
+        Ok(scheduler)
+    }
+
+    /// Move throttled tiles out of the batch into the deferred set.
+    /// The returned batch contains only tiles that should render immediately.
+    pub fn defer(&mut self, batch: TileBatch) -> Result<TileBatch> {
+        let mut immediate = batch.empty_like();
+        let mut deferred_count = 0;
+
+        for coord in batch.tiles {
+            if self.policy.interval_for(coord.z).is_some() {
+                self.state.tiles.entry(coord.z).or_default().insert(coord.to_string());
+                deferred_count += 1;
+            } else {
+                immediate.add_tile(coord);
+            }
+        }
+
+        if deferred_count > 0 {
+            debug!("Deferred {} low zoom tiles ({} now pending)",
+                   deferred_count, self.pending());
+            self.save_state()?;
+        }
+
+        Ok(immediate)
+    }
+
+    /// Deferred tiles whose zoom level is due for a refresh. They stay in
+    /// the deferred set until `commit_flush` confirms they were rendered.
+    pub fn due(&self, now: DateTime<Utc>) -> Option<TileBatch> {
+        let due_zooms: Vec<u8> = self.state.tiles
+            .keys()
+            .copied()
+            .filter(|&z| self.is_due(z, now))
+            .collect();
+
+        if due_zooms.is_empty() {
+            return None;
+        }
+
+        let mut batch = TileBatch::new(self.state_path.clone());
+        for tile in due_zooms.iter().flat_map(|z| &self.state.tiles[z]) {
+            // Only valid tiles are ever parked (see `load_state`)
+            if let Ok(coord) = TileCoord::from_str(tile) {
+                batch.add_tile(coord);
+            }
+        }
+
+        info!("Flushing {} deferred tiles for zooms {:?}", batch.len(), due_zooms);
+        Some(batch)
+    }
+
+    /// Drop the tiles of a flushed batch from the deferred set once they are
+    /// committed, and restart their zooms' refresh intervals
+    pub fn commit_flush(&mut self, batch: &TileBatch, now: DateTime<Utc>) -> Result<()> {
+        for coord in &batch.tiles {
+            if let Some(tiles) = self.state.tiles.get_mut(&coord.z) {
+                tiles.remove(&coord.to_string());
+            }
+            self.state.last_flush.insert(coord.z, now);
+        }
+        self.state.tiles.retain(|_, tiles| !tiles.is_empty());
+        self.save_state()
+    }
+
+    /// Number of tiles currently parked
+    pub fn pending(&self) -> usize {
+        self.state.tiles.values().map(BTreeSet::len).sum()
+    }
+
+    /// Whether a zoom level's refresh interval has elapsed since its last flush
+    fn is_due(&self, zoom: u8, now: DateTime<Utc>) -> bool {
+        let Some(interval) = self.policy.interval_for(zoom) else {
+            // Policy changed since the tile was parked - no longer throttled
+            return true;
+        };
+
+        match self.state.last_flush.get(&zoom) {
+            Some(last) => now - *last >= Duration::seconds(interval as i64),
+            None => true,
+        }
+    }
+
+    fn load_state(path: &Path) -> Result<DeferredState> {
+        if !path.exists() {
+            return Ok(DeferredState::default());
+        }
+
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read deferred tiles file: {}", path.display()))?;
+
+        let mut state: DeferredState = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse deferred tiles file: {}", path.display()))?;
+        for tiles in state.tiles.values_mut() {
+            tiles.retain(|tile| match TileCoord::from_str(tile) {
+                Ok(_) => true,
+                Err(e) => {
+                    warn!("Dropping unparseable deferred tile '{}': {}", tile, e);
+                    false
+                }
+            });
+        }
+        state.tiles.retain(|_, tiles| !tiles.is_empty());
+        Ok(state)
+    }
+
+    /// Persist the deferred set atomically (write to temp file, then rename)
+    fn save_state(&self) -> Result<()> {
+        if let Some(parent) = self.state_path.parent() {
+            std::fs::create_dir_all(parent)
+                .context("Failed to create deferred tiles directory")?;
+        }
+
+        let tmp_path = self.state_path.with_extension("tmp");
+        let contents = serde_json::to_string(&self.state)
+            .context("Failed to serialize deferred tiles")?;
+
+        std::fs::write(&tmp_path, contents)
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, &self.state_path)
+            .with_context(|| format!("Failed to replace {}", self.state_path.display()))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config(name: &str) -> Config {
+        let mut config = Config::default();
+        config.files.deferred_tiles_path = std::env::temp_dir().join(name);
+        std::fs::remove_file(&config.files.deferred_tiles_path).ok();
+        config
+    }
+
+    fn test_batch() -> TileBatch {
+        let mut batch = TileBatch::new(PathBuf::from("/tmp/test.txt"));
+        batch.add_tile(TileCoord::new(3, 4, 2));
+        batch.add_tile(TileCoord::new(7, 60, 40));
+        batch.add_tile(TileCoord::new(14, 8234, 5425));
+        batch
+    }
+
+    #[test]
+    fn test_defer_splits_by_policy() {
+        let config = test_config("test_scheduler_split.json");
+        let mut scheduler = TileScheduler::new(&config).unwrap();
+
+        let immediate = scheduler.defer(test_batch()).unwrap();
+        assert_eq!(immediate.len(), 1);
+        assert!(immediate.tiles.contains(&TileCoord::new(14, 8234, 5425)));
+        assert_eq!(scheduler.pending(), 2);
+
+        std::fs::remove_file(&config.files.deferred_tiles_path).ok();
+    }
+
+    #[test]
+    fn test_flush_respects_intervals() {
+        let config = test_config("test_scheduler_due.json");
+        let mut scheduler = TileScheduler::new(&config).unwrap();
+        let start = Utc::now();
+
+        let flush = |scheduler: &mut TileScheduler, now| {
+            let batch = scheduler.due(now)?;
+            scheduler.commit_flush(&batch, now).unwrap();
+            Some(batch)
+        };
+
+        // Never flushed before, so everything deferred is due right away
+        scheduler.defer(test_batch()).unwrap();
+        let flushed = flush(&mut scheduler, start).unwrap();
+        assert_eq!(flushed.len(), 2);
+        assert_eq!(scheduler.pending(), 0);
+
+        // Re-dirtied shortly after: nothing due until each zoom's interval passes
+        scheduler.defer(test_batch()).unwrap();
+        assert!(flush(&mut scheduler, start + Duration::minutes(5)).is_none());
+
+        let flushed = flush(&mut scheduler, start + Duration::minutes(15)).unwrap();
+        assert!(flushed.tiles.contains(&TileCoord::new(7, 60, 40)));
+        assert_eq!(scheduler.pending(), 1);
+
+        let flushed = flush(&mut scheduler, start + Duration::hours(1)).unwrap();
+        assert!(flushed.tiles.contains(&TileCoord::new(3, 4, 2)));
+        assert_eq!(scheduler.pending(), 0);
+
+        std::fs::remove_file(&config.files.deferred_tiles_path).ok();
+    }
+
+    #[test]
+    fn test_failed_flush_keeps_tiles() {
+        let config = test_config("test_scheduler_failed_flush.json");
+        let now = Utc::now();
+
+        {
+            let mut scheduler = TileScheduler::new(&config).unwrap();
+            scheduler.defer(test_batch()).unwrap();
+            // Rendering the flush fails, so it is never committed
+            assert_eq!(scheduler.due(now).unwrap().len(), 2);
+            assert_eq!(scheduler.pending(), 2);
+        }
+
+        // Still parked, on disk too, and still due
+        let mut scheduler = TileScheduler::new(&config).unwrap();
+        assert_eq!(scheduler.pending(), 2);
+        let batch = scheduler.due(now).unwrap();
+        assert_eq!(batch.len(), 2);
+
+        // A tile re-deferred while the flush ran stays parked after it commits
+        let mut again = TileBatch::new(PathBuf::from("/tmp/test.txt"));
+        again.add_tile(TileCoord::new(7, 61, 40));
+        scheduler.defer(again).unwrap();
+        scheduler.commit_flush(&batch, now).unwrap();
+        assert_eq!(TileScheduler::new(&config).unwrap().pending(), 1);
+
+        std::fs::remove_file(&config.files.deferred_tiles_path).ok();
+    }
+
+    #[test]
+    fn test_deferred_set_survives_restart() {
+        let config = test_config("test_scheduler_restart.json");
+
+        {
+            let mut scheduler = TileScheduler::new(&config).unwrap();
+            scheduler.defer(test_batch()).unwrap();
+        }
+
+        let restored = TileScheduler::new(&config).unwrap();
+        assert_eq!(restored.pending(), 2);
+
+        std::fs::remove_file(&config.files.deferred_tiles_path).ok();
+    }
+}
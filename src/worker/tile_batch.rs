@@ -11,6 +11,27 @@ pub struct TileBatch {
     pub created_at: DateTime<Utc>,
     pub min_zoom: u8,
     pub max_zoom: u8,
+    pub replication: ReplicationInfo,
+}
+
+/// Replication metadata for the OSM changes a batch reflects
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplicationInfo {
+    pub sequence: Option<i64>,
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl ReplicationInfo {
+    /// Keep the newest sequence and timestamp of `self` and `other`
+    pub fn merge(&mut self, other: &ReplicationInfo) {
+        self.sequence = self.sequence.max(other.sequence);
+        self.timestamp = self.timestamp.max(other.timestamp);
+    }
+
+    /// Time from the OSM data timestamp to `at`
+    pub fn age_at(&self, at: DateTime<Utc>) -> Option<chrono::Duration> {
+        self.timestamp.map(|timestamp| at - timestamp)
+    }
 }
 
 impl TileBatch {
@@ -22,9 +43,18 @@ impl TileBatch {
             created_at: Utc::now(),
             min_zoom: u8::MAX,
             max_zoom: 0,
+            replication: ReplicationInfo::default(),
         }
     }
 
+    /// Create an empty batch carrying the same source, receipt time and replication metadata
+    pub fn empty_like(&self) -> Self {
+        let mut batch = TileBatch::new(self.source_file.clone());
+        batch.created_at = self.created_at;
+        batch.replication = self.replication.clone();
+        batch
+    }
+
     /// Add a tile coordinate to the batch
     pub fn add_tile(&mut self, coord: TileCoord) {
         self.min_zoom = self.min_zoom.min(coord.z);
@@ -53,6 +83,22 @@ impl TileBatch {
         by_zoom.into_iter().collect()
     }
 
+    /// Split the batch into chunks of at most `chunk_size` tiles, ordered by zoom
+    /// and then Hilbert curve position (PMTiles tile id order) for DB locality.
+    /// Tiles outside the grid sort last and fail when rendered.
+    pub fn chunks(&self, chunk_size: usize) -> Vec<Vec<TileCoord>> {
+        let mut tiles: Vec<(u64, &TileCoord)> = self.tiles
+            .iter()
+            .map(|tile| (tile.to_tile_id().unwrap_or(u64::MAX), tile))
+            .collect();
+        tiles.sort_unstable_by_key(|(tile_id, _)| *tile_id);
+
+        tiles
+            .chunks(chunk_size.max(1))
+            .map(|chunk| chunk.iter().map(|(_, tile)| (*tile).clone()).collect())
+            .collect()
+    }
+
     /// Get a summary of the batch for logging
     pub fn summary(&self) -> BatchSummary {
         let mut zoom_counts = std::collections::BTreeMap::new();
@@ -68,6 +114,7 @@ impl TileBatch {
             zoom_distribution: zoom_counts,
             source_file: self.source_file.clone(),
             created_at: self.created_at,
+            replication_sequence: self.replication.sequence,
         }
     }
 
@@ -98,6 +145,7 @@ pub struct BatchSummary {
     pub zoom_distribution: std::collections::BTreeMap<u8, usize>,
     pub source_file: PathBuf,
     pub created_at: DateTime<Utc>,
+    pub replication_sequence: Option<i64>,
 }
 
 impl std::fmt::Display for BatchSummary {
@@ -110,7 +158,13 @@ impl std::fmt::Display for BatchSummary {
             self.max_zoom,
             self.source_file.display(),
             self.created_at.format("%Y-%m-%d %H:%M:%S UTC")
-        )
+        )?;
+
+        if let Some(sequence) = self.replication_sequence {
+            write!(f, ", sequence: {}", sequence)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -159,4 +213,43 @@ mod tests {
         assert_eq!(batch.len(), 2); // Should remove z16 tile
         assert_eq!(batch.max_zoom, 12);
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_chunks_ordered_by_zoom_then_hilbert() {
+        let mut batch = TileBatch::new(PathBuf::from("/tmp/test.txt"));
+        
+        for (x, y) in [(1, 1), (0, 0), (1, 0), (0, 1)] {
+            batch.add_tile(TileCoord::new(1, x, y));
+        }
+        batch.add_tile(TileCoord::new(0, 0, 0));
+        
+        let chunks = batch.chunks(2);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[2].len(), 1);
+        
+        // Hilbert order at z1 is (0,0), (0,1), (1,1), (1,0)
+        let order: Vec<TileCoord> = chunks.into_iter().flatten().collect();
+        assert_eq!(order, vec![
+            TileCoord::new(0, 0, 0),
+            TileCoord::new(1, 0, 0),
+            TileCoord::new(1, 0, 1),
+            TileCoord::new(1, 1, 1),
+            TileCoord::new(1, 1, 0),
+        ]);
+    }
+
+    #[test]
+    fn test_replication_merge_keeps_newest() {
+        let older = "2026-10-18T09:00:00Z".parse().unwrap();
+        let newer = "2026-10-18T10:00:00Z".parse().unwrap();
+
+        let mut info = ReplicationInfo { sequence: Some(5), timestamp: Some(newer) };
+        info.merge(&ReplicationInfo { sequence: Some(4), timestamp: Some(older) });
+        info.merge(&ReplicationInfo::default());
+        assert_eq!(info, ReplicationInfo { sequence: Some(5), timestamp: Some(newer) });
+
+        let at = "2026-10-18T10:01:30Z".parse().unwrap();
+        assert_eq!(info.age_at(at), Some(chrono::Duration::seconds(90)));
+        assert_eq!(ReplicationInfo::default().age_at(at), None);
+    }
+}